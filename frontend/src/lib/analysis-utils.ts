@@ -1,4 +1,4 @@
-import type { Transaction, ChartData, ProcessedTimeSeriesData } from './types'
+import type { AggregateRow, ChartData, ProcessedTimeSeriesData } from './types'
 
 export interface ProcessedTimePeriodData {
   breakfast: number
@@ -14,8 +14,8 @@ export interface ProcessedMerchantData {
 }
 
 /**
- * Checks if a given Date object's time falls within a specified time range.
- * @param date The Date object to check.
+ * Checks if a "HH:MM" time of day falls within a specified time range.
+ * @param time The "HH:MM" string to check.
  * @param startHour The start hour of the range (0-23).
  * @param startMinute The start minute of the range (0-59).
  * @param endHour The end hour of the range (0-23).
@@ -23,14 +23,13 @@ export interface ProcessedMerchantData {
  * @returns True if the time is within the range, false otherwise.
  */
 function isTimeInRange(
-  date: Date,
+  time: string,
   startHour: number,
   startMinute: number,
   endHour: number,
   endMinute: number,
 ): boolean {
-  const hours = date.getHours()
-  const minutes = date.getMinutes()
+  const [hours, minutes] = time.split(':').map(Number)
 
   const currentTimeInMinutes = hours * 60 + minutes
   const startTimeInMinutes = startHour * 60 + startMinute
@@ -42,24 +41,25 @@ function isTimeInRange(
   )
 }
 
+// Expects rows grouped by "half_hour"; every meal boundary is on the half hour
 export function processTimePeriodData(
-  transactions: Transaction[],
+  rows: AggregateRow[],
 ): ProcessedTimePeriodData {
-  const counts = transactions.reduce(
-    (acc, transaction) => {
-      const transactionTime = new Date(transaction.time) // Assuming transaction.time is an ISO string
+  const counts = rows.reduce(
+    (acc, row) => {
+      const transactionTime = row.key
 
       if (isTimeInRange(transactionTime, 5, 0, 10, 30)) {
         // Breakfast: 05:00 - 10:30
-        acc.breakfast += 1
+        acc.breakfast += row.count
       } else if (isTimeInRange(transactionTime, 10, 30, 13, 30)) {
         // Lunch: 10:30 - 13:30
-        acc.lunch += 1
+        acc.lunch += row.count
       } else if (isTimeInRange(transactionTime, 16, 30, 19, 30)) {
         // Dinner: 16:30 - 19:30
-        acc.dinner += 1
+        acc.dinner += row.count
       } else {
-        acc.unknown += 1
+        acc.unknown += row.count
       }
       return acc
     },
@@ -81,31 +81,18 @@ export function processTimePeriodData(
   }
 }
 
-// Placeholder for other analysis functions
+// Expects rows grouped by "month"
 export function processTimeSeriesData(
-  transactions: Transaction[],
+  rows: AggregateRow[],
 ): ProcessedTimeSeriesData {
-  if (transactions.length === 0) {
+  if (rows.length === 0) {
     return { chartData: [] }
   }
 
-  const monthlySpending: Map<string, number> = new Map()
-
-  transactions.forEach((transaction) => {
-    const date = new Date(transaction.time)
-    const year = date.getFullYear()
-    const month = date.getMonth() + 1 // JavaScript months are 0-indexed
-    const yearMonthKey = `${year}-${month.toString().padStart(2, '0')}`
-
-    const currentAmount = monthlySpending.get(yearMonthKey) || 0
-    // Assuming amount is negative for spending, take absolute for total spending volume if that's the desired metric.
-    // Or sum them up if you want net (e.g. income vs spending if amounts can be positive).
-    // The Rust code uses .abs(), so we'll do that here for consistency for now.
-    monthlySpending.set(
-      yearMonthKey,
-      currentAmount + Math.abs(transaction.amount),
-    )
-  })
+  // The Rust code uses .abs(), so we'll do that here for consistency for now.
+  const monthlySpending: Map<string, number> = new Map(
+    rows.map((row) => [row.key, Math.abs(row.sum)]),
+  )
 
   const sortedKeys = Array.from(monthlySpending.keys()).sort()
 
@@ -145,30 +132,19 @@ export function processTimeSeriesData(
   return { chartData: filledChartData }
 }
 
+// Expects rows grouped by "merchant"
 export function processMerchantData(
-  transactions: Transaction[],
+  rows: AggregateRow[],
   topN: number = 15, // Optionally show only top N merchants by spending
 ): ProcessedMerchantData {
-  if (transactions.length === 0) {
+  if (rows.length === 0) {
     return { chartData: [], rawData: [] }
   }
 
-  const merchantSpending: Map<string, number> = new Map()
-
-  transactions.forEach((transaction) => {
-    const currentAmount = merchantSpending.get(transaction.merchant) || 0
-    merchantSpending.set(
-      transaction.merchant,
-      currentAmount + transaction.amount,
-    )
-  })
-
-  const aggregatedData = Array.from(merchantSpending.entries()).map(
-    ([merchant, totalAmount]) => ({
-      merchant,
-      totalAmount,
-    }),
-  )
+  const aggregatedData = rows.map((row) => ({
+    merchant: row.key,
+    totalAmount: row.sum,
+  }))
 
   // Sort by totalAmount (negative for spending, so ascending sort means most spent first)
   // Or sort by Math.abs(totalAmount) descending for top spenders regardless of refunds.
@@ -1,11 +1,29 @@
-import type { Transaction, FilterOptions, FetchTransactionsRequest, AccountUpdateRequest, HallticketUpdateRequest, AccountCookieResponse } from "./types";
+import type { Transaction, FilterOptions, FetchTransactionsRequest, InsertReport, SyncState, AccountUpdateRequest, HallticketUpdateRequest, AccountCookieResponse, ProfilesResponse, TransactionsQuery, AggregateRequest, AggregateRow, GroupBy, ManualTransactionRequest } from "./types";
 
 const API_BASE_URL = "/api"; // Assuming the Vite proxy is set up or a relative path works
 
+// A failed request, `kind` is set when the server tells what went wrong,
+// e.g. "session_expired" when fetching with an expired hallticket
+export class ApiError extends Error {
+  status: number;
+  kind?: string;
+
+  constructor(message: string, status: number, kind?: string) {
+    super(message);
+    this.name = "ApiError";
+    this.status = status;
+    this.kind = kind;
+  }
+}
+
 async function handleResponse<T>(response: Response): Promise<T> {
   if (!response.ok) {
     const errorData = await response.json().catch(() => ({ message: response.statusText }));
-    throw new Error(errorData.message || `HTTP error! status: ${response.status}`);
+    throw new ApiError(
+      errorData.message || `HTTP error! status: ${response.status}`,
+      response.status,
+      errorData.kind,
+    );
   }
   if (response.status === 204 || response.headers.get("content-length") === "0") {
     // Handle cases where backend returns 200/204 with no content
@@ -21,6 +39,18 @@ export const fetchAllTransactions = async (): Promise<Transaction[]> => {
   return handleResponse<Transaction[]>(response);
 };
 
+// One page of transactions, sorted on the server
+export const fetchTransactionsPage = async (query: TransactionsQuery): Promise<Transaction[]> => {
+  const params = new URLSearchParams();
+  Object.entries(query).forEach(([key, value]) => {
+    if (value !== undefined) {
+      params.set(key, String(value));
+    }
+  });
+  const response = await fetch(`${API_BASE_URL}/transactions?${params}`);
+  return handleResponse<Transaction[]>(response);
+};
+
 export const fetchFilteredTransactions = async (filterOpts: FilterOptions): Promise<Transaction[]> => {
   const response = await fetch(`${API_BASE_URL}/transactions/query`, {
     method: "POST",
@@ -32,6 +62,27 @@ export const fetchFilteredTransactions = async (filterOpts: FilterOptions): Prom
   return handleResponse<Transaction[]>(response);
 };
 
+// Top-ups, refunds and subsidies are not meals, so analysis pages only look at expenses
+export const fetchExpenseTransactions = async (): Promise<Transaction[]> => {
+  return fetchFilteredTransactions({ kinds: ["expense"] });
+};
+
+export const fetchAggregate = async (request: AggregateRequest): Promise<AggregateRow[]> => {
+  const response = await fetch(`${API_BASE_URL}/transactions/aggregate`, {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify(request),
+  });
+  return handleResponse<AggregateRow[]>(response);
+};
+
+// Expense statistics for the analysis pages, computed by the server
+export const fetchExpenseAggregate = async (groupBy: GroupBy): Promise<AggregateRow[]> => {
+  return fetchAggregate({ group_by: groupBy, filter: { kinds: ["expense"] } });
+};
+
 export const fetchTransactionCount = async (): Promise<number> => {
   const response = await fetch(`${API_BASE_URL}/transactions/count`);
   const data = await handleResponse<any>(response); // Use any for initial parsing, then check type
@@ -58,7 +109,7 @@ export const fetchTransactionCount = async (): Promise<number> => {
   return 0; // Default or throw error
 };
 
-export const triggerFetchTransactions = async (request: FetchTransactionsRequest): Promise<void> => {
+export const triggerFetchTransactions = async (request: FetchTransactionsRequest): Promise<InsertReport> => {
   const response = await fetch(`${API_BASE_URL}/transactions/fetch`, {
     method: "POST",
     headers: {
@@ -66,7 +117,81 @@ export const triggerFetchTransactions = async (request: FetchTransactionsRequest
     },
     body: JSON.stringify(request),
   });
-  await handleResponse<void>(response); // Expecting no content on success
+  return handleResponse<InsertReport>(response);
+};
+
+// Stops the running fetch, keeping the transactions fetched so far
+export const cancelFetchTransactions = async (): Promise<void> => {
+  const response = await fetch(`${API_BASE_URL}/transactions/fetch`, {
+    method: "DELETE",
+  });
+  return handleResponse<void>(response);
+};
+
+export const fetchSyncState = async (): Promise<SyncState> => {
+  const response = await fetch(`${API_BASE_URL}/transactions/sync`);
+  return handleResponse<SyncState>(response);
+};
+
+// Annotation APIs
+// A blank note removes it
+export const updateNote = async (id: string, note: string): Promise<void> => {
+  const response = await fetch(`${API_BASE_URL}/transactions/${id}/note`, {
+    method: "PUT",
+    headers: {
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify({ note }),
+  });
+  await handleResponse<void>(response);
+};
+
+export const updateTags = async (id: string, tags: string[]): Promise<void> => {
+  const response = await fetch(`${API_BASE_URL}/transactions/${id}/tags`, {
+    method: "PUT",
+    headers: {
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify({ tags }),
+  });
+  await handleResponse<void>(response);
+};
+
+// All tags in use, sorted
+export const fetchTags = async (): Promise<string[]> => {
+  const response = await fetch(`${API_BASE_URL}/transactions/tags`);
+  return handleResponse<string[]>(response);
+};
+
+// Manual and hidden transaction APIs
+export const addManualTransaction = async (request: ManualTransactionRequest): Promise<Transaction> => {
+  const response = await fetch(`${API_BASE_URL}/transactions/manual`, {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify(request),
+  });
+  return handleResponse<Transaction>(response);
+};
+
+// Only manual transactions can be deleted, fetched ones can be hidden
+export const deleteManualTransaction = async (id: string): Promise<void> => {
+  const response = await fetch(`${API_BASE_URL}/transactions/${id}`, {
+    method: "DELETE",
+  });
+  await handleResponse<void>(response);
+};
+
+export const updateHidden = async (id: string, hidden: boolean): Promise<void> => {
+  const response = await fetch(`${API_BASE_URL}/transactions/${id}/hidden`, {
+    method: "PUT",
+    headers: {
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify({ hidden }),
+  });
+  await handleResponse<void>(response);
 };
 
 // Config APIs
@@ -96,4 +221,29 @@ export const getAccountCookie = async (): Promise<AccountCookieResponse> => {
   const response = await fetch(`${API_BASE_URL}/config/account-cookie`);
   // This endpoint might return 404 which handleResponse will throw as error, this is fine.
   return handleResponse<AccountCookieResponse>(response);
-}; 
\ No newline at end of file
+};
+
+// Unlike getAccountCookie, returns the account and cookie unmasked
+export const revealAccountCookie = async (): Promise<AccountCookieResponse> => {
+  const response = await fetch(`${API_BASE_URL}/config/account-cookie/reveal`, {
+    method: "POST",
+  });
+  return handleResponse<AccountCookieResponse>(response);
+};
+
+// Profile APIs
+export const fetchProfiles = async (): Promise<ProfilesResponse> => {
+  const response = await fetch(`${API_BASE_URL}/profiles`);
+  return handleResponse<ProfilesResponse>(response);
+};
+
+export const createProfile = async (name: string): Promise<void> => {
+  const response = await fetch(`${API_BASE_URL}/profiles`, {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify({ name }),
+  });
+  await handleResponse<void>(response);
+};
@@ -1,18 +1,125 @@
+export type TransactionKind = "expense" | "top_up" | "refund" | "subsidy";
+
 export interface Transaction {
   id: string; // i64 can be large, string is safer for IDs from backend
   time: string; // ISO 8601 date string
   amount: number;
   merchant: string;
+  kind: TransactionKind;
+  balance: number | null; // card balance after the transaction
+  terminal: string | null; // POS terminal id
+  tran_type: string | null; // type name reported by the card system
+  raw: string | null; // original row from the card system, as JSON
+  note: string | null; // free-text note added by the user
+  tags: string[]; // tags added by the user, sorted
+  manual: boolean; // entered by hand, with a negative id
+  hidden: boolean; // left out of listings and analysis unless include_hidden is set
 }
 
 export interface FilterOptions {
   time?: [string, string]; // [startDate, endDate] ISO 8601 date strings
   merchant?: string;
-  amount?: [number, number]; // [minAmount, maxAmount]
+  merchant_match?: MerchantMatch; // how `merchant` is matched, "exact" by default
+  merchants?: string[]; // any of these merchants, matched exactly
+  exclude_merchants?: string[];
+  categories?: MerchantCategory[];
+  amount?: [number | null, number | null]; // [minAmount, maxAmount), null for an open side
+  kinds?: TransactionKind[];
+  weekdays?: Weekday[];
+  time_of_day?: [string, string][]; // ["11:00:00", "13:30:00"] windows, wrapping past midnight if end < start
+  any_of?: FilterOptions[]; // matches if any of these filters matches
+  tags?: string[]; // transactions with at least one of these tags
+  note?: string; // transactions whose note contains this text
+  include_hidden?: boolean; // also include hidden transactions
+  profile?: string; // account profile, defaults to the server's active profile
+  sort?: [SortKey, SortOrder]; // newest first if not set
+  limit?: number;
+  offset?: number;
+}
+
+export type MerchantCategory =
+  | "canteen_food"
+  | "canteen_drink"
+  | "supermarket"
+  | "bathhouse"
+  | "other"
+  | "unknown";
+
+export type Weekday = "Mon" | "Tue" | "Wed" | "Thu" | "Fri" | "Sat" | "Sun";
+
+// "fuzzy" also matches pinyin initials ("st" for 食堂) and small typos
+export type MerchantMatch = "exact" | "prefix" | "substring" | "fuzzy";
+
+export type SortKey = "time" | "amount" | "merchant";
+export type SortOrder = "asc" | "desc";
+
+// Query parameters of GET /api/transactions
+export interface TransactionsQuery {
+  profile?: string;
+  sort?: SortKey;
+  order?: SortOrder;
+  limit?: number;
+  offset?: number;
+  include_hidden?: boolean;
+}
+
+// Body of POST /api/transactions/manual
+export interface ManualTransactionRequest {
+  time: string; // ISO 8601 date string
+  amount: number; // negative for money spent
+  merchant: string;
+  kind?: TransactionKind; // implied by the sign of the amount if omitted
+}
+
+// Body of POST /api/transactions/aggregate
+export type GroupBy = "day" | "week" | "month" | "merchant" | "hour" | "half_hour";
+
+export interface AggregateRequest {
+  group_by: GroupBy;
+  filter?: FilterOptions;
+}
+
+// Statistics of the transactions sharing one group key
+export interface AggregateRow {
+  key: string; // "2025-03-01", "2025-03", merchant name, "08", "08:30", ...
+  count: number;
+  sum: number;
+  avg: number;
+  min: number;
+  max: number;
+}
+
+export interface ProfilesResponse {
+  active: string;
+  profiles: string[];
 }
 
 export interface FetchTransactionsRequest {
-  start_date: string; // ISO 8601 date string
+  start_date?: string; // ISO 8601 date string, since the last sync if omitted
+  resume?: boolean; // continue the interrupted fetch instead
+}
+
+// Where an interrupted fetch resumes
+export interface FetchCheckpoint {
+  end_time: string; // ISO 8601
+  page: number;
+  before: string | null; // ISO 8601
+}
+
+// Outcome of the fetches of a profile
+export interface SyncState {
+  last_success: string | null; // ISO 8601
+  newest_transaction: string | null;
+  last_error: string | null;
+  last_error_at: string | null;
+  checkpoint: FetchCheckpoint | null; // set if the last fetch did not complete
+}
+
+// Outcome of storing fetched transactions
+export interface InsertReport {
+  new: number;
+  duplicate: number; // already stored, skipped
+  conflict: number; // differ from the stored ones, quarantined
 }
 
 export interface AccountUpdateRequest {
@@ -23,6 +130,7 @@ export interface HallticketUpdateRequest {
   hallticket: string;
 }
 
+// Masked unless returned by the reveal route
 export interface AccountCookieResponse {
   account: string;
   cookie: string;
@@ -2,8 +2,8 @@ import { createFileRoute } from '@tanstack/react-router'
 import { useQuery } from '@tanstack/react-query'
 import { Bar, BarChart, CartesianGrid, XAxis, YAxis, Tooltip } from 'recharts'
 
-import { fetchAllTransactions } from '../../lib/api'
-import type { Transaction } from '../../lib/types'
+import { fetchExpenseAggregate } from '../../lib/api'
+import type { AggregateRow } from '../../lib/types'
 import { processMerchantData } from '../../lib/analysis-utils'
 import { ChartContainer, ChartTooltipContent } from '../../components/ui/chart'
 import {
@@ -29,17 +29,17 @@ const chartConfig = {
 
 function MerchantAnalysisPage() {
   const {
-    data: transactions,
+    data: rows,
     isLoading,
     error,
-  } = useQuery<Transaction[], Error>({
-    queryKey: ['transactions'],
-    queryFn: fetchAllTransactions,
+  } = useQuery<AggregateRow[], Error>({
+    queryKey: ['aggregate', 'expenses', 'merchant'],
+    queryFn: () => fetchExpenseAggregate('merchant'),
     staleTime: 1000 * 60 * 5, // Cache for 5 minutes
   })
 
-  const analysisResult = transactions
-    ? processMerchantData(transactions)
+  const analysisResult = rows
+    ? processMerchantData(rows)
     : { chartData: [], rawData: [] }
 
   if (isLoading) return <div className="p-4">Loading chart data...</div>
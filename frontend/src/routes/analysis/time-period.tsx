@@ -10,8 +10,8 @@ import {
   Cell,
 } from 'recharts' // Directly use recharts for more control if shadcn/ui chart is a wrapper
 
-import { fetchAllTransactions } from '../../lib/api'
-import type { Transaction } from '../../lib/types'
+import { fetchExpenseAggregate } from '../../lib/api'
+import type { AggregateRow } from '../../lib/types'
 import { processTimePeriodData } from '../../lib/analysis-utils'
 import { ChartContainer, ChartTooltipContent } from '../../components/ui/chart' // Use Shadcn chart components
 import {
@@ -49,19 +49,19 @@ const chartConfig = {
 
 function TimePeriodAnalysisPage() {
   const {
-    data: transactions,
+    data: rows,
     isLoading,
     error,
-  } = useQuery<Transaction[], Error>({
-    queryKey: ['transactions'],
-    queryFn: fetchAllTransactions,
+  } = useQuery<AggregateRow[], Error>({
+    queryKey: ['aggregate', 'expenses', 'half_hour'],
+    queryFn: () => fetchExpenseAggregate('half_hour'),
     staleTime: 1000 * 60 * 5, // Cache for 5 minutes
   })
 
   const analysisData = useMemo(() => {
     console.log('processing data')
-    return transactions ? processTimePeriodData(transactions) : null
-  }, [transactions])
+    return rows ? processTimePeriodData(rows) : null
+  }, [rows])
   console.log(analysisData)
 
   if (isLoading) return <div className="p-4">Loading chart data...</div>
@@ -2,8 +2,8 @@ import { createFileRoute } from '@tanstack/react-router'
 import { useQuery } from '@tanstack/react-query'
 import { Line, LineChart, CartesianGrid, XAxis, YAxis } from 'recharts'
 
-import { fetchAllTransactions } from '../../lib/api'
-import type { Transaction } from '../../lib/types'
+import { fetchExpenseAggregate } from '../../lib/api'
+import type { AggregateRow } from '../../lib/types'
 import { processTimeSeriesData } from '../../lib/analysis-utils'
 import {
   ChartContainer,
@@ -32,17 +32,17 @@ const chartConfig = {
 
 function TimeSeriesAnalysisPage() {
   const {
-    data: transactions,
+    data: rows,
     isLoading,
     error,
-  } = useQuery<Transaction[], Error>({
-    queryKey: ['transactions'],
-    queryFn: fetchAllTransactions,
+  } = useQuery<AggregateRow[], Error>({
+    queryKey: ['aggregate', 'expenses', 'month'],
+    queryFn: () => fetchExpenseAggregate('month'),
     staleTime: 1000 * 60 * 5, // Cache for 5 minutes
   })
 
-  const analysisResult = transactions
-    ? processTimeSeriesData(transactions)
+  const analysisResult = rows
+    ? processTimeSeriesData(rows)
     : { chartData: [] }
 
   console.log('TimeSeries Chart Data:', analysisResult.chartData) // Log chart data
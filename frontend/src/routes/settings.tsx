@@ -1,5 +1,10 @@
 import { createFileRoute } from '@tanstack/react-router';
 import { useEffect, useState } from 'react';
+import { revealAccountCookie } from '../lib/api';
+import type { AccountCookieResponse } from '../lib/types';
+
+// Extract hallticket value from the cookie string "hallticket=value"
+const hallticketOf = (cookie: string) => cookie.match(/hallticket=([^;]*)/)?.[1] ?? '';
 
 export const Route = createFileRoute('/settings')({
   component: SettingsPage,
@@ -8,6 +13,8 @@ export const Route = createFileRoute('/settings')({
 function SettingsPage() {
   const [account, setAccount] = useState('');
   const [hallticket, setHallticket] = useState('');
+  // Stored values are masked until revealed, and shown as placeholders
+  const [masked, setMasked] = useState<AccountCookieResponse | null>(null);
   const [isLoading, setIsLoading] = useState(false);
   const [error, setError] = useState<string | null>(null);
   const [successMessage, setSuccessMessage] = useState<string | null>(null);
@@ -28,12 +35,7 @@ function SettingsPage() {
             throw new Error(`Failed to fetch settings: ${response.statusText}`);
           }
         } else {
-          const data = await response.json();
-          setAccount(data.account || '');
-          // Extract hallticket value from the cookie string "hallticket=value"
-          const cookieValue = data.cookie || '';
-          const hallticketMatch = cookieValue.match(/hallticket=([^;]*)/);
-          setHallticket(hallticketMatch ? hallticketMatch[1] : '');
+          setMasked(await response.json());
         }
       } catch (err) {
         setError(err instanceof Error ? err.message : 'An unknown error occurred');
@@ -46,6 +48,21 @@ function SettingsPage() {
     fetchSettings();
   }, []);
 
+  const handleReveal = async () => {
+    setIsLoading(true);
+    setError(null);
+    try {
+      const data = await revealAccountCookie();
+      setAccount(data.account);
+      setHallticket(hallticketOf(data.cookie));
+    } catch (err) {
+      setError(err instanceof Error ? err.message : 'An unknown error occurred');
+      console.error(err);
+    } finally {
+      setIsLoading(false);
+    }
+  };
+
   const handleSubmit = async (event: React.FormEvent<HTMLFormElement>) => {
     event.preventDefault();
     setIsLoading(true);
@@ -53,26 +70,29 @@ function SettingsPage() {
     setSuccessMessage(null);
 
     try {
-      // Update account
-      const accountRes = await fetch('/api/config/account', {
-        method: 'PUT',
-        headers: { 'Content-Type': 'application/json' },
-        body: JSON.stringify({ account }),
-      });
-      if (!accountRes.ok) {
-        const errorData = await accountRes.text();
-        throw new Error(`Failed to update account: ${accountRes.statusText} - ${errorData}`);
+      // Empty fields keep the stored values
+      if (account) {
+        const accountRes = await fetch('/api/config/account', {
+          method: 'PUT',
+          headers: { 'Content-Type': 'application/json' },
+          body: JSON.stringify({ account }),
+        });
+        if (!accountRes.ok) {
+          const errorData = await accountRes.text();
+          throw new Error(`Failed to update account: ${accountRes.statusText} - ${errorData}`);
+        }
       }
 
-      // Update hallticket
-      const hallticketRes = await fetch('/api/config/hallticket', {
-        method: 'PUT',
-        headers: { 'Content-Type': 'application/json' },
-        body: JSON.stringify({ hallticket }),
-      });
-      if (!hallticketRes.ok) {
-        const errorData = await hallticketRes.text();
-        throw new Error(`Failed to update hallticket: ${hallticketRes.statusText} - ${errorData}`);
+      if (hallticket) {
+        const hallticketRes = await fetch('/api/config/hallticket', {
+          method: 'PUT',
+          headers: { 'Content-Type': 'application/json' },
+          body: JSON.stringify({ hallticket }),
+        });
+        if (!hallticketRes.ok) {
+          const errorData = await hallticketRes.text();
+          throw new Error(`Failed to update hallticket: ${hallticketRes.statusText} - ${errorData}`);
+        }
       }
 
       setSuccessMessage('Settings updated successfully!');
@@ -117,6 +137,7 @@ function SettingsPage() {
             id="account"
             value={account}
             onChange={(e) => setAccount(e.target.value)}
+            placeholder={masked?.account}
             className="mt-1 block w-full px-3 py-2 border border-gray-300 rounded-md shadow-sm focus:outline-none focus:ring-indigo-500 focus:border-indigo-500 sm:text-sm"
             disabled={isLoading}
           />
@@ -133,13 +154,24 @@ function SettingsPage() {
             onChange={(e) => setHallticket(e.target.value)}
             className="mt-1 block w-full px-3 py-2 border border-gray-300 rounded-md shadow-sm focus:outline-none focus:ring-indigo-500 focus:border-indigo-500 sm:text-sm"
             disabled={isLoading}
-            placeholder="e.g., value from your hallticket cookie"
+            placeholder={masked ? hallticketOf(masked.cookie) : 'e.g., value from your hallticket cookie'}
           />
            <p className="mt-1 text-xs text-gray-500">
             This is typically the value part of your '''hallticket=YOUR_VALUE_HERE''' cookie.
           </p>
         </div>
 
+        {masked && (
+          <button
+            type="button"
+            onClick={handleReveal}
+            disabled={isLoading}
+            className="text-sm text-indigo-600 hover:underline disabled:text-indigo-300"
+          >
+            Reveal stored account and hallticket
+          </button>
+        )}
+
         <div>
           <button
             type="submit"
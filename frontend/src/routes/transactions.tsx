@@ -1,5 +1,5 @@
-import { createFileRoute } from '@tanstack/react-router'
-import { useQuery } from '@tanstack/react-query'
+import { createFileRoute, Link } from '@tanstack/react-router'
+import { useQuery, useQueryClient } from '@tanstack/react-query'
 import type {
   ColumnDef,
   HeaderContext,
@@ -17,8 +17,14 @@ import {
 import { ArrowUpDown } from 'lucide-react'
 import * as React from 'react'
 
-import { fetchAllTransactions } from '../lib/api'
-import type { Transaction } from '../lib/types'
+import {
+  ApiError,
+  fetchAllTransactions,
+  cancelFetchTransactions,
+  fetchSyncState,
+  triggerFetchTransactions,
+} from '../lib/api'
+import type { InsertReport, SyncState, Transaction } from '../lib/types'
 import { Button } from '../components/ui/button'
 import { Input } from '../components/ui/input'
 import {
@@ -100,6 +106,48 @@ function TransactionsPage() {
   })
 
   const [columnFilters, setColumnFilters] = React.useState<ColumnFiltersState>([])
+  const queryClient = useQueryClient()
+  const { data: syncState } = useQuery<SyncState, Error>({
+    queryKey: ['syncState'],
+    queryFn: fetchSyncState,
+  })
+  const [fetching, setFetching] = React.useState(false)
+  const [report, setReport] = React.useState<InsertReport | null>(null)
+  const [fetchError, setFetchError] = React.useState<string | null>(null)
+  const [sessionExpired, setSessionExpired] = React.useState(false)
+  const [cancelled, setCancelled] = React.useState(false)
+
+  const handleFetch = async (resume: boolean) => {
+    setFetching(true)
+    setFetchError(null)
+    setSessionExpired(false)
+    setCancelled(false)
+    setReport(null)
+    try {
+      // without a start date, only transactions newer than the stored ones are fetched
+      setReport(await triggerFetchTransactions(resume ? { resume } : {}))
+    } catch (err) {
+      if (err instanceof ApiError && err.kind === 'cancelled') {
+        setCancelled(true)
+        return
+      }
+      setSessionExpired(err instanceof ApiError && err.status === 401)
+      setFetchError(err instanceof Error ? err.message : 'Failed to fetch transactions')
+    } finally {
+      // pages are stored as they arrive, even if the fetch fails or is cancelled
+      await queryClient.invalidateQueries({ queryKey: ['transactions'] })
+      await queryClient.invalidateQueries({ queryKey: ['syncState'] })
+      setFetching(false)
+    }
+  }
+
+  const handleCancelFetch = async () => {
+    try {
+      await cancelFetchTransactions()
+    } catch (err) {
+      setFetchError(err instanceof Error ? err.message : 'Failed to cancel fetching')
+    }
+  }
 
   const table = useReactTable<Transaction>({
     data: transactions ?? [],
@@ -128,12 +176,71 @@ function TransactionsPage() {
 
   return (
     <div className="p-4">
-      <div className="mb-6">
-        <h1 className="text-3xl font-bold tracking-tight mb-2">Transactions</h1>
-        <p className="text-muted-foreground">
-          View and manage your transaction history.
-        </p>
+      <div className="mb-6 flex items-start justify-between">
+        <div>
+          <h1 className="text-3xl font-bold tracking-tight mb-2">Transactions</h1>
+          <p className="text-muted-foreground">
+            View and manage your transaction history.
+          </p>
+        </div>
+        <div className="text-right">
+          {fetching && (
+            <Button variant="outline" className="mr-2" onClick={handleCancelFetch}>
+              Cancel
+            </Button>
+          )}
+          {!fetching && syncState?.checkpoint && (
+            <Button
+              variant="outline"
+              className="mr-2"
+              onClick={() => handleFetch(true)}
+              disabled={fetching}
+            >
+              Resume interrupted fetch
+            </Button>
+          )}
+          <Button onClick={() => handleFetch(false)} disabled={fetching}>
+            {fetching ? 'Fetching...' : 'Fetch new transactions'}
+          </Button>
+          <p className="text-sm text-muted-foreground mt-2">
+            {syncState?.last_success
+              ? `Last synced ${new Date(syncState.last_success).toLocaleString()}`
+              : 'Never synced'}
+          </p>
+        </div>
       </div>
+      {!fetchError && syncState?.last_error && (
+        <div className="bg-red-100 border border-red-400 text-red-700 px-4 py-3 rounded relative mb-4" role="alert">
+          Last fetch failed: {syncState.last_error}
+        </div>
+      )}
+      {fetchError && (
+        <div className="bg-red-100 border border-red-400 text-red-700 px-4 py-3 rounded relative mb-4" role="alert">
+          {sessionExpired ? (
+            <>
+              The session has expired, log in to card.xjtu.edu.cn again and update the hallticket
+              in{' '}
+              <Link to="/settings" className="underline">
+                Settings
+              </Link>
+              .
+            </>
+          ) : (
+            fetchError
+          )}
+        </div>
+      )}
+      {cancelled && (
+        <div className="bg-yellow-100 border border-yellow-400 text-yellow-700 px-4 py-3 rounded relative mb-4" role="alert">
+          Fetch cancelled, the transactions fetched until then were kept. Resume to continue.
+        </div>
+      )}
+      {report && (
+        <div className="bg-green-100 border border-green-400 text-green-700 px-4 py-3 rounded relative mb-4" role="alert">
+          {report.new} new, {report.duplicate} already stored, {report.conflict}{' '}
+          conflicting
+        </div>
+      )}
       <Card>
         <CardHeader>{/* <CardTitle>Transactions</CardTitle> */}</CardHeader>
         <CardContent>
@@ -14,10 +14,12 @@ pub enum Layers {
     #[allow(dead_code)]
     Home,
     Fetch,
-    Transaction(Option<FilterOptions>),
+    Transaction(Option<Box<FilterOptions>>),
     CookieInput,
     Help(HelpMsg),
     Analysis,
+    Profiles,
+    Conflicts,
 }
 
 impl std::fmt::Display for Layers {
@@ -29,6 +31,8 @@ impl std::fmt::Display for Layers {
             Layers::CookieInput => write!(f, "CookieInput"),
             Layers::Help(_) => write!(f, "Help"),
             Layers::Analysis => write!(f, "Analysis"),
+            Layers::Profiles => write!(f, "Profiles"),
+            Layers::Conflicts => write!(f, "Conflicts"),
         }
     }
 }
@@ -28,10 +28,10 @@ pub(crate) struct RootState {
 }
 
 impl RootState {
-    pub fn new(config: Config) -> Self {
+    pub fn new(config: Config) -> Result<Self> {
         let (action_tx, action_rx) = mpsc::unbounded_channel();
 
-        let manager = TransactionManager::new(config.config.db_path())
+        let manager = TransactionManager::open(config.config.db_path(), config.config.passphrase())
             .with_context(|| {
                 format!(
                     "Fail to connect to Database at {}",
@@ -42,23 +42,25 @@ impl RootState {
                         .map(|p| p.display().to_string())
                         .unwrap_or("memory".into())
                 )
-            })
-            .unwrap();
+            })?;
+        manager
+            .switch_profile(config.config.account_profile())
+            .context("Error when selecting account profile")?;
 
         if let Some(account) = &config.fetch.account {
-            manager.update_account(account).unwrap();
+            manager.update_account(account)?;
         }
         if let Some(hallticket) = &config.fetch.hallticket {
-            manager.update_hallticket(hallticket).unwrap();
+            manager.update_hallticket(hallticket)?;
         }
 
-        Self {
+        Ok(Self {
             should_quit: false,
             action_tx,
             action_rx,
             manager,
             config,
-        }
+        })
     }
 
     pub fn send_action<T: Into<Action>>(&self, action: T) {
@@ -193,7 +195,6 @@ pub(super) mod test {
     use crate::{
         actions::{LayerManageAction, Layers},
         cli::{ClapSource, Cli},
-        libs::fetcher::MealFetcher,
         page::{
             cookie_input::CookieInput, fetch::Fetch, help_popup::HelpPopup,
             transactions::Transactions,
@@ -221,17 +222,25 @@ pub(super) mod test {
     fn root_state_set_fetch_config() {
         let config = get_config(vec!["--account", "123456", "--hallticket", "543210"], true);
 
-        let root = RootState::new(config);
+        let root = RootState::new(config).unwrap();
         let (account, cookie) = root.manager.get_account_cookie().unwrap();
         assert_eq!(account, "123456");
         assert_eq!(cookie, "hallticket=543210");
     }
 
+    #[test]
+    fn root_state_rejects_invalid_profile() {
+        let config = get_config(vec!["--account-profile", " "], true);
+
+        let err = RootState::new(config).err().unwrap();
+        assert!(format!("{:#}", err).contains("Error when selecting account profile"));
+    }
+
     pub fn get_app() -> App {
         let config = get_config(vec!["--use-mock-data"], true);
-        let state = RootState::new(config);
-        let app = App::new(state, tui::TestTui::new().into());
-        app
+        let state = RootState::new(config).unwrap();
+
+        App::new(state, tui::TestTui::new().into())
     }
 
     #[tokio::test]
@@ -265,7 +274,7 @@ pub(super) mod test {
             .unwrap()
             .downcast_ref::<Fetch>()
             .unwrap();
-        assert!(matches!(fetch.get_client(), MealFetcher::Mock(_)));
+        assert_eq!(fetch.get_client().name(), "mock data");
     }
 
     #[tokio::test]
@@ -273,7 +282,7 @@ pub(super) mod test {
         let mut app = get_app();
 
         app.perform_action(Action::Quit);
-        assert_eq!(app.state.should_quit, true);
+        assert!(app.state.should_quit);
     }
 
     #[tokio::test]
@@ -5,10 +5,10 @@ use std::{
 
 use crate::{
     actions::{LayerManageAction, Layers},
-    libs::fetcher::{MealFetcher, MockMealFetcher, RealMealFetcher},
+    libs::fetcher::MockMealFetcher,
     page::{
-        Layer, analysis::Analysis, cookie_input::CookieInput, fetch::Fetch, help_popup::HelpPopup,
-        home::Home, transactions::Transactions,
+        Layer, analysis::Analysis, conflicts::Conflicts, cookie_input::CookieInput, fetch::Fetch,
+        help_popup::HelpPopup, home::Home, profiles::Profiles, transactions::Transactions,
     },
     tui::Event,
 };
@@ -175,21 +175,18 @@ impl LayerManager {
                 tx: state.action_tx.clone().into(),
             }) as Box<dyn Layer>,
             Layers::Transaction(filter_opt) => Box::new(Transactions::new(
-                filter_opt,
+                filter_opt.map(|f| *f),
                 state.action_tx.clone().into(),
                 state.manager.clone(),
             )),
             Layers::Fetch => Box::new(
                 Fetch::new(state.action_tx.clone().into(), state.manager.clone()).client(
-                    if state.config.fetch.use_mock_data {
-                        MealFetcher::Mock(
-                            MockMealFetcher::default()
-                                .set_sim_delay(Duration::from_secs(1))
-                                .per_page(50),
-                        )
-                    } else {
-                        MealFetcher::Real(RealMealFetcher::default())
-                    },
+                    // mock data is paced so the progress can be watched
+                    state.config.fetch.source_with(
+                        MockMealFetcher::default()
+                            .set_sim_delay(Duration::from_secs(1))
+                            .per_page(50),
+                    ),
                 ),
             ),
             Layers::CookieInput => Box::new(CookieInput::new(
@@ -210,6 +207,14 @@ impl LayerManager {
                 state.action_tx.clone().into(),
                 state.manager.clone(),
             )),
+            Layers::Profiles => Box::new(Profiles::new(
+                state.action_tx.clone().into(),
+                state.manager.clone(),
+            )),
+            Layers::Conflicts => Box::new(Conflicts::new(
+                state.action_tx.clone().into(),
+                state.manager.clone(),
+            )),
         };
         page.init();
         Some(page.into())
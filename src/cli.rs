@@ -1,8 +1,18 @@
-use clap::{Parser, Subcommand};
-use color_eyre::Result;
+use std::path::PathBuf;
+
+use chrono::{NaiveTime, Weekday};
+use clap::{Args, Parser, Subcommand};
+use color_eyre::{Result, eyre::eyre};
 use config::Source;
 
-use crate::config::get_data_dir;
+use crate::{
+    config::get_data_dir,
+    libs::{
+        money::Money,
+        transactions::{FilterOptions, GroupBy, MerchantMatch},
+    },
+    utils::merchant_class::MerchantType,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version = version(), about = "How much did you eat at XJTU?")]
@@ -26,6 +36,13 @@ pub struct Cli {
     #[arg(long, default_value_t = false)]
     pub db_in_mem: bool,
 
+    /// Account profile to use [default: default]
+    ///
+    /// Each profile keeps its own account, hallticket and transactions, so
+    /// several cards can share one database. A new profile is created on first use.
+    #[arg(long, value_name = "NAME")]
+    pub account_profile: Option<String>,
+
     /// Account for fetching transactions
     ///
     /// Get it on https://card.xjtu.edu.cn
@@ -49,14 +66,73 @@ pub struct Cli {
 pub enum Commands {
     /// Clean the local database
     ///
-    /// Clean up the database used for caching transactions and other data.
+    /// Clean up the transactions of the account profile selected with
+    /// `--account-profile` (the default profile if not given).
     ///
     /// If you have set a custom data path, you will need to set the same path
     /// if you want to clear the database when running this command.
     ///
-    /// This command is helpful when you switches between different accounts or
-    /// using mock data.
+    /// This command is helpful when you switches between using real and mock data.
     ClearDb,
+    /// Snapshot the local database into the `backups` directory next to it
+    ///
+    /// Safe to run while the TUI or `web` is open. Only the most recent
+    /// backups are kept.
+    Backup {
+        /// Number of backups to keep, older ones are deleted
+        #[arg(long, value_name = "N", default_value_t = 10)]
+        keep: usize,
+
+        /// List the existing backups instead of creating one
+        #[arg(long, default_value_t = false)]
+        list: bool,
+    },
+    /// Replace the local database with a backup
+    ///
+    /// The backup is checked for integrity first, and the current database is
+    /// backed up before being overwritten.
+    Restore {
+        /// Backup file to restore [default: the latest backup]
+        #[arg(value_name = "FILE_PATH")]
+        file: Option<PathBuf>,
+    },
+    /// List fetched transactions that conflict with stored ones, or resolve one
+    ///
+    /// A conflict is a fetched transaction with the ID of a stored one but a
+    /// different time, amount or merchant. It is quarantined instead of
+    /// failing the import until resolved.
+    Conflicts {
+        /// Resolve the conflict with this ID by keeping the stored version
+        #[arg(long, value_name = "ID", conflicts_with = "use_incoming")]
+        keep: Option<i64>,
+
+        /// Resolve the conflict with this ID by replacing the stored version
+        /// with the fetched one
+        #[arg(long, value_name = "ID")]
+        use_incoming: Option<i64>,
+    },
+    /// Fetch transactions of the account profile and store them
+    ///
+    /// Uses the account and hallticket saved for the profile, or `--use-mock-data`.
+    /// Prints how many fetched transactions were new, already stored or
+    /// conflicting with stored ones. Ctrl-C stops fetching, keeping the
+    /// transactions fetched so far.
+    Fetch {
+        /// Fetch transactions since this date, in format YYYY-MM-DD
+        ///
+        /// Without it, only the transactions newer than the stored ones are fetched.
+        #[arg(long, value_name = "DATE")]
+        since: Option<String>,
+
+        /// Continue the last fetch from where it stopped
+        ///
+        /// Fetched pages are stored as they arrive, so a fetch that failed or
+        /// was interrupted only needs to fetch the remaining ones.
+        #[arg(long, default_value_t = false, conflicts_with = "since")]
+        resume: bool,
+    },
+    /// Show when the account profile was last fetched and the last fetch error
+    SyncStatus,
     Web,
     ExportCsv {
         /// Path to the output CSV file
@@ -67,15 +143,21 @@ pub enum Commands {
         #[arg(short, long, value_name = "MERCHANT_NAME")]
         merchant: Option<String>,
 
+        /// How to match --merchant: exact, prefix, substring or fuzzy
+        ///
+        /// fuzzy also matches pinyin initials (`st` for 食堂) and small typos
+        #[arg(long, value_name = "MODE", default_value_t = MerchantMatch::Exact)]
+        merchant_match: MerchantMatch,
+
         /// Filter by transaction min cost (positive value)
         /// Will be converted to negative for database query
-        #[arg(long, value_name = "FLOAT")]
-        min_amount: Option<f64>,
+        #[arg(long, value_name = "AMOUNT")]
+        min_amount: Option<Money>,
 
         /// Filter by transaction max cost (positive value)
         /// Will be converted to negative for database query
-        #[arg(long, value_name = "FLOAT")]
-        max_amount: Option<f64>,
+        #[arg(long, value_name = "AMOUNT")]
+        max_amount: Option<Money>,
 
         /// Filter by start date (inclusive) in format YYYY-MM-DD
         #[arg(long, value_name = "DATE")]
@@ -84,9 +166,138 @@ pub enum Commands {
         /// Filter by end date (exclusive) in format YYYY-MM-DD
         #[arg(long, value_name = "DATE")]
         time_end: Option<String>,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Print expense statistics grouped by time or merchant
+    Stats {
+        /// One of day, week, month, merchant, hour, half_hour
+        #[arg(short, long, value_name = "GROUP", default_value_t = GroupBy::Month)]
+        group_by: GroupBy,
+
+        /// Filter by merchant name
+        #[arg(short, long, value_name = "MERCHANT_NAME")]
+        merchant: Option<String>,
+
+        /// How to match --merchant: exact, prefix, substring or fuzzy
+        ///
+        /// fuzzy also matches pinyin initials (`st` for 食堂) and small typos
+        #[arg(long, value_name = "MODE", default_value_t = MerchantMatch::Exact)]
+        merchant_match: MerchantMatch,
+
+        /// Filter by start date (inclusive) in format YYYY-MM-DD
+        #[arg(long, value_name = "DATE")]
+        time_start: Option<String>,
+
+        /// Filter by end date (exclusive) in format YYYY-MM-DD
+        #[arg(long, value_name = "DATE")]
+        time_end: Option<String>,
+
+        #[command(flatten)]
+        filter: FilterArgs,
     },
 }
 
+/// Filter options shared by the commands reading transactions
+#[derive(Args, Debug, Clone, Default)]
+pub struct FilterArgs {
+    /// Base filter as JSON, in the format accepted by `POST /api/transactions/query`
+    ///
+    /// The other filter options are applied on top of it.
+    #[arg(long, value_name = "JSON", value_parser = parse_filter)]
+    pub filter: Option<FilterOptions>,
+
+    /// Also include this merchant, matched exactly, can be repeated
+    #[arg(long, value_name = "MERCHANT_NAME")]
+    pub any_merchant: Vec<String>,
+
+    /// Leave out this merchant, can be repeated
+    #[arg(long, value_name = "MERCHANT_NAME")]
+    pub exclude_merchant: Vec<String>,
+
+    /// Only include this merchant category, can be repeated
+    ///
+    /// One of canteen_food, canteen_drink, supermarket, bathhouse, other, unknown
+    #[arg(long, value_name = "CATEGORY")]
+    pub category: Vec<MerchantType>,
+
+    /// Only include this day of the week (mon, tue, ...), can be repeated
+    #[arg(long, value_name = "DAY")]
+    pub weekday: Vec<Weekday>,
+
+    /// Only include this time of day, like 11:00-13:30, can be repeated
+    ///
+    /// A window ending before it starts wraps past midnight.
+    #[arg(long, value_name = "HH:MM-HH:MM", value_parser = parse_time_window)]
+    pub time_of_day: Vec<(NaiveTime, NaiveTime)>,
+
+    /// Only include transactions with this tag, can be repeated
+    #[arg(long, value_name = "TAG")]
+    pub tag: Vec<String>,
+
+    /// Only include transactions whose note contains this text
+    #[arg(long, value_name = "TEXT")]
+    pub note: Option<String>,
+
+    /// Also include transactions matching this JSON filter, can be repeated
+    #[arg(long, value_name = "JSON", value_parser = parse_filter)]
+    pub or: Vec<FilterOptions>,
+
+    /// Also include hidden transactions
+    #[arg(long)]
+    pub include_hidden: bool,
+}
+
+impl FilterArgs {
+    /// The `--filter` base, or `default` if not given, with the other options applied
+    pub fn build(&self, default: FilterOptions) -> FilterOptions {
+        let mut filter = self.filter.clone().unwrap_or(default);
+        for merchant in &self.any_merchant {
+            filter = filter.or_merchant(merchant);
+        }
+        for merchant in &self.exclude_merchant {
+            filter = filter.exclude_merchant(merchant);
+        }
+        for category in &self.category {
+            filter = filter.category(category.clone());
+        }
+        for weekday in &self.weekday {
+            filter = filter.weekday(*weekday);
+        }
+        for (start, end) in &self.time_of_day {
+            filter = filter.time_of_day(*start, *end);
+        }
+        for tag in &self.tag {
+            filter = filter.tag(tag);
+        }
+        if let Some(note) = &self.note {
+            filter = filter.note_contains(note);
+        }
+        for alternative in &self.or {
+            filter = filter.or(alternative.clone());
+        }
+        if self.include_hidden {
+            filter = filter.include_hidden();
+        }
+        filter
+    }
+}
+
+fn parse_filter(s: &str) -> Result<FilterOptions> {
+    Ok(serde_json::from_str(s)?)
+}
+
+fn parse_time_window(s: &str) -> Result<(NaiveTime, NaiveTime)> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| eyre!("Expected a window like 11:00-13:30"))?;
+    Ok((
+        NaiveTime::parse_from_str(start.trim(), "%H:%M")?,
+        NaiveTime::parse_from_str(end.trim(), "%H:%M")?,
+    ))
+}
+
 const VERSION_MESSAGE: &str = concat!(env!("CARGO_PKG_VERSION"));
 
 pub fn version() -> String {
@@ -109,6 +320,7 @@ Data directory: {data_dir_path}"
 pub(crate) struct ClapSource {
     data_dir: Option<String>,
     db_in_men: bool,
+    account_profile: Option<String>,
     account: Option<String>,
     hallticket: Option<String>,
     use_mock_data: bool,
@@ -119,6 +331,7 @@ impl ClapSource {
         Self {
             data_dir: cli.data_dir.clone(),
             db_in_men: cli.db_in_mem,
+            account_profile: cli.account_profile.clone(),
             account: cli.account.clone(),
             hallticket: cli.hallticket.clone(),
             use_mock_data: cli.use_mock_data,
@@ -145,6 +358,13 @@ impl Source for ClapSource {
             config::Value::new(None, self.db_in_men),
         );
 
+        if self.account_profile.is_some() {
+            map.insert(
+                "account_profile".to_string(),
+                config::Value::new(None, self.account_profile.clone()),
+            );
+        }
+
         if self.account.is_some() {
             map.insert(
                 "fetch.account".to_string(),
@@ -148,11 +148,11 @@ impl InputComp {
         match self.mode {
             InputMode::Idle => (),
             InputMode::Focused => {
-                if let Event::Key(key) = event {
-                    if self.control_keys.enter_keys.contains(&(*key).into()) {
-                        self.mode = InputMode::Inputting;
-                        status.consumed();
-                    }
+                if let Event::Key(key) = event
+                    && self.control_keys.enter_keys.contains(&(*key).into())
+                {
+                    self.mode = InputMode::Inputting;
+                    status.consumed();
                 }
             }
             InputMode::Inputting => match event {
@@ -188,6 +188,11 @@ impl InputComp {
         (status, output_string)
     }
 
+    /// Replace the text, e.g. to show a value changed elsewhere
+    pub fn set_text<T: Into<String>>(&mut self, text: T) {
+        self.input = Input::new(text.into());
+    }
+
     pub fn set_mode(&mut self, mode: InputMode) {
         self.mode = mode;
     }
@@ -1,10 +1,12 @@
-use std::{env, path::PathBuf};
+use std::{env, path::PathBuf, sync::Arc, time::Duration};
 
 use color_eyre::{Result, eyre::Context};
 use directories::ProjectDirs;
 use lazy_static::lazy_static;
 use serde::Deserialize;
 
+use crate::libs::fetcher::{FetchLimits, MockMealFetcher, RealMealFetcher, TransactionSource};
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct AppConfig {
     #[serde(default)]
@@ -15,6 +17,14 @@ pub struct AppConfig {
     /// Use an in-memory database, which means all data will lost when the program exits
     #[serde(default)]
     db_in_mem: bool,
+
+    /// Account profile to use, see [`AppConfig::account_profile`]
+    #[serde(default)]
+    account_profile: Option<String>,
+
+    /// Passphrase encrypting the stored credentials, see [`AppConfig::passphrase`]
+    #[serde(default)]
+    passphrase: Option<String>,
 }
 
 impl AppConfig {
@@ -27,6 +37,21 @@ impl AppConfig {
             Some(self.data_dir.join(&self.db_path))
         }
     }
+
+    /// Returns the name of the account profile to start with
+    pub fn account_profile(&self) -> &str {
+        self.account_profile
+            .as_deref()
+            .unwrap_or(crate::libs::transactions::DEFAULT_PROFILE)
+    }
+
+    /// Returns the passphrase the account and hallticket are encrypted with,
+    /// read from the `XJTU_MEALFLOW_PASSPHRASE` environment variable
+    ///
+    /// Without one, a key file next to the database is used.
+    pub fn passphrase(&self) -> Option<&str> {
+        self.passphrase.as_deref().filter(|p| !p.is_empty())
+    }
 }
 
 /// Configuration for fetching transactions from XJTU server
@@ -35,11 +60,72 @@ impl AppConfig {
 /// related configurations in database.
 ///
 /// The source of truth for fetching is from Database.
+///
+/// The limits can be set with environment variables such as
+/// `XJTU_MEALFLOW_FETCH__MAX_PAGES`, unset ones keep the defaults of [`FetchLimits`].
 #[derive(Clone, Debug, Deserialize, Default)]
 pub struct FetchConfig {
     pub account: Option<String>,
     pub hallticket: Option<String>,
+    #[serde(default)]
     pub use_mock_data: bool,
+
+    /// Attempts per page, including the first one
+    #[serde(default)]
+    max_attempts: Option<u32>,
+    /// Wait before the first retry, in milliseconds
+    #[serde(default)]
+    initial_backoff_ms: Option<u64>,
+    /// Upper bound on the wait before a retry, in milliseconds
+    #[serde(default)]
+    max_backoff_ms: Option<u64>,
+    /// Pause between two pages, in milliseconds
+    #[serde(default)]
+    page_delay_ms: Option<u64>,
+    /// Time a single request may take, in seconds
+    #[serde(default)]
+    request_timeout_secs: Option<u64>,
+    /// Upper bound on the pages requested by one fetch
+    #[serde(default)]
+    max_pages: Option<u32>,
+}
+
+impl FetchConfig {
+    /// Retry and rate limits of fetching, defaulting to [`FetchLimits::default`]
+    pub fn limits(&self) -> FetchLimits {
+        let default = FetchLimits::default();
+        FetchLimits {
+            max_attempts: self.max_attempts.unwrap_or(default.max_attempts).max(1),
+            initial_backoff: self
+                .initial_backoff_ms
+                .map_or(default.initial_backoff, Duration::from_millis),
+            max_backoff: self
+                .max_backoff_ms
+                .map_or(default.max_backoff, Duration::from_millis),
+            page_delay: self
+                .page_delay_ms
+                .map_or(default.page_delay, Duration::from_millis),
+            request_timeout: self
+                .request_timeout_secs
+                .map_or(default.request_timeout, Duration::from_secs),
+            max_pages: self.max_pages.unwrap_or(default.max_pages).max(1),
+        }
+    }
+
+    /// Where transactions are fetched from, depending on `--use-mock-data`
+    pub fn source(&self) -> Arc<dyn TransactionSource> {
+        self.source_with(MockMealFetcher::default())
+    }
+
+    /// Like [`FetchConfig::source`], using `mock` when mock data is configured,
+    /// so a caller can tune how mock data is paged
+    pub fn source_with(&self, mock: MockMealFetcher) -> Arc<dyn TransactionSource> {
+        if self.use_mock_data {
+            Arc::new(mock.limits(self.limits()))
+        } else {
+            Arc::new(RealMealFetcher::default().limits(self.limits()))
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -60,6 +146,15 @@ impl Config {
         let mut builder = config::Config::builder()
             .set_default("data_dir", data_dir.to_str().unwrap())?
             .set_default("db_path", "transactions.db")?;
+        if let Ok(passphrase) = env::var(format!("{}_PASSPHRASE", PROJECT_NAME.clone())) {
+            builder = builder.set_default("passphrase", passphrase)?;
+        }
+        builder = builder.add_source(
+            config::Environment::with_prefix(&PROJECT_NAME)
+                .prefix_separator("_")
+                .separator("__")
+                .try_parsing(true),
+        );
 
         // Add CLI source last (highest priority)
         if let Some(cli_source) = cli_source {
@@ -77,7 +172,7 @@ impl Config {
 }
 
 pub fn get_data_dir() -> PathBuf {
-    let directory = if let Some(s) = env::var(format!("{}_DATA", PROJECT_NAME.clone()))
+    if let Some(s) = env::var(format!("{}_DATA", PROJECT_NAME.clone()))
         .ok()
         .map(PathBuf::from)
         .clone()
@@ -87,8 +182,7 @@ pub fn get_data_dir() -> PathBuf {
         proj_dirs.data_local_dir().to_path_buf()
     } else {
         PathBuf::from(".").join(".data")
-    };
-    directory
+    }
 }
 
 fn project_directory() -> Option<ProjectDirs> {
@@ -127,9 +221,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn passphrase_from_env() {
+        temp_env::with_vars(
+            [(
+                format!("{}_PASSPHRASE", PROJECT_NAME.clone()).as_str(),
+                Some("correct horse"),
+            )],
+            || {
+                let config = Config::new(None).unwrap();
+                assert_eq!(config.config.passphrase(), Some("correct horse"));
+            },
+        );
+        temp_env::with_vars_unset(
+            [format!("{}_PASSPHRASE", PROJECT_NAME.clone()).as_str()],
+            || assert_eq!(Config::new(None).unwrap().config.passphrase(), None),
+        );
+    }
+
     #[test]
     fn data_dir_from_cli() {
-        let args = crate::cli::Cli::parse_from(&["test-config", "--data-dir", ".cli-data"]);
+        let args = crate::cli::Cli::parse_from(["test-config", "--data-dir", ".cli-data"]);
         let config = Config::new(Some(ClapSource::new(&args))).expect("Failed to load config");
 
         assert_eq!(config.config.data_dir, PathBuf::from(".cli-data"));
@@ -137,7 +249,7 @@ mod tests {
 
     #[test]
     fn account_from_dir() {
-        let args = Cli::parse_from(&["test-config", "--account", "123456"]);
+        let args = Cli::parse_from(["test-config", "--account", "123456"]);
         let config = Config::new(Some(ClapSource::new(&args))).expect("Failed to load config");
 
         assert_eq!(config.fetch.account.unwrap(), "123456");
@@ -145,15 +257,26 @@ mod tests {
 
     #[test]
     fn hallticket_from_dir() {
-        let args = Cli::parse_from(&["test-config", "--hallticket", "123456"]);
+        let args = Cli::parse_from(["test-config", "--hallticket", "123456"]);
         let config = Config::new(Some(ClapSource::new(&args))).expect("Failed to load config");
 
         assert_eq!(config.fetch.hallticket.unwrap(), "123456");
     }
 
+    #[test]
+    fn account_profile_from_cli() {
+        let args = Cli::parse_from(["test-config"]);
+        let config = Config::new(Some(ClapSource::new(&args))).expect("Failed to load config");
+        assert_eq!(config.config.account_profile(), "default");
+
+        let args = Cli::parse_from(["test-config", "--account-profile", "roommate"]);
+        let config = Config::new(Some(ClapSource::new(&args))).expect("Failed to load config");
+        assert_eq!(config.config.account_profile(), "roommate");
+    }
+
     #[test]
     fn db_in_men_path() {
-        let args = Cli::parse_from(&["test-config", "--db-in-mem"]);
+        let args = Cli::parse_from(["test-config", "--db-in-mem"]);
         let config = Config::new(Some(ClapSource::new(&args))).expect("Failed to load config");
 
         assert_eq!(config.config.db_path(), None);
@@ -161,14 +284,58 @@ mod tests {
 
     #[test]
     fn use_mock_data() {
-        let args = Cli::parse_from(&["test-config", "--use-mock-data"]);
+        let args = Cli::parse_from(["test-config", "--use-mock-data"]);
         let config = Config::new(Some(ClapSource::new(&args))).expect("Failed to load config");
 
-        assert_eq!(config.fetch.use_mock_data, true);
+        assert!(config.fetch.use_mock_data);
 
-        let args = Cli::parse_from(&["test-config"]);
+        let args = Cli::parse_from(["test-config"]);
         let config = Config::new(Some(ClapSource::new(&args))).expect("Failed to load config");
 
-        assert_eq!(config.fetch.use_mock_data, false);
+        assert!(!config.fetch.use_mock_data);
+    }
+
+    #[test]
+    fn fetch_limits_from_env() {
+        let config = Config::new(None).unwrap();
+        assert_eq!(config.fetch.limits(), FetchLimits::default());
+
+        temp_env::with_vars(
+            [
+                (
+                    format!("{}_FETCH__MAX_PAGES", PROJECT_NAME.clone()).as_str(),
+                    Some("20"),
+                ),
+                (
+                    format!("{}_FETCH__PAGE_DELAY_MS", PROJECT_NAME.clone()).as_str(),
+                    Some("1500"),
+                ),
+            ],
+            || {
+                let limits = Config::new(None).unwrap().fetch.limits();
+                assert_eq!(limits.max_pages, 20);
+                assert_eq!(limits.page_delay, Duration::from_millis(1500));
+                assert_eq!(limits.max_attempts, FetchLimits::default().max_attempts);
+            },
+        );
+
+        // a fetch always gets to request a page
+        temp_env::with_vars(
+            [
+                (
+                    format!("{}_FETCH__MAX_PAGES", PROJECT_NAME.clone()).as_str(),
+                    Some("0"),
+                ),
+                (
+                    format!("{}_FETCH__MAX_ATTEMPTS", PROJECT_NAME.clone()).as_str(),
+                    Some("0"),
+                ),
+            ],
+            || {
+                let limits = Config::new(None).unwrap().fetch.limits();
+                assert_eq!(limits.max_pages, 1);
+                assert_eq!(limits.max_attempts, 1);
+            },
+        );
     }
 }
@@ -39,6 +39,17 @@
 //! cargo run -- export-csv --merchant "超市"
 //! ```
 //!
+//! 默认要求商家名称完全一致，`--merchant-match` 可改为前缀（`prefix`）、
+//! 子串（`substring`）或模糊（`fuzzy`）匹配。模糊匹配支持拼音首字母和少量错字：
+//!
+//! ```bash
+//! # 所有名称含"超市"的商家
+//! cargo run -- export-csv --merchant "超市" --merchant-match substring
+//!
+//! # 按拼音首字母查找"梧桐苑"的商家
+//! cargo run -- export-csv --merchant "wty" --merchant-match fuzzy
+//! ```
+//!
 //! ### 按日期区间筛选
 //!
 //! 导出指定日期范围内的交易：
@@ -67,6 +78,33 @@
 //!   --output "filtered_transactions.csv"
 //! ```
 //!
+//! ### 按类别、星期和时段筛选
+//!
+//! `--category`、`--exclude-merchant`、`--weekday` 和 `--time-of-day` 均可重复使用：
+//!
+//! ```bash
+//! # 工作日午饭时段在食堂的消费，不含某个窗口
+//! cargo run -- export-csv --category canteen_food \
+//!   --weekday mon --weekday tue --weekday wed --weekday thu --weekday fri \
+//!   --time-of-day 11:00-13:30 --exclude-merchant "炸吧"
+//! ```
+//!
+//! 也可以按用户添加的标签和备注筛选：
+//!
+//! ```bash
+//! # 标签为"考试周"或"请客"的消费
+//! cargo run -- export-csv --tag "考试周" --tag "请客"
+//!
+//! # 备注中含有"生日"的消费
+//! cargo run -- export-csv --note "生日"
+//! ```
+//!
+//! 更复杂的条件（如“或”条件组）可以用 `--filter` 传入与 Web API 相同的 JSON：
+//!
+//! ```bash
+//! cargo run -- export-csv --filter '{"any_of": [{"categories": ["bathhouse"]}, {"merchant": "超市", "merchant_match": "substring"}]}'
+//! ```
+//!
 //! ## 日期格式
 //!
 //! 所有日期参数必须使用 `YYYY-MM-DD` 格式，例如：
@@ -80,6 +118,12 @@
 //! - `Time`: 交易时间（格式：YYYY-MM-DD HH:MM:SS +ZZZZ）
 //! - `Amount`: 交易金额（负数表示消费，正数表示充值）
 //! - `Merchant`: 商家名称
+//! - `Kind`: 交易类型（`expense` 消费、`top_up` 充值、`refund` 退款、`subsidy` 补助）
+//! - `Balance`: 交易后卡内余额（校园卡系统未返回时为空）
+//! - `Terminal`: 终端（POS 机）编号
+//! - `Type`: 校园卡系统给出的交易类型名称
+//! - `Note`: 用户备注
+//! - `Tags`: 用户标签，以 `;` 分隔
 
 use std::fs::File;
 use std::io::Write;
@@ -88,7 +132,10 @@ use std::path::Path;
 use chrono::{DateTime, FixedOffset, NaiveDate};
 use color_eyre::eyre::{Context, Result};
 
-use super::transactions::{FilterOptions, Transaction, TransactionManager};
+use super::{
+    money::Money,
+    transactions::{FilterOptions, MerchantMatch, Transaction, TransactionManager},
+};
 
 /// CSV 导出器
 ///
@@ -103,14 +150,18 @@ pub struct ExportOptions {
     pub output: Option<String>,
     /// 商家名称筛选
     pub merchant: Option<String>,
+    /// 商家名称匹配方式
+    pub merchant_match: MerchantMatch,
     /// 最小金额筛选（正数）
-    pub min_amount: Option<f64>,
+    pub min_amount: Option<Money>,
     /// 最大金额筛选（正数）
-    pub max_amount: Option<f64>,
+    pub max_amount: Option<Money>,
     /// 开始日期筛选
     pub time_start: Option<String>,
     /// 结束日期筛选
     pub time_end: Option<String>,
+    /// 其余筛选条件（商家类别、星期、时段等），上面的选项在此基础上叠加
+    pub base_filter: FilterOptions,
 }
 
 impl CsvExporter {
@@ -154,26 +205,27 @@ impl CsvExporter {
     ///
     /// 将用户输入的选项转换为数据库查询的筛选条件
     fn build_filter_options(options: &ExportOptions) -> Result<FilterOptions> {
-        let mut filter_opt = FilterOptions::default();
+        let mut filter_opt = options.base_filter.clone();
 
         // (1) 商家筛选
         if let Some(merchant) = &options.merchant {
-            filter_opt = filter_opt.merchant(merchant);
+            filter_opt = filter_opt.search_merchant(merchant, options.merchant_match);
         }
 
         // (2) 金额筛选
         // 用户输入正数范围，转换为数据库中的负数范围
         if let Some(min) = options.min_amount {
-            if min < 0.0 {
+            if min.is_negative() {
                 println!("Warning: min_amount should be positive, converting absolute value");
             }
             // 用户最小值 -> 数据库最大值（逻辑反转）
-            let db_max = -min.abs();
+            // 数据库上界为开区间，加一分使恰好等于最小值的记录也被导出
+            let db_max = -min.abs() + Money::from_cents(1);
             filter_opt = filter_opt.max(db_max);
         }
 
         if let Some(max) = options.max_amount {
-            if max < 0.0 {
+            if max.is_negative() {
                 println!("Warning: max_amount should be positive, converting absolute value");
             }
             // 用户最大值 -> 数据库最小值（逻辑反转）
@@ -218,6 +270,7 @@ impl CsvExporter {
             || options.max_amount.is_some()
             || options.time_start.is_some()
             || options.time_end.is_some()
+            || options.base_filter != FilterOptions::default()
     }
 
     /// 解析日期字符串，将时间设为当天开始 (00:00:00)
@@ -288,16 +341,30 @@ impl CsvExporter {
     ) -> Result<()> {
         let mut file = File::create(file_path)?;
 
-        writeln!(file, "ID,Time,Amount,Merchant")?;
+        writeln!(
+            file,
+            "ID,Time,Amount,Merchant,Kind,Balance,Terminal,Type,Note,Tags"
+        )?;
+
+        let quote = |s: &str| format!("\"{}\"", s.replace("\"", "\"\""));
 
         for transaction in transactions {
             writeln!(
                 file,
-                "{},{},{},\"{}\"",
+                "{},{},{},{},{},{},{},{},{},{}",
                 transaction.id,
                 transaction.time.format("%Y-%m-%d %H:%M:%S %z"),
                 transaction.amount,
-                transaction.merchant.replace("\"", "\"\"")
+                quote(&transaction.merchant),
+                transaction.kind,
+                transaction
+                    .balance
+                    .map(|b| b.to_string())
+                    .unwrap_or_default(),
+                quote(transaction.terminal.as_deref().unwrap_or_default()),
+                quote(transaction.tran_type.as_deref().unwrap_or_default()),
+                quote(transaction.note.as_deref().unwrap_or_default()),
+                quote(&transaction.tags.join(";")),
             )?;
         }
 
@@ -1,74 +1,267 @@
-use chrono::{DateTime, FixedOffset};
 use color_eyre::{
     Result, Section, SectionExt,
-    eyre::{WrapErr, bail, eyre},
+    eyre::{WrapErr, eyre},
 };
-use reqwest::{blocking::Client, header};
+use futures::{FutureExt, Stream, future::BoxFuture, stream};
+use reqwest::{Client, StatusCode, header};
 use serde::{Deserialize, Serialize};
 use std::{
     str::{self},
-    thread::sleep,
+    sync::Arc,
     time::Duration,
 };
-
-use crate::{libs::transactions::Transaction, page::fetch::FetchProgress};
+use strum::IntoStaticStr;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::{
+    libs::{
+        money::Money,
+        transactions::{FetchCheckpoint, IdSequencer, Transaction, TransactionKind},
+    },
+    page::fetch::FetchProgress,
+};
 
 pub const API_ORIGIN: &str = "http://card.xjtu.edu.cn";
 pub const API_PATH: &str = "/Report/GetPersonTrjn";
 
 #[derive(Deserialize, Debug, Clone, Serialize)]
 struct ApiResponse {
-    rows: Vec<TransactionRow>,
+    /// Rows as sent by the server, stored as-is in [`Transaction::raw`] and
+    /// read into a [`TransactionRow`] for the fields we use
+    rows: Vec<serde_json::Value>,
 }
 
-#[derive(Deserialize, Debug, Clone, Serialize)]
+/// What the card system may answer with: rows, or another JSON object
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ApiReply {
+    Rows(ApiResponse),
+    /// The card system answers an expired session with its usual envelope,
+    /// just without `rows`
+    Other(serde_json::Map<String, serde_json::Value>),
+}
+
+#[derive(Deserialize, Debug, Clone)]
 struct TransactionRow {
     #[serde(rename = "OCCTIME")]
     time: String,
     #[serde(rename = "TRANAMT")]
-    amount: f64,
+    amount: Money,
     #[serde(rename = "MERCNAME")]
     merchant: String,
+    /// Transaction type name, e.g. "电子账户消费"
+    #[serde(rename = "TRANNAME", default)]
+    tran_name: Option<String>,
+    /// Card balance after the transaction
+    #[serde(rename = "CARDBAL", default)]
+    balance: Option<Money>,
+    /// Terminal (POS) id, sent as either a number or a string
+    #[serde(
+        rename = "POSCODE",
+        alias = "TERMID",
+        default,
+        deserialize_with = "deserialize_opt_string_or_number"
+    )]
+    terminal: Option<String>,
 }
 
-#[derive(Debug, Clone)]
-pub enum MealFetcher {
-    Real(RealMealFetcher),
-    Mock(MockMealFetcher),
+fn deserialize_opt_string_or_number<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(
+        match Option::<serde_json::Value>::deserialize(deserializer)? {
+            Some(serde_json::Value::String(s)) => Some(s),
+            Some(serde_json::Value::Number(n)) => Some(n.to_string()),
+            _ => None,
+        },
+    )
 }
 
-impl From<RealMealFetcher> for MealFetcher {
-    fn from(fetcher: RealMealFetcher) -> Self {
-        MealFetcher::Real(fetcher)
-    }
+/// What a [`TransactionSource`] supports, read by the code driving it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceCapabilities {
+    /// Fetching needs the account and cookie of the profile, passed with
+    /// [`TransactionSource::with_credentials`]
+    pub needs_credentials: bool,
+    /// Pages are sorted from the newest transaction, so fetching can stop at
+    /// the first page reaching the end time instead of reading every page
+    pub newest_first: bool,
 }
-impl From<MockMealFetcher> for MealFetcher {
-    fn from(fetcher: MockMealFetcher) -> Self {
-        MealFetcher::Mock(fetcher)
+
+/// A system transactions are fetched from, page by page
+///
+/// [`fetch`], the Fetch page and the web API only go through this trait, so a
+/// new source (a file replay, another campus system) only has to implement it.
+pub trait TransactionSource: std::fmt::Debug + Send + Sync {
+    /// Short name, used in logs
+    fn name(&self) -> &'static str;
+
+    fn capabilities(&self) -> SourceCapabilities;
+
+    /// A copy of this source fetching with the given credentials
+    ///
+    /// Only called if [`SourceCapabilities::needs_credentials`] is set.
+    fn with_credentials(&self, account: &str, cookie: &str) -> Arc<dyn TransactionSource>;
+
+    /// Retry and rate limits the source fetches with
+    fn fetch_limits(&self) -> FetchLimits {
+        FetchLimits::default()
+    }
+
+    /// The raw response for a page, starting at 1
+    fn fetch_page(&self, page: u32) -> BoxFuture<'_, Result<String>>;
+
+    /// Transactions in a response, an empty page meaning there are no more
+    ///
+    /// Defaults to the format of card.xjtu.edu.cn.
+    fn parse_page(&self, response: &str) -> Result<Vec<Transaction>> {
+        api_response_to_transactions(response)
     }
 }
 
-impl Default for MealFetcher {
+/// How hard a fetch may hit the server
+///
+/// Read from the `fetch` section of [`Config`](crate::config::Config), see
+/// [`FetchConfig::limits`](crate::config::FetchConfig::limits).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FetchLimits {
+    /// Attempts per page before giving up, including the first one
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled for every further one
+    pub initial_backoff: Duration,
+    /// Upper bound on the wait before a retry
+    pub max_backoff: Duration,
+    /// Pause before requesting the next page
+    pub page_delay: Duration,
+    /// Time a single request may take
+    pub request_timeout: Duration,
+    /// Upper bound on the pages requested by one fetch, a fetch stopped by it
+    /// fails with [`FetchError::PageLimit`] and can be resumed
+    pub max_pages: u32,
+}
+
+impl Default for FetchLimits {
     fn default() -> Self {
-        Self::Real(RealMealFetcher::default())
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            page_delay: Duration::from_millis(500),
+            request_timeout: Duration::from_secs(30),
+            max_pages: 200,
+        }
+    }
+}
+
+impl FetchLimits {
+    /// Wait before retrying after the given failed attempt, starting at 1
+    ///
+    /// Exponential, with the upper half jittered so that several clients
+    /// failing together do not retry together.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+        let half = exp / 2;
+        half + half.mul_f64(rand::random::<f64>())
+    }
+}
+
+/// Why a page could not be fetched
+///
+/// Carried by the reports of [`TransactionSource::fetch_page`] and
+/// [`TransactionSource::parse_page`], see [`FetchError::of`].
+#[derive(Debug, Clone, PartialEq, Eq, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum FetchError {
+    /// The hallticket expired or was rejected, it has to be updated
+    SessionExpired,
+    /// The server could not be reached or did not answer in time
+    Network(String),
+    /// The server answered with this error status
+    Server(u16),
+    /// The server asked to slow down
+    RateLimited,
+    /// The response is not the expected JSON
+    MalformedResponse(String),
+    /// This many pages were requested without reaching the end time
+    PageLimit(u32),
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchError::SessionExpired => write!(f, "The session has expired"),
+            FetchError::Network(e) => write!(f, "Network error: {}", e),
+            FetchError::Server(status) => write!(f, "Request failed with status: {}", status),
+            FetchError::RateLimited => write!(f, "Rate limited by the server"),
+            FetchError::MalformedResponse(e) => write!(f, "Malformed response: {}", e),
+            FetchError::PageLimit(pages) => write!(f, "Stopped at the limit of {} pages", pages),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+impl FetchError {
+    /// The fetch error behind a report, if it was caused by one
+    pub fn of(report: &color_eyre::Report) -> Option<&FetchError> {
+        report.chain().find_map(|e| e.downcast_ref::<FetchError>())
+    }
+
+    /// Short name, e.g. `session_expired`
+    pub fn kind(&self) -> &'static str {
+        self.into()
+    }
+
+    /// Whether sending the same request again may succeed
+    fn is_transient(&self) -> bool {
+        match self {
+            FetchError::Network(_) | FetchError::RateLimited => true,
+            FetchError::Server(status) => *status >= 500,
+            FetchError::SessionExpired
+            | FetchError::MalformedResponse(_)
+            | FetchError::PageLimit(_) => false,
+        }
+    }
+
+    /// A report of the error, with a hint on what to do about it
+    fn into_report(self) -> color_eyre::Report {
+        match self {
+            FetchError::SessionExpired => color_eyre::Report::new(self).with_note(
+                || "Consider re-logging in to card.xjtu.edu.cn and updating your hallticket.",
+            ),
+            FetchError::PageLimit(_) => color_eyre::Report::new(self)
+                .with_note(|| "Resume the fetch to continue with older transactions."),
+            _ => color_eyre::Report::new(self),
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct RealMealFetcher {
+    /// Shared by every page and retry, and by the copies made with
+    /// [`TransactionSource::with_credentials`], so connections are reused
+    client: Client,
     cookie: Option<String>,
     account: Option<String>,
     origin: String,
     per_page: u32,
+    limits: FetchLimits,
 }
 
 impl Default for RealMealFetcher {
     fn default() -> Self {
         Self {
+            client: Client::new(),
             cookie: Default::default(),
             account: Default::default(),
             origin: API_ORIGIN.into(),
             per_page: 50,
+            limits: FetchLimits::default(),
         }
     }
 }
@@ -103,9 +296,11 @@ impl RealMealFetcher {
         }
     }
 
-    fn fetch_transaction_one_page(&self, page: u32) -> Result<String> {
-        let client = Client::new();
+    pub fn limits(self, limits: FetchLimits) -> Self {
+        Self { limits, ..self }
+    }
 
+    async fn fetch_transaction_one_page(&self, page: u32) -> Result<String> {
         let cookie = self.cookie.clone().ok_or(eyre!("Cookie not set"))?;
         let account = self.account.clone().ok_or(eyre!("Account not set"))?;
 
@@ -145,145 +340,314 @@ impl RealMealFetcher {
             account, page, self.per_page
         );
 
-        // Attempt request with retry logic
-        let mut attempts = 0;
-        let max_attempts = 3;
-        let mut last_error = None;
+        if page > 1 {
+            tokio::time::sleep(self.limits.page_delay).await;
+        }
 
-        while attempts < max_attempts {
-            match client
+        let mut attempt = 1;
+        loop {
+            let request = self
+                .client
                 .post(format!("{}{}", &self.origin, API_PATH))
+                .timeout(self.limits.request_timeout)
                 .headers(headers.clone())
-                .body(body.clone())
-                .send()
-            {
-                Ok(response) => {
-                    if response.status().is_success() {
-                        match response.text() {
-                            Ok(api_response) => {
-                                return Ok(api_response);
-                            }
-                            Err(e) => {
-                                last_error = Some(eyre!("Failed to parse response: {}", e));
-                            }
-                        }
-                    } else {
-                        last_error =
-                            Some(eyre!("Request failed with status: {}", response.status()));
-                    }
+                .body(body.clone());
+            let retry_after = match Self::send(request).await {
+                Ok(response) => return Ok(response),
+                Err(e) if !e.is_transient() => return Err(e.into_report()),
+                Err(e) if attempt >= self.limits.max_attempts => {
+                    return Err(e
+                        .into_report()
+                        .wrap_err(format!("Gave up after {} attempts", attempt)));
                 }
                 Err(e) => {
-                    last_error = Some(eyre!("Request error: {}", e));
+                    let wait = self.limits.backoff(attempt);
+                    warn!(
+                        "Page {} attempt {} failed, retrying in {:?}: {}",
+                        page, attempt, wait, e
+                    );
+                    wait
                 }
+            };
+            tokio::time::sleep(retry_after).await;
+            attempt += 1;
+        }
+    }
+
+    /// Send a request once
+    async fn send(request: reqwest::RequestBuilder) -> Result<String, FetchError> {
+        let response = request.send().await.map_err(|e| {
+            if e.is_timeout() {
+                FetchError::Network(format!("Request timed out: {}", e))
+            } else {
+                FetchError::Network(e.to_string())
+            }
+        })?;
+        match response.status() {
+            StatusCode::TOO_MANY_REQUESTS => return Err(FetchError::RateLimited),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                return Err(FetchError::SessionExpired);
             }
+            status if !status.is_success() => return Err(FetchError::Server(status.as_u16())),
+            _ => (),
+        }
+        response
+            .text()
+            .await
+            .map_err(|e| FetchError::Network(format!("Failed to read response: {}", e)))
+    }
+}
+
+impl TransactionSource for RealMealFetcher {
+    fn name(&self) -> &'static str {
+        "card.xjtu.edu.cn"
+    }
 
-            // Retry after delay 1000 (blocking)
-            sleep(Duration::new(1, 0));
-            attempts += 1;
+    fn capabilities(&self) -> SourceCapabilities {
+        SourceCapabilities {
+            needs_credentials: true,
+            newest_first: true,
         }
+    }
+
+    fn with_credentials(&self, account: &str, cookie: &str) -> Arc<dyn TransactionSource> {
+        Arc::new(self.clone().account(account).cookie(cookie))
+    }
+
+    fn fetch_limits(&self) -> FetchLimits {
+        self.limits
+    }
 
-        // If we get here, all attempts failed
-        bail!(last_error.unwrap_or_else(|| eyre!("Failed to fetch transactions")))
+    fn fetch_page(&self, page: u32) -> BoxFuture<'_, Result<String>> {
+        self.fetch_transaction_one_page(page).boxed()
     }
 }
 
 fn api_response_to_transactions(s: &str) -> Result<Vec<Transaction>> {
-    let api_response = serde_json::from_str::<ApiResponse>(s).map_err(|e| {
-        if e.is_data() && format!("{}", e).contains("missing field `rows`") {
-            eyre!("{}. This may indicate that your cookie has expired.", e).with_note(
-                || "Consider re-logging in to card.xjtu.edu.cn and updating your cookie.",
-            )
-        } else {
-            eyre!("Failed to parse API response: {}", e)
-        }
-        .with_section(|| s.to_string().header("Incorrect API response:"))
+    let reply = serde_json::from_str::<ApiReply>(s)
+        .map_err(|e| FetchError::MalformedResponse(e.to_string()));
+    let api_response = match reply {
+        Ok(ApiReply::Rows(api_response)) => Ok(api_response),
+        // rows that are there but not a list are a broken answer, not a login
+        Ok(ApiReply::Other(reply)) if reply.contains_key("rows") => Err(
+            FetchError::MalformedResponse("`rows` is not a list of rows".to_string()),
+        ),
+        Ok(ApiReply::Other(_)) => Err(FetchError::SessionExpired),
+        Err(e) => Err(e),
+    }
+    .map_err(|e| {
+        e.into_report()
+            .with_section(|| s.to_string().header("Incorrect API response:"))
     })?;
 
-    let row_map = |row: TransactionRow| {
+    let row_map = |raw: serde_json::Value| {
+        let row = TransactionRow::deserialize(&raw).map_err(|e| {
+            FetchError::MalformedResponse(e.to_string())
+                .into_report()
+                .with_section(|| raw.to_string().header("Incorrect transaction row:"))
+        })?;
         // Parse the date
         let time_str = &row.time.trim();
         let Ok(time) = Transaction::parse_to_fixed_utc_plus8(time_str, "%Y-%m-%d %H:%M:%S") else {
-            return None;
+            return Ok(None);
         };
 
         let amount = row.amount;
+        // rows that move no money carry no information
+        if amount == Money::ZERO {
+            return Ok(None);
+        }
         let merchant = row.merchant.trim().to_string();
+        let trimmed = |s: &Option<String>| {
+            s.as_deref()
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+        };
 
-        Some(Transaction::new(amount, merchant, time))
+        let mut transaction = Transaction::new(amount, merchant, time);
+        transaction.kind = classify_kind(amount, row.tran_name.as_deref());
+        transaction.balance = row.balance;
+        transaction.terminal = trimmed(&row.terminal);
+        transaction.tran_type = trimmed(&row.tran_name);
+        transaction.raw = Some(raw.to_string());
+        Ok(Some(transaction))
     };
 
-    Ok(api_response
+    api_response
         .rows
         .into_iter()
-        .filter_map(row_map)
-        .filter(|t| t.amount < 0.0)
-        .collect())
+        .filter_map(|raw| row_map(raw).transpose())
+        .collect()
 }
 
-pub fn fetch<F>(
-    end_time: DateTime<FixedOffset>,
-    client: MealFetcher,
-    progress_cb: F,
-) -> Result<Vec<Transaction>>
-where
-    F: Fn(FetchProgress) -> Result<()>,
-{
-    let mut all_transactions: Vec<Transaction> = Vec::new();
-    let max_pages = 200;
+/// Decide the [`TransactionKind`] of a row from its amount and `TRANNAME`
+///
+/// Negative amounts are always expenses. Incoming money is a refund or subsidy
+/// when the type name says so, and a top-up otherwise.
+fn classify_kind(amount: Money, tran_name: Option<&str>) -> TransactionKind {
+    if amount.is_negative() {
+        return TransactionKind::Expense;
+    }
+    let tran_name = tran_name.unwrap_or_default();
+    if ["退款", "冲正", "撤销"]
+        .iter()
+        .any(|k| tran_name.contains(k))
+    {
+        TransactionKind::Refund
+    } else if ["补助", "补贴"].iter().any(|k| tran_name.contains(k)) {
+        TransactionKind::Subsidy
+    } else {
+        TransactionKind::TopUp
+    }
+}
 
-    progress_cb(FetchProgress {
-        current_page: 0,
-        total_entries_fetched: 0,
-        oldest_date: None,
-    })?;
+/// A page of transactions fetched by [`fetch_pages`]
+#[derive(Debug, Clone)]
+pub struct FetchedPage {
+    /// Transactions of the page newer than the end time, newest first, with
+    /// their final IDs
+    pub transactions: Vec<Transaction>,
+    /// Progress of the fetch up to and including this page
+    pub progress: FetchProgress,
+    /// Where to resume once this page is stored
+    pub checkpoint: FetchCheckpoint,
+}
 
-    for page in 1..=max_pages {
-        let page_transactions = match &client {
-            MealFetcher::Real(c) => c.fetch_transaction_one_page(page),
-            MealFetcher::Mock(c) => c.fetch_transaction_one_page(page),
-        }
-        .with_context(|| format!("Error when fetching on page {}", page))?;
+/// Where [`fetch_pages`] is at between two pages
+struct PageCursor {
+    source: Arc<dyn TransactionSource>,
+    cancel: CancellationToken,
+    /// Where to resume after the last page
+    checkpoint: FetchCheckpoint,
+    /// Page to request next
+    page: u32,
+    /// Pages requested so far, bounded by [`FetchLimits::max_pages`]
+    requested: u32,
+    sequencer: IdSequencer,
+    total_entries_fetched: u32,
+    done: bool,
+}
 
-        let page_transactions =
-            api_response_to_transactions(&page_transactions).with_context(|| {
+/// Fetch transactions newer than the end time of a checkpoint from a source,
+/// page by page
+///
+/// The stream ends after the last page, or for a
+/// [`newest_first`](SourceCapabilities::newest_first) source after the page
+/// reaching the end time. It ends with an error if a page cannot be fetched,
+/// with [`FetchError::PageLimit`] once [`FetchLimits::max_pages`] pages were
+/// requested without reaching the end, or as soon as `cancel` is cancelled,
+/// aborting the request in flight.
+///
+/// Each page carries the checkpoint to resume from once it is stored. For a
+/// newest first source, that is the page on which the oldest second reached
+/// first appeared, keeping only that second and older ones: identical
+/// purchases in it get the same IDs as if the fetch had never stopped, and
+/// pages moved down by newer transactions are fetched again rather than
+/// skipped. Other sources resume from the next page, numbering identical
+/// purchases on from the ID counters of the last page.
+pub fn fetch_pages(
+    checkpoint: FetchCheckpoint,
+    source: Arc<dyn TransactionSource>,
+    cancel: CancellationToken,
+) -> impl Stream<Item = Result<FetchedPage>> {
+    let cursor = PageCursor {
+        source,
+        cancel,
+        page: checkpoint.page,
+        requested: 0,
+        sequencer: IdSequencer::resume(&checkpoint.counters),
+        checkpoint,
+        total_entries_fetched: 0,
+        done: false,
+    };
+    stream::unfold(cursor, |mut cursor| async move {
+        if cursor.done {
+            return None;
+        }
+        let max_pages = cursor.source.fetch_limits().max_pages;
+        if cursor.requested >= max_pages {
+            // the checkpoint of the last page is kept, so the fetch can go on
+            cursor.done = true;
+            return Some((Err(FetchError::PageLimit(max_pages).into_report()), cursor));
+        }
+        cursor.requested += 1;
+        let page = cursor.page;
+        let response = tokio::select! {
+            biased;
+            _ = cursor.cancel.cancelled() => Err(eyre!("Fetching was cancelled")),
+            response = cursor.source.fetch_page(page) => {
+                response.with_context(|| format!("Error when fetching on page {}", page))
+            }
+        };
+        let transactions = response.and_then(|response| {
+            cursor.source.parse_page(&response).with_context(|| {
                 format!(
-                    "Error when parsing data returned from XJTU server on page {}",
+                    "Error when parsing data returned from {} on page {}",
+                    cursor.source.name(),
                     page
                 )
-            })?;
-        if page_transactions.is_empty() {
-            break;
-        }
-
-        all_transactions.extend(page_transactions);
+            })
+        });
+        let mut transactions = match transactions {
+            Ok(transactions) if transactions.is_empty() => return None,
+            Ok(transactions) => transactions,
+            Err(e) => {
+                cursor.done = true;
+                return Some((Err(e), cursor));
+            }
+        };
 
+        cursor.total_entries_fetched += transactions.len() as u32;
+        let oldest_date = transactions.iter().map(|t| t.time).min();
+        let progress = FetchProgress {
+            current_page: page,
+            total_entries_fetched: cursor.total_entries_fetched,
+            oldest_date,
+        };
         // Check if we've reached transactions older than the end timestamp
-        if let Some(last_transaction) = all_transactions.last() {
-            progress_cb(FetchProgress {
-                current_page: page,
-                total_entries_fetched: all_transactions.len() as u32,
-                oldest_date: Some(last_transaction.time),
-            })?;
-
-            let last_timestamp = last_transaction.time.timestamp();
-            if last_timestamp <= end_time.timestamp() {
-                // Filter out transactions older than the end timestamp
-                all_transactions.retain(|t| (t.time.timestamp()) > end_time.timestamp());
-                break;
+        let end = cursor.checkpoint.end_time.timestamp();
+        if oldest_date.is_some_and(|t| t.timestamp() <= end) {
+            transactions.retain(|t| t.time.timestamp() > end);
+            cursor.done = cursor.source.capabilities().newest_first;
+        }
+
+        if cursor.source.capabilities().newest_first {
+            // stored before the fetch was interrupted
+            if let Some(before) = cursor.checkpoint.before {
+                transactions.retain(|t| t.time.timestamp() <= before.timestamp());
+            }
+            cursor.sequencer.assign(&mut transactions);
+            if let Some(oldest) = transactions.iter().map(|t| t.time).min()
+                && cursor.checkpoint.before != Some(oldest)
+            {
+                cursor.checkpoint.page = page;
+                cursor.checkpoint.before = Some(oldest);
             }
         } else {
-            bail!("No transactions fetched");
+            cursor.sequencer.assign(&mut transactions);
+            cursor.checkpoint.page = page + 1;
+            cursor.checkpoint.counters = cursor.sequencer.counters(&transactions);
         }
-    }
 
-    Ok(all_transactions)
+        cursor.page += 1;
+        Some((
+            Ok(FetchedPage {
+                transactions,
+                progress,
+                checkpoint: cursor.checkpoint.clone(),
+            }),
+            cursor,
+        ))
+    })
 }
 
 #[derive(Debug, Clone)]
 pub struct MockMealFetcher {
     sim_delay: Option<Duration>,
     per_page: u32,
-    data: Vec<TransactionRow>,
+    limits: FetchLimits,
+    data: Vec<serde_json::Value>,
 }
 
 impl Default for MockMealFetcher {
@@ -292,23 +656,25 @@ impl Default for MockMealFetcher {
             env!("CARGO_MANIFEST_DIR"),
             "/test/mock-data/mock-transactions.json"
         ));
-        let mut  data = serde_json::from_str::<Vec<TransactionRow>>(data).context(
+        let mut  data = serde_json::from_str::<Vec<serde_json::Value>>(data).context(
             "Failed to parse mock data. This may indicate that the mock data file is missing or corrupted.",
         ).unwrap();
 
-        let parse_date = |date_str: &str| {
+        let parse_date = |row: &serde_json::Value| {
+            let date_str = row["OCCTIME"].as_str().unwrap();
             Transaction::parse_to_fixed_utc_plus8(date_str, "%Y-%m-%d %H:%M:%S").unwrap()
         };
 
         data.sort_by(|a, b| {
-            let a_time = parse_date(&a.time);
-            let b_time = parse_date(&b.time);
+            let a_time = parse_date(a);
+            let b_time = parse_date(b);
             b_time.cmp(&a_time)
         });
 
         Self {
             sim_delay: None,
             per_page: 20,
+            limits: FetchLimits::default(),
             data,
         }
     }
@@ -328,11 +694,26 @@ impl MockMealFetcher {
         self
     }
 
-    fn fetch_transaction_one_page(&self, page: u32) -> Result<String> {
+    /// Serve these rows, in this order, instead of the bundled mock data
+    #[cfg(test)]
+    pub fn rows(self, data: Vec<serde_json::Value>) -> Self {
+        Self { data, ..self }
+    }
+
+    /// Only [`FetchLimits::max_pages`] applies to mock data
+    pub fn limits(self, limits: FetchLimits) -> Self {
+        Self { limits, ..self }
+    }
+
+    async fn fetch_transaction_one_page(&self, page: u32) -> Result<String> {
         if let Some(d) = self.sim_delay {
-            sleep(d);
+            tokio::time::sleep(d).await;
         }
+        self.page_response(page)
+    }
 
+    /// The API response for a page, without the simulated delay
+    fn page_response(&self, page: u32) -> Result<String> {
         let start = std::cmp::min((page - 1) * self.per_page, self.data.len() as u32);
         let end = std::cmp::min(start + self.per_page, self.data.len() as u32);
 
@@ -345,15 +726,65 @@ impl MockMealFetcher {
     }
 }
 
+impl TransactionSource for MockMealFetcher {
+    fn name(&self) -> &'static str {
+        "mock data"
+    }
+
+    fn capabilities(&self) -> SourceCapabilities {
+        SourceCapabilities {
+            needs_credentials: false,
+            newest_first: true,
+        }
+    }
+
+    fn with_credentials(&self, _account: &str, _cookie: &str) -> Arc<dyn TransactionSource> {
+        Arc::new(self.clone())
+    }
+
+    fn fetch_limits(&self) -> FetchLimits {
+        self.limits
+    }
+
+    fn fetch_page(&self, page: u32) -> BoxFuture<'_, Result<String>> {
+        self.fetch_transaction_one_page(page).boxed()
+    }
+}
+
 #[cfg(test)]
 pub mod test_utils {
-    use crate::libs::transactions::Transaction;
-
-    use super::{MockMealFetcher, api_response_to_transactions};
+    use std::sync::Arc;
+
+    use chrono::{DateTime, FixedOffset};
+    use color_eyre::Result;
+    use futures::StreamExt;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::libs::transactions::{FetchCheckpoint, Transaction};
+
+    use super::{MockMealFetcher, TransactionSource, api_response_to_transactions, fetch_pages};
+
+    /// Fetch all transactions newer than `end_time` without storing them
+    pub async fn fetch(
+        end_time: DateTime<FixedOffset>,
+        source: Arc<dyn TransactionSource>,
+        cancel: CancellationToken,
+    ) -> Result<Vec<Transaction>> {
+        let mut all_transactions = Vec::new();
+        let mut pages = std::pin::pin!(fetch_pages(
+            FetchCheckpoint::start(end_time),
+            source,
+            cancel
+        ));
+        while let Some(page) = pages.next().await {
+            all_transactions.extend(page?.transactions);
+        }
+        Ok(all_transactions)
+    }
 
     pub fn get_mock_data(count: u32) -> Vec<Transaction> {
         let fetcher = MockMealFetcher::default().per_page(count);
-        let data = fetcher.fetch_transaction_one_page(1).unwrap();
+        let data = fetcher.page_response(1).unwrap();
         api_response_to_transactions(&data).unwrap()
     }
 
@@ -376,6 +807,7 @@ mod tests {
     use chrono::Duration as CDuration;
     use chrono::Local;
     use chrono::TimeZone as _;
+    use futures::StreamExt;
     use std::time::{Duration, Instant};
 
     use crate::libs::transactions::OFFSET_UTC_PLUS8;
@@ -392,13 +824,124 @@ mod tests {
     }
 
     #[test]
-    fn test_fetch_mock() {
+    fn test_api_response_errors() {
+        // the captured envelope without its rows, as sent for an expired session
+        let mut expired: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/test/mock-data/api-resp.json"
+            )))
+            .unwrap();
+        expired.remove("rows");
+        for resp in [
+            serde_json::to_string(&expired).unwrap().as_str(),
+            r#"{"issucceed": false, "total": 0}"#,
+            "{}",
+        ] {
+            let err = api_response_to_transactions(resp).unwrap_err();
+            assert_eq!(
+                FetchError::of(&err),
+                Some(&FetchError::SessionExpired),
+                "{}",
+                resp
+            );
+            assert_eq!(FetchError::of(&err).unwrap().kind(), "session_expired");
+        }
+
+        for resp in [
+            r#"{"rows": null, "total": 0}"#,
+            r#"{"rows": "none"}"#,
+            r#"["not", "an", "object"]"#,
+            "<html>busy</html>",
+        ] {
+            let err = api_response_to_transactions(resp).unwrap_err();
+            assert!(
+                matches!(FetchError::of(&err), Some(FetchError::MalformedResponse(_))),
+                "{}",
+                resp
+            );
+            assert_eq!(FetchError::of(&err).unwrap().kind(), "malformed_response");
+        }
+    }
+
+    #[test]
+    fn test_classify_kind() {
+        assert_eq!(
+            classify_kind(Money::from_yuan(-4.0), Some("电子账户消费")),
+            TransactionKind::Expense
+        );
+        assert_eq!(
+            classify_kind(Money::from_yuan(100.0), Some("银行转账充值")),
+            TransactionKind::TopUp
+        );
+        assert_eq!(
+            classify_kind(Money::from_yuan(100.0), None),
+            TransactionKind::TopUp
+        );
+        assert_eq!(
+            classify_kind(Money::from_yuan(4.0), Some("消费退款")),
+            TransactionKind::Refund
+        );
+        assert_eq!(
+            classify_kind(Money::from_yuan(50.0), Some("补助发放")),
+            TransactionKind::Subsidy
+        );
+    }
+
+    #[test]
+    fn test_keep_incoming_rows() {
+        let resp = r#"{"rows": [
+            {"OCCTIME": "2025-03-24 13:13:28", "MERCNAME": "库迪咖啡 ", "TRANAMT": -4, "TRANNAME": "电子账户消费"},
+            {"OCCTIME": "2025-03-24 12:00:00", "MERCNAME": "", "TRANAMT": 100, "TRANNAME": "银行转账充值"},
+            {"OCCTIME": "2025-03-24 11:00:00", "MERCNAME": "", "TRANAMT": 0}
+        ]}"#;
+        let transactions = api_response_to_transactions(resp).unwrap();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].kind, TransactionKind::Expense);
+        assert_eq!(transactions[1].kind, TransactionKind::TopUp);
+        assert_eq!(transactions[1].amount, Money::from_cents(10000));
+    }
+
+    #[test]
+    fn test_keep_raw_row() {
+        let resp = r#"{"rows": [
+            {"OCCTIME": "2025-03-24 13:13:28", "MERCNAME": "库迪咖啡 ", "TRANAMT": -4,
+             "TRANNAME": "电子账户消费 ", "CARDBAL": 96.5, "POSCODE": 10203, "JDESC": "消费"}
+        ]}"#;
+        let transactions = api_response_to_transactions(resp).unwrap();
+        let t = &transactions[0];
+        assert_eq!(t.balance, Some(Money::from_cents(9650)));
+        assert_eq!(t.terminal.as_deref(), Some("10203"));
+        assert_eq!(t.tran_type.as_deref(), Some("电子账户消费"));
+
+        let raw: serde_json::Value = serde_json::from_str(t.raw.as_ref().unwrap()).unwrap();
+        assert_eq!(raw["JDESC"], "消费");
+        assert_eq!(raw["POSCODE"], 10203);
+        assert_eq!(raw["MERCNAME"], "库迪咖啡 ");
+        assert_eq!(raw["CARDBAL"], 96.5);
+
+        // the row is stored as sent, whatever name the terminal id comes under
+        let resp = r#"{"rows": [
+            {"OCCTIME": "2025-03-24 13:13:28", "MERCNAME": "库迪咖啡", "TRANAMT": -4,
+             "TERMID": "T-7"}
+        ]}"#;
+        let t = &api_response_to_transactions(resp).unwrap()[0];
+        assert_eq!(t.terminal.as_deref(), Some("T-7"));
+        let raw: serde_json::Value = serde_json::from_str(t.raw.as_ref().unwrap()).unwrap();
+        assert_eq!(raw["TERMID"], "T-7");
+        assert!(raw.get("POSCODE").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_mock() {
         let fetcher = MockMealFetcher::default();
         let end_time = OFFSET_UTC_PLUS8
             .with_ymd_and_hms(2025, 3, 1, 0, 0, 0)
             .unwrap();
 
-        let transactions = fetch(end_time, MealFetcher::Mock(fetcher), |_| Ok(())).unwrap();
+        let transactions = test_utils::fetch(end_time, Arc::new(fetcher), CancellationToken::new())
+            .await
+            .unwrap();
         assert!(!transactions.is_empty());
         transactions.iter().for_each(|t| {
             assert!(t.time.timestamp() > end_time.timestamp());
@@ -407,60 +950,35 @@ mod tests {
 
     #[tokio::test]
     async fn test_fetch_mock_progress() {
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<FetchProgress>(1);
-        let (c_tx, mut c_rx) = tokio::sync::mpsc::unbounded_channel::<u32>();
-        tokio::task::spawn_blocking(move || {
-            let fetcher = MockMealFetcher::default().set_sim_delay(Duration::from_millis(200));
-            let end_time = OFFSET_UTC_PLUS8
-                .with_ymd_and_hms(2025, 3, 6, 0, 0, 0)
-                .unwrap();
-            let result = fetch(end_time, MealFetcher::Mock(fetcher), |fp| {
-                tx.blocking_send(fp)?;
-                Ok(())
-            })
+        let fetcher = MockMealFetcher::default().set_sim_delay(Duration::from_millis(200));
+        let end_time = OFFSET_UTC_PLUS8
+            .with_ymd_and_hms(2025, 3, 6, 0, 0, 0)
             .unwrap();
-            c_tx.send(result.len() as u32).unwrap();
-            drop(tx);
-            drop(c_tx);
-        });
-
-        let mut received = Vec::<(FetchProgress, Instant)>::new();
+        let pages = fetch_pages(
+            FetchCheckpoint::start(end_time),
+            Arc::new(fetcher),
+            CancellationToken::new(),
+        );
+        let mut pages = std::pin::pin!(pages);
 
-        loop {
-            match tokio::time::timeout(Duration::from_secs(5), rx.recv()).await {
-                Ok(Some(p)) => received.push((p, Instant::now())),
-                Ok(None) => break,
-                Err(_) => println!("timeout"),
-            }
+        let mut received = Vec::<(FetchedPage, Instant)>::new();
+        while let Some(page) = pages.next().await {
+            received.push((page.unwrap(), Instant::now()));
         }
-
         assert_ne!(received.len(), 0);
 
-        loop {
-            match tokio::time::timeout(Duration::from_secs(5), c_rx.recv()).await {
-                Ok(Some(p)) => assert!(
-                    // some fetched items are older than end_time, so they are filtered out
-                    // thus, reported progress may be larger than the total fetched items
-                    p <= received.last().unwrap().0.total_entries_fetched,
-                    "fetched items count should be smaller as progress-reported total"
-                ),
-                Ok(None) => break,
-                Err(_) => println!("timeout"),
-            }
+        let fetched: usize = received.iter().map(|(p, _)| p.transactions.len()).sum();
+        // some fetched items are older than end_time, so they are filtered out
+        // thus, reported progress may be larger than the total fetched items
+        assert!(fetched as u32 <= received.last().unwrap().0.progress.total_entries_fetched);
+        for (i, (page, _)) in received.iter().enumerate() {
+            assert_eq!(page.progress.current_page, i as u32 + 1);
         }
 
-        let gaps = if received.len() > 1 {
-            received
-                .windows(2)
-                .map(|window| {
-                    let (_, first_instant) = &window[0];
-                    let (_, second_instant) = &window[1];
-                    second_instant.duration_since(*first_instant)
-                })
-                .collect::<Vec<Duration>>()
-        } else {
-            vec![]
-        };
+        let gaps = received
+            .windows(2)
+            .map(|window| window[1].1.duration_since(window[0].1))
+            .collect::<Vec<Duration>>();
 
         // Verify we have appropriate delays between progress updates
         assert!(
@@ -469,14 +987,32 @@ mod tests {
             "Progress updates should be appropriate due to simulated delay (200ms), {:?}",
             gaps
         );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_cancelled() {
+        let fetcher = MockMealFetcher::default().set_sim_delay(Duration::from_secs(10));
+        let end_time = OFFSET_UTC_PLUS8
+            .with_ymd_and_hms(2022, 1, 1, 0, 0, 0)
+            .unwrap();
+        let cancel = CancellationToken::new();
 
-        println!("{:?}\n{:?}", received, gaps);
+        let started = Instant::now();
+        let cancel_soon = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            cancel_soon.cancel();
+        });
+        let result = test_utils::fetch(end_time, Arc::new(fetcher), cancel).await;
+        // the page being fetched is abandoned
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(result.unwrap_err().to_string().contains("cancelled"));
     }
 
-    #[test]
-    fn test_request() {
+    #[tokio::test]
+    async fn test_request() {
         // Request a new server from the pool
-        let mut server = mockito::Server::new();
+        let mut server = mockito::Server::new_async().await;
 
         // Use one of these addresses to configure your client
         let _host = server.host_with_port();
@@ -491,25 +1027,143 @@ mod tests {
                 env!("CARGO_MANIFEST_DIR"),
                 "/test/mock-data/api-resp.json"
             )))
-            .create();
+            .create_async()
+            .await;
 
         let fetch = RealMealFetcher::default()
             .account("Account")
             .cookie("Cookie")
             .origin(url);
 
-        let t = fetch.fetch_transaction_one_page(1).unwrap();
+        let t = fetch.fetch_transaction_one_page(1).await.unwrap();
 
-        assert_eq!(api_response_to_transactions(&t).unwrap().is_empty(), false);
+        assert!(!api_response_to_transactions(&t).unwrap().is_empty());
 
         // You can use `Mock::assert` to verify that your mock was called
         // TODO check if request is valid
-        mock.assert();
+        mock.assert_async().await;
+    }
+
+    /// Limits that retry without slowing the tests down
+    fn quick_limits(max_attempts: u32) -> FetchLimits {
+        FetchLimits {
+            max_attempts,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(20),
+            page_delay: Duration::ZERO,
+            ..Default::default()
+        }
     }
 
     #[test]
+    fn test_backoff() {
+        let limits = FetchLimits::default();
+        for attempt in 1..10 {
+            let exp = (limits.initial_backoff * 2u32.pow(attempt - 1)).min(limits.max_backoff);
+            let wait = limits.backoff(attempt);
+            assert!(
+                wait >= exp / 2 && wait <= exp,
+                "{:?} for attempt {}",
+                wait,
+                attempt
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_server_errors() {
+        let mut server = mockito::Server::new_async().await;
+        // mocks are matched in order until they got their expected hits
+        let failing = server
+            .mock("POST", API_PATH)
+            .with_status(503)
+            .expect(2)
+            .create_async()
+            .await;
+        let ok = server
+            .mock("POST", API_PATH)
+            .with_status(200)
+            .with_body(r#"{"rows": []}"#)
+            .create_async()
+            .await;
+
+        let fetcher = RealMealFetcher::default()
+            .account("Account")
+            .cookie("Cookie")
+            .origin(server.url())
+            .limits(quick_limits(3));
+        assert!(fetcher.fetch_transaction_one_page(1).await.is_ok());
+        failing.assert_async().await;
+        ok.assert_async().await;
+
+        // gives up once out of attempts
+        server.reset();
+        let fetcher = fetcher.limits(quick_limits(2));
+        let failing = server
+            .mock("POST", API_PATH)
+            .with_status(500)
+            .expect(2)
+            .create_async()
+            .await;
+        let err = fetcher.fetch_transaction_one_page(1).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("Gave up after 2 attempts"));
+        assert_eq!(FetchError::of(&err), Some(&FetchError::Server(500)));
+        failing.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_retried() {
+        let mut server = mockito::Server::new_async().await;
+        let rejected = server
+            .mock("POST", API_PATH)
+            .with_status(403)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let fetcher = RealMealFetcher::default()
+            .account("Account")
+            .cookie("Cookie")
+            .origin(server.url())
+            .limits(quick_limits(3));
+        let err = fetcher.fetch_transaction_one_page(1).await.unwrap_err();
+        assert_eq!(FetchError::of(&err), Some(&FetchError::SessionExpired));
+        rejected.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_max_pages() {
+        let end_time = OFFSET_UTC_PLUS8
+            .with_ymd_and_hms(2022, 1, 1, 0, 0, 0)
+            .unwrap();
+        let fetcher = MockMealFetcher::default().limits(FetchLimits {
+            max_pages: 2,
+            ..Default::default()
+        });
+        let pages = fetch_pages(
+            FetchCheckpoint::start(end_time),
+            Arc::new(fetcher.clone()),
+            CancellationToken::new(),
+        )
+        .collect::<Vec<_>>()
+        .await;
+        assert_eq!(pages.len(), 3);
+        assert!(pages[..2].iter().all(Result::is_ok));
+        let err = pages[2].as_ref().unwrap_err();
+        assert_eq!(FetchError::of(err), Some(&FetchError::PageLimit(2)));
+
+        // the limit counts the pages of one fetch, not page numbers
+        let checkpoint = pages[1].as_ref().unwrap().checkpoint.clone();
+        let pages = fetch_pages(checkpoint, Arc::new(fetcher), CancellationToken::new())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(pages.len(), 3);
+        assert!(pages[..2].iter().all(Result::is_ok));
+    }
+
+    #[tokio::test]
     #[ignore]
-    fn test_fetch_transactions() {
+    async fn test_fetch_transactions() {
         dotenv::dotenv().ok();
 
         let cookie = std::env::var("XMF_COOKIE").unwrap();
@@ -517,7 +1171,9 @@ mod tests {
         let end_time = Local::now().fixed_offset() - CDuration::days(7);
         let fetch = RealMealFetcher::default().account(account).cookie(cookie);
 
-        let transactions = super::fetch(end_time, MealFetcher::Real(fetch), |_| Ok(())).unwrap();
+        let transactions = test_utils::fetch(end_time, Arc::new(fetch), CancellationToken::new())
+            .await
+            .unwrap();
         println!("{:?}", transactions);
         assert!(!transactions.is_empty());
     }
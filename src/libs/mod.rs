@@ -1,3 +1,4 @@
 pub mod export_csv;
 pub mod fetcher;
+pub mod money;
 pub mod transactions;
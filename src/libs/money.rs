@@ -0,0 +1,210 @@
+//! Fixed-point money type
+//!
+//! Amounts are kept as a whole number of cents (分) so that sums never pick up
+//! floating-point error and range filters compare exactly.
+
+use std::{
+    iter::Sum,
+    ops::{Add, AddAssign, Neg, Sub},
+    str::FromStr,
+};
+
+use color_eyre::eyre::{Result, bail, eyre};
+use rusqlite::{
+    ToSql,
+    types::{FromSql, FromSqlResult, ToSqlOutput, ValueRef},
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// An amount of money in cents, negative for money leaving the card
+///
+/// Serialized as a JSON number of yuan (e.g. `-18.72`) so the web API keeps
+/// its shape; the value always has at most two decimals.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(i64);
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    pub const fn from_cents(cents: i64) -> Self {
+        Money(cents)
+    }
+
+    /// Convert an amount in yuan, rounding to the nearest cent
+    pub fn from_yuan(yuan: f64) -> Self {
+        Money((yuan * 100.0).round() as i64)
+    }
+
+    pub const fn cents(self) -> i64 {
+        self.0
+    }
+
+    /// The amount in yuan, for charts and other places that need a float
+    pub fn to_yuan(self) -> f64 {
+        self.0 as f64 / 100.0
+    }
+
+    pub const fn abs(self) -> Self {
+        Money(self.0.saturating_abs())
+    }
+
+    pub const fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+}
+
+impl std::fmt::Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let cents = self.0.unsigned_abs();
+        write!(f, "{}{}.{:02}", sign, cents / 100, cents % 100)
+    }
+}
+
+impl FromStr for Money {
+    type Err = color_eyre::Report;
+
+    /// Parse a decimal amount in yuan such as `-18.72`, `4` or `0.5` without
+    /// going through a float
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (yuan, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if yuan.is_empty() && fraction.is_empty()
+            || fraction.len() > 2
+            || !yuan
+                .chars()
+                .chain(fraction.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            bail!("Invalid amount: {}", s);
+        }
+        let yuan: i64 = if yuan.is_empty() {
+            0
+        } else {
+            yuan.parse().map_err(|_| eyre!("Invalid amount: {}", s))?
+        };
+        let fraction: i64 = format!("{:0<2}", fraction).parse().unwrap_or(0);
+        let cents = yuan
+            .checked_mul(100)
+            .and_then(|c| c.checked_add(fraction))
+            .ok_or_else(|| eyre!("Amount out of range: {}", s))?;
+        Ok(Money(if negative { -cents } else { cents }))
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+    fn add(self, rhs: Self) -> Self::Output {
+        Money(self.0 + rhs.0)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, rhs: Self) {
+        self.0 += rhs.0;
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+    fn sub(self, rhs: Self) -> Self::Output {
+        Money(self.0 - rhs.0)
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+    fn neg(self) -> Self::Output {
+        Money(-self.0)
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Money::ZERO, Add::add)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.to_yuan())
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Number(f64),
+            Text(String),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Number(yuan) => Ok(Money::from_yuan(yuan)),
+            Repr::Text(s) => s.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl ToSql for Money {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.0))
+    }
+}
+
+impl FromSql for Money {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        i64::column_result(value).map(Money)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        assert_eq!(Money::from_cents(-1872).to_string(), "-18.72");
+        assert_eq!(Money::from_cents(-5).to_string(), "-0.05");
+        assert_eq!(Money::from_cents(400).to_string(), "4.00");
+        assert_eq!(Money::ZERO.to_string(), "0.00");
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("-18.72".parse::<Money>().unwrap(), Money::from_cents(-1872));
+        assert_eq!("4".parse::<Money>().unwrap(), Money::from_cents(400));
+        assert_eq!("0.5".parse::<Money>().unwrap(), Money::from_cents(50));
+        assert_eq!("+.05".parse::<Money>().unwrap(), Money::from_cents(5));
+        assert!("1.234".parse::<Money>().is_err());
+        assert!("abc".parse::<Money>().is_err());
+        assert!("-".parse::<Money>().is_err());
+    }
+
+    #[test]
+    fn no_float_drift() {
+        let total: Money = std::iter::repeat_n(Money::from_yuan(0.1), 1000).sum();
+        assert_eq!(total, Money::from_cents(10000));
+        assert_eq!(Money::from_yuan(-1.37), Money::from_cents(-137));
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            serde_json::to_string(&Money::from_cents(-1872)).unwrap(),
+            "-18.72"
+        );
+        assert_eq!(
+            serde_json::from_str::<Money>("-18.72").unwrap(),
+            Money::from_cents(-1872)
+        );
+        assert_eq!(
+            serde_json::from_str::<Money>("\"3.5\"").unwrap(),
+            Money::from_cents(350)
+        );
+    }
+}
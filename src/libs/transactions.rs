@@ -1,32 +1,182 @@
 use std::{
+    collections::HashMap,
     path::PathBuf,
     sync::{Arc, Mutex},
 };
 
-use chrono::{DateTime, FixedOffset, TimeZone};
+use chrono::{DateTime, FixedOffset, NaiveTime, TimeZone, Weekday};
 use color_eyre::eyre::{Context, ContextCompat, Result, bail};
-use rusqlite::{Connection, params};
+use rusqlite::{
+    Connection, ToSql, params,
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
+};
 use serde::{Deserialize, Serialize}; // Added import
-
-#[derive(Debug, Clone, Serialize, Deserialize)] // Added Serialize, Deserialize
+use strum::{EnumIter, EnumString, IntoStaticStr};
+use tracing::warn;
+
+use super::money::Money;
+use crate::utils::merchant_class::MerchantType;
+
+mod aggregate;
+mod annotations;
+mod backup;
+mod conflicts;
+mod credentials;
+mod manual;
+mod merchant_search;
+mod migrations;
+mod pool;
+mod sync;
+
+use pool::Storage;
+
+pub use aggregate::{AggregateRow, GroupBy};
+pub use annotations::normalize_tags;
+pub use backup::Backups;
+pub use conflicts::{Conflict, Resolution};
+pub use credentials::{CredentialKey, mask_cookie, mask_secret};
+pub use merchant_search::MerchantMatch;
+pub use sync::{FetchCheckpoint, SyncState};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)] // Added Serialize, Deserialize
 pub struct Transaction {
     pub id: i64,
     /// Time of the transaction in UTC+8
     pub time: DateTime<FixedOffset>,
-    pub amount: f64,
+    pub amount: Money,
     pub merchant: String,
+    /// Whether money left (expense) or entered (top-up, refund, subsidy) the card
+    #[serde(default)]
+    pub kind: TransactionKind,
+    /// Card balance right after the transaction, if reported by the card system
+    #[serde(default)]
+    pub balance: Option<Money>,
+    /// Terminal (POS) id, telling apart the counters of one merchant
+    #[serde(default)]
+    pub terminal: Option<String>,
+    /// Transaction type name reported by the card system, e.g. "电子账户消费"
+    #[serde(default)]
+    pub tran_type: Option<String>,
+    /// The original row returned by the card system, as JSON
+    #[serde(default)]
+    pub raw: Option<String>,
+    /// Free-text note added by the user
+    #[serde(default)]
+    pub note: Option<String>,
+    /// Tags added by the user, sorted, like "exam week"
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Entered by hand rather than fetched, see [`TransactionManager::add_manual`]
+    #[serde(default)]
+    pub manual: bool,
+    /// Left out of listings and analysis unless asked for, see
+    /// [`FilterOptions::include_hidden`]
+    #[serde(default)]
+    pub hidden: bool,
+}
+
+/// Direction and nature of a transaction
+///
+/// Only [`TransactionKind::Expense`] counts as spending on food; the other kinds
+/// are money coming into the card and are kept for completeness.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    EnumIter,
+    EnumString,
+    IntoStaticStr,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum TransactionKind {
+    #[default]
+    Expense,
+    TopUp,
+    Refund,
+    Subsidy,
+}
+
+impl TransactionKind {
+    /// Kind implied by the sign of the amount alone
+    pub fn from_amount(amount: Money) -> Self {
+        if amount.is_negative() {
+            TransactionKind::Expense
+        } else {
+            TransactionKind::TopUp
+        }
+    }
+
+    /// Identifier used in the database and the web API
+    pub fn as_str(&self) -> &'static str {
+        self.into()
+    }
+
+    /// Human readable label used in the TUI
+    pub fn label(&self) -> &'static str {
+        match self {
+            TransactionKind::Expense => "消费",
+            TransactionKind::TopUp => "充值",
+            TransactionKind::Refund => "退款",
+            TransactionKind::Subsidy => "补助",
+        }
+    }
+}
+
+impl std::fmt::Display for TransactionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl ToSql for TransactionKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for TransactionKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
 }
 
 pub const OFFSET_UTC_PLUS8: FixedOffset =
     FixedOffset::east_opt(8 * 3600).expect("Failed to create FixedOffset +8");
 
+/// Account profile used when none is chosen, and the one holding all data
+/// from before profiles existed
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// 64-bit FNV-1a hash with the sign bit cleared, so the result is a non-negative `i64`
+fn fnv1a(key: &str) -> i64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let hash = key.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    });
+    (hash & i64::MAX as u64) as i64
+}
+
 impl Transaction {
-    pub fn new(amount: f64, merchant: String, time: DateTime<FixedOffset>) -> Self {
+    pub fn new(amount: Money, merchant: String, time: DateTime<FixedOffset>) -> Self {
         Transaction {
-            id: Transaction::hash(&format!("{}&{}&{}", time.timestamp(), amount, &merchant)),
+            id: Transaction::stable_id(time.timestamp(), amount.cents(), &merchant, 0),
             time,
             amount,
             merchant,
+            kind: TransactionKind::from_amount(amount),
+            ..Default::default()
         }
     }
 
@@ -42,22 +192,164 @@ impl Transaction {
             .with_context(|| format!("Ambiguous result when adding TZ info to {}", naive_dt))
     }
 
-    fn hash(s: &str) -> i64 {
-        use std::hash::{DefaultHasher, Hash, Hasher};
-        let mut hasher = DefaultHasher::new();
-        s.hash(&mut hasher);
-        i64::from_ne_bytes(hasher.finish().to_ne_bytes())
+    /// Compute the ID of a fetched transaction
+    ///
+    /// The ID is the 64-bit FNV-1a hash of the UTF-8 string
+    /// `v1|{unix timestamp}|{amount in cents}|{merchant}|{seq}`, with the sign bit
+    /// cleared. FNV-1a is fully specified, so IDs stay the same across Rust
+    /// releases and platforms; negative IDs are never produced.
+    ///
+    /// `seq` tells apart genuinely identical purchases (same second, amount and
+    /// merchant): it is the position of the transaction among its identical
+    /// siblings in the order the card system lists them, see
+    /// [`IdSequencer`].
+    pub fn stable_id(timestamp: i64, amount_cents: i64, merchant: &str, seq: u32) -> i64 {
+        fnv1a(&format!(
+            "v1|{}|{}|{}|{}",
+            timestamp, amount_cents, merchant, seq
+        ))
+    }
+
+    /// Scope a transaction ID to an account profile
+    ///
+    /// IDs in [`DEFAULT_PROFILE`] are kept as-is, so databases from before
+    /// profiles existed keep their IDs. Other profiles hash their name into the
+    /// ID, so the same purchase seen on two cards is stored twice.
+    pub fn scoped_id(id: i64, profile: &str) -> i64 {
+        if profile == DEFAULT_PROFILE {
+            id
+        } else {
+            fnv1a(&format!("{}|{}", profile, id))
+        }
+    }
+}
+
+/// Assigns the `seq` of [`Transaction::stable_id`] over several batches
+///
+/// Identical purchases can be split across two pages of a fetch, so the
+/// counters are kept from one page to the next.
+#[derive(Debug, Default)]
+pub struct IdSequencer {
+    seen: HashMap<(i64, i64, String), u32>,
+}
+
+impl IdSequencer {
+    /// Give identical transactions distinct IDs
+    ///
+    /// The first occurrence keeps `seq` 0, the next one gets 1, and so on,
+    /// counting the batches assigned before. Batches must be in the order
+    /// returned by the card system so that fetching the same period twice
+    /// yields the same IDs.
+    pub fn assign(&mut self, transactions: &mut [Transaction]) {
+        for t in transactions.iter_mut() {
+            let key = (t.time.timestamp(), t.amount.cents(), t.merchant.clone());
+            let seq = self.seen.entry(key).or_insert(0);
+            t.id = Transaction::stable_id(t.time.timestamp(), t.amount.cents(), &t.merchant, *seq);
+            *seq += 1;
+        }
+    }
+
+    /// A sequencer going on from counters saved with [`IdSequencer::counters`]
+    pub fn resume(counters: &[SeqCounter]) -> Self {
+        let seen = counters
+            .iter()
+            .map(|c| ((c.time, c.cents, c.merchant.clone()), c.next))
+            .collect();
+        Self { seen }
+    }
+
+    /// The counters of the purchases in `transactions`
+    ///
+    /// When pages are in time order, identical purchases split across two
+    /// pages are all on the boundary, so the counters of the last page are
+    /// enough to number on in a later fetch.
+    pub fn counters(&self, transactions: &[Transaction]) -> Vec<SeqCounter> {
+        let mut counters: Vec<SeqCounter> = Vec::new();
+        for t in transactions {
+            let key = (t.time.timestamp(), t.amount.cents(), t.merchant.clone());
+            if counters
+                .iter()
+                .any(|c| (c.time, c.cents, &c.merchant) == (key.0, key.1, &key.2))
+            {
+                continue;
+            }
+            if let Some(next) = self.seen.get(&key) {
+                counters.push(SeqCounter {
+                    time: key.0,
+                    cents: key.1,
+                    merchant: key.2,
+                    next: *next,
+                });
+            }
+        }
+        counters
+    }
+}
+
+/// The next `seq` an [`IdSequencer`] gives to a purchase
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeqCounter {
+    /// Unix timestamp of the purchase
+    pub time: i64,
+    pub cents: i64,
+    pub merchant: String,
+    pub next: u32,
+}
+
+/// Outcome of [`TransactionManager::insert`] for each transaction of the batch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InsertReport {
+    /// Transactions that were not stored yet
+    pub new: u64,
+    /// Transactions already stored, which were skipped
+    pub duplicate: u64,
+    /// Transactions conflicting with a stored one, which were quarantined
+    pub conflict: u64,
+}
+
+impl std::ops::AddAssign for InsertReport {
+    fn add_assign(&mut self, other: Self) {
+        self.new += other.new;
+        self.duplicate += other.duplicate;
+        self.conflict += other.conflict;
+    }
+}
+
+impl std::fmt::Display for InsertReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} new, {} already stored, {} conflicting",
+            self.new, self.duplicate, self.conflict
+        )
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct TransactionManager {
-    conn: Arc<Mutex<Connection>>,
+    db: Arc<Storage>,
+    /// Account profile that reads and writes are scoped to
+    ///
+    /// Shared by clones, so switching the profile affects every page at once.
+    profile: Arc<Mutex<String>>,
+    /// Key encrypting the account and cookie of every profile
+    key: Arc<CredentialKey>,
 }
 
 impl TransactionManager {
+    #[cfg(test)]
     pub fn new(db_path: Option<PathBuf>) -> Result<Self> {
-        let conn = match db_path.as_ref() {
+        TransactionManager::open(db_path, None)
+    }
+
+    /// Open the database, encrypting credentials with a key derived from the
+    /// passphrase if given
+    ///
+    /// Without a passphrase, a file database uses the key file next to it
+    /// (`transactions.key` for `transactions.db`), and an in-memory database
+    /// a random key.
+    pub fn open(db_path: Option<PathBuf>, passphrase: Option<&str>) -> Result<Self> {
+        let mut conn = match db_path.as_ref() {
             Some(db_path) => {
                 std::fs::create_dir_all(db_path.parent().unwrap())
                     .context("Failed to create dir for local cache DB")?;
@@ -71,202 +363,303 @@ impl TransactionManager {
             None => Connection::open_in_memory()?,
         };
 
-        // Initialize the database
-        TransactionManager::init_db(&conn)
-            .with_context(|| "Failed to initialize local cache DB")?;
+        // Initialize the database, upgrading the schema if needed
+        migrations::migrate(&mut conn).with_context(|| "Failed to initialize local cache DB")?;
+        merchant_search::register_functions(&conn)?;
+
+        let key = match (passphrase, &db_path) {
+            (Some(passphrase), _) => CredentialKey::from_passphrase(&conn, passphrase)?,
+            (None, Some(db_path)) => CredentialKey::load_or_create(&db_path.with_extension("key"))?,
+            (None, None) => CredentialKey::random(),
+        };
+        key.seal_database(&conn)?;
 
         Ok(TransactionManager {
-            conn: Arc::new(Mutex::new(conn)),
+            db: Arc::new(Storage::new(conn, db_path.as_ref())?),
+            profile: Arc::new(Mutex::new(DEFAULT_PROFILE.to_string())),
+            key: Arc::new(key),
         })
     }
 
-    fn init_db(conn: &Connection) -> Result<(), rusqlite::Error> {
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS transactions (
-                id INTEGER PRIMARY KEY,
-                time TEXT NOT NULL,
-                amount REAL NOT NULL,
-                merchant TEXT NOT NULL
-            )",
-            [],
-        )?;
-        conn.execute(
-            "CREATE TRIGGER IF NOT EXISTS prevent_transaction_conflict 
-                BEFORE INSERT ON transactions
-                FOR EACH ROW
-                BEGIN
-                    SELECT CASE
-                    WHEN EXISTS (
-                        SELECT 1 FROM transactions 
-                        WHERE id = NEW.id 
-                        AND time = NEW.time 
-                        AND amount = NEW.amount 
-                        AND merchant = NEW.merchant
-                    ) THEN
-                        RAISE(IGNORE)  -- 完全相同的记录则静默跳过
-                    WHEN EXISTS (
-                        SELECT 1 FROM transactions 
-                        WHERE id = NEW.id
-                    ) THEN
-                        RAISE(ABORT, 'Conflict: Existing transaction with different data')  -- ID存在但数据不同时终止
-                    END;
-                END;",
-            [],
-        )?;
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS cookies (
-            account TEXT PRIMARY KEY,
-            cookie TEXT NOT NULL
-        )",
-            [],
-        )?;
-        Ok(())
+    /// Name of the active account profile
+    pub fn profile(&self) -> String {
+        self.profile.lock().unwrap().clone()
     }
 
-    pub fn insert(&self, transactions: &Vec<Transaction>) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-
-        // insert at once
-        let mut stmt = conn
-            .prepare("INSERT INTO transactions (id, time, amount, merchant) VALUES (?, ?, ?, ?)")?;
+    /// Switch this manager and all its clones to another account profile,
+    /// creating it if needed
+    pub fn switch_profile(&self, name: &str) -> Result<()> {
+        self.create_profile(name)?;
+        *self.profile.lock().unwrap() = name.trim().to_string();
+        Ok(())
+    }
 
-        for transaction in transactions {
-            stmt.execute(params![
-                transaction.id,
-                transaction.time,
-                transaction.amount,
-                transaction.merchant
-            ])
-            .with_context(|| {
-                format!(
-                    "Error when inserting transactions into Database, transaction: {:?}",
-                    transaction
-                )
-            })?;
-        }
+    /// Create an account profile without credentials, if it does not exist yet
+    pub fn create_profile(&self, name: &str) -> Result<()> {
+        let name = TransactionManager::check_profile_name(name)?;
+        self.db
+            .write()
+            .execute("INSERT OR IGNORE INTO profiles (name) VALUES (?)", [name])?;
         Ok(())
     }
 
-    /// Fetch all transactions from the database
+    /// A manager sharing this database but scoped to another account profile
     ///
-    /// Do not guarantee the order of transactions
-    pub fn fetch_all(&self) -> Result<Vec<Transaction>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT id, time, amount, merchant FROM transactions")?;
-        let transactions = stmt.query_map([], |row| {
-            Ok(Transaction {
-                id: row.get(0)?,
-                time: row.get(1)?,
-                amount: row.get(2)?,
-                merchant: row.get(3)?,
-            })
-        })?;
+    /// Unlike [`TransactionManager::switch_profile`], existing clones are not
+    /// affected, which makes it suitable for serving concurrent web requests.
+    pub fn for_profile(&self, name: &str) -> Result<Self> {
+        let name = TransactionManager::check_profile_name(name)?;
+        Ok(TransactionManager {
+            db: self.db.clone(),
+            profile: Arc::new(Mutex::new(name.to_string())),
+            key: self.key.clone(),
+        })
+    }
 
-        Ok(transactions.filter_map(|t| t.ok()).collect())
+    /// Names of all account profiles, sorted
+    pub fn list_profiles(&self) -> Result<Vec<String>> {
+        let conn = self.db.read()?;
+        let mut stmt = conn.prepare("SELECT name FROM profiles ORDER BY name")?;
+        let names = stmt.query_map([], |row| row.get(0))?;
+        Ok(names.collect::<rusqlite::Result<_>>()?)
     }
 
-    pub fn fetch_filtered(&self, filter_opt: &FilterOptions) -> Result<Vec<Transaction>> {
-        let conn = self.conn.lock().unwrap();
+    fn check_profile_name(name: &str) -> Result<&str> {
+        let name = name.trim();
+        if name.is_empty() {
+            bail!("Profile name must not be empty");
+        }
+        Ok(name)
+    }
+
+    /// Columns of `transactions`, selected when loading [`Transaction`]s in the
+    /// order expected by [`TransactionManager::row_to_transaction`] and followed
+    /// by [`annotations::ANNOTATION_COLUMNS`]
+    ///
+    /// `time` is a Unix timestamp and `utc_offset` the offset in seconds the
+    /// transaction was recorded in.
+    const COLUMNS: &str =
+        "id, time, utc_offset, amount, merchant, kind, balance, terminal, tran_type, raw, hidden";
+
+    fn row_to_transaction(row: &rusqlite::Row) -> rusqlite::Result<Transaction> {
+        let timestamp: i64 = row.get(1)?;
+        let time = FixedOffset::east_opt(row.get(2)?)
+            .and_then(|offset| offset.timestamp_opt(timestamp, 0).single())
+            .ok_or(rusqlite::Error::IntegralValueOutOfRange(1, timestamp))?;
+        let id: i64 = row.get(0)?;
+        Ok(Transaction {
+            id,
+            time,
+            amount: row.get(3)?,
+            merchant: row.get(4)?,
+            kind: row.get(5)?,
+            balance: row.get(6)?,
+            terminal: row.get(7)?,
+            tran_type: row.get(8)?,
+            raw: row.get(9)?,
+            hidden: row.get(10)?,
+            note: row.get(11)?,
+            tags: serde_json::from_str(&row.get::<_, String>(12)?).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(12, rusqlite::types::Type::Text, e.into())
+            })?,
+            manual: id < 0,
+        })
+    }
 
-        let mut conditions = Vec::new();
-        let mut params = Vec::new();
+    /// Insert transactions into the active profile
+    ///
+    /// IDs are scoped to the profile with [`Transaction::scoped_id`]. The
+    /// batch is inserted atomically: transactions already stored are skipped,
+    /// and ones conflicting with a stored transaction of the same ID are
+    /// quarantined for review (see [`TransactionManager::list_conflicts`])
+    /// instead of failing the import.
+    pub fn insert(&self, transactions: &Vec<Transaction>) -> Result<InsertReport> {
+        let profile = self.profile();
+        let mut conn = self.db.write();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR IGNORE INTO profiles (name) VALUES (?)",
+            [&profile],
+        )?;
 
-        if let Some((start, end)) = &filter_opt.time {
-            conditions.push("time >= ? AND time < ?");
-            params.push(start.to_string());
-            params.push(end.to_string());
+        let mut report = InsertReport::default();
+        {
+            let mut stmt = TransactionManager::prepare_insert(&tx)?;
+            for transaction in transactions {
+                let id = Transaction::scoped_id(transaction.id, &profile);
+                match TransactionManager::insert_row(&mut stmt, id, transaction, &profile) {
+                    // the trigger skips identical rows without an error
+                    Ok(0) => report.duplicate += 1,
+                    Ok(_) => report.new += 1,
+                    Err(e) if conflicts::is_conflict(&e) => {
+                        // the trigger only aborted this row, the batch goes on
+                        let incoming = Transaction {
+                            id,
+                            ..transaction.clone()
+                        };
+                        conflicts::quarantine(&tx, &incoming, &profile)?;
+                        report.conflict += 1;
+                    }
+                    Err(e) => {
+                        return Err(e).with_context(|| {
+                            format!(
+                                "Error when inserting transactions into Database, transaction: {:?}",
+                                transaction
+                            )
+                        });
+                    }
+                }
+            }
         }
-
-        if let Some(merchant) = &filter_opt.merchant {
-            conditions.push("merchant = ?");
-            params.push(merchant.to_string());
+        tx.commit()?;
+        if report.conflict > 0 {
+            warn!(
+                "Quarantined {} transactions conflicting with stored ones",
+                report.conflict
+            );
         }
+        Ok(report)
+    }
 
-        if let Some((min, max)) = &filter_opt.amount {
-            conditions.push("amount >= ? AND amount < ?");
-            params.push(min.to_string());
-            params.push(max.to_string());
-        }
+    /// Statement inserting [`TransactionManager::COLUMNS`] and the profile,
+    /// executed by [`TransactionManager::insert_row`]
+    fn prepare_insert(conn: &Connection) -> rusqlite::Result<rusqlite::Statement<'_>> {
+        conn.prepare(&format!(
+            "INSERT INTO transactions ({}, profile) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            TransactionManager::COLUMNS
+        ))
+    }
 
-        let where_clause = if conditions.is_empty() {
-            String::new()
-        } else {
-            format!("WHERE {}", conditions.join(" AND "))
-        };
+    fn insert_row(
+        stmt: &mut rusqlite::Statement,
+        id: i64,
+        transaction: &Transaction,
+        profile: &str,
+    ) -> rusqlite::Result<usize> {
+        stmt.execute(params![
+            id,
+            transaction.time.timestamp(),
+            transaction.time.offset().local_minus_utc(),
+            transaction.amount,
+            transaction.merchant,
+            transaction.kind,
+            transaction.balance,
+            transaction.terminal,
+            transaction.tran_type,
+            transaction.raw,
+            transaction.hidden,
+            profile
+        ])
+    }
 
-        let query = format!(
-            "SELECT id, time, amount, merchant FROM transactions {}",
-            where_clause
+    /// Fetch all transactions of the active profile from the database, newest first
+    pub fn fetch_all(&self) -> Result<Vec<Transaction>> {
+        self.fetch_filtered(&FilterOptions::default())
+    }
+
+    /// Fetch transactions matching the filter
+    ///
+    /// Scoped to [`FilterOptions::profile`] if set, and to the active profile otherwise.
+    /// Ordered by [`FilterOptions::sort`], newest first if not set, and paginated
+    /// with [`FilterOptions::limit`] and [`FilterOptions::offset`].
+    pub fn fetch_filtered(&self, filter_opt: &FilterOptions) -> Result<Vec<Transaction>> {
+        let (where_clause, params) = self.where_clause(filter_opt);
+
+        let (key, order) = filter_opt.sort.unwrap_or_default();
+        let mut query = format!(
+            "SELECT {}, {} FROM transactions {} ORDER BY {} {}, id",
+            TransactionManager::COLUMNS,
+            annotations::ANNOTATION_COLUMNS,
+            where_clause,
+            key.column(),
+            order.as_sql()
         );
+        if filter_opt.limit.is_some() || filter_opt.offset.is_some() {
+            // SQLite only accepts OFFSET after LIMIT, where -1 means no limit
+            query.push_str(&format!(
+                " LIMIT {} OFFSET {}",
+                filter_opt.limit.map_or(-1, |l| l as i64),
+                filter_opt.offset.unwrap_or(0)
+            ));
+        }
 
+        let conn = self.db.read()?;
         let mut stmt = conn.prepare(&query)?;
 
-        let param_refs: Vec<&dyn rusqlite::ToSql> =
-            params.iter().map(|p| p as &dyn rusqlite::ToSql).collect();
-
-        let transactions = stmt.query_map(rusqlite::params_from_iter(param_refs), |row| {
-            Ok(Transaction {
-                id: row.get(0)?,
-                time: row.get(1)?,
-                amount: row.get(2)?,
-                merchant: row.get(3)?,
-            })
-        })?;
+        let transactions = stmt.query_map(
+            rusqlite::params_from_iter(params.iter()),
+            TransactionManager::row_to_transaction,
+        )?;
 
         Ok(transactions.filter_map(|t| t.ok()).collect())
     }
 
-    pub fn fetch_count(&self) -> Result<u64> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT COUNT(*) FROM transactions")?;
-        let count: i64 = stmt.query_row([], |row| row.get(0))?;
+    /// Number of transactions matching the filter, ignoring sorting and pagination
+    pub fn fetch_filtered_count(&self, filter_opt: &FilterOptions) -> Result<u64> {
+        let (where_clause, params) = self.where_clause(filter_opt);
+        let conn = self.db.read()?;
+        let count: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM transactions {}", where_clause),
+            rusqlite::params_from_iter(params.iter()),
+            |row| row.get(0),
+        )?;
         Ok(count as u64)
     }
 
+    /// Build the `WHERE` clause selecting the transactions matching the filter
+    fn where_clause(&self, filter_opt: &FilterOptions) -> (String, Vec<Box<dyn ToSql>>) {
+        let profile = filter_opt.profile.clone().unwrap_or_else(|| self.profile());
+
+        let mut conditions = vec!["profile = ?".to_string()];
+        let mut params: Vec<Box<dyn ToSql>> = vec![Box::new(profile)];
+        if !filter_opt.include_hidden {
+            conditions.push("hidden = 0".to_string());
+        }
+        filter_opt.push_conditions(&mut conditions, &mut params);
+
+        (format!("WHERE {}", conditions.join(" AND ")), params)
+    }
+
+    /// Number of transactions in the active profile
+    pub fn fetch_count(&self) -> Result<u64> {
+        self.fetch_filtered_count(&FilterOptions::default())
+    }
+
+    /// Delete all transactions of the active profile
     #[allow(dead_code)]
     pub fn clear_db(&self) -> Result<(), rusqlite::Error> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute("DELETE FROM transactions", [])?;
+        let profile = self.profile();
+        let conn = self.db.write();
+        conn.execute("DELETE FROM transactions WHERE profile = ?", [&profile])?;
+        conn.execute("DELETE FROM sync_state WHERE profile = ?", [&profile])?;
+        conn.execute(
+            "DELETE FROM fetch_checkpoints WHERE profile = ?",
+            [&profile],
+        )?;
         Ok(())
     }
 
-    /// Update account(optional) and cookie in cookies table
-    ///
-    /// If there is already a record, update it. Otherwise, insert a new record.
-    /// There should always be only one records in cookies table
+    /// Update the account of the active profile, keeping its cookie
     pub fn update_account(&self, account: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-
-        // Check if there are existing records
-        let existing = conn.query_row("SELECT cookie FROM cookies LIMIT 1", [], |row| {
-            row.get::<_, String>(0)
-        });
-
-        // Determine account value to use
-        let cookie = existing.unwrap_or_default();
-
-        // Replace the record
-        conn.execute("DELETE FROM cookies", [])?;
-        let mut stmt = conn.prepare("INSERT INTO cookies (account, cookie) VALUES (?, ?)")?;
-        stmt.execute(params![account, cookie])?;
+        let profile = self.profile();
+        let conn = self.db.write();
+        conn.execute(
+            "INSERT INTO profiles (name, account) VALUES (?, ?)
+            ON CONFLICT(name) DO UPDATE SET account = excluded.account",
+            params![profile, self.key.encrypt(account)],
+        )?;
         Ok(())
     }
 
+    /// Update the cookie of the active profile, keeping its account
     pub fn update_cookie(&self, cookie: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-
-        // Check if there are existing records
-        let existing = conn.query_row("SELECT account FROM cookies LIMIT 1", [], |row| {
-            row.get::<_, String>(0)
-        });
-
-        // Determine account value to use
-        let account = existing.unwrap_or_default();
-
-        // Replace the record
-        conn.execute("DELETE FROM cookies", [])?;
-        let mut stmt = conn.prepare("INSERT INTO cookies (account, cookie) VALUES (?, ?)")?;
-        stmt.execute(params![account, cookie])?;
+        let profile = self.profile();
+        let conn = self.db.write();
+        conn.execute(
+            "INSERT INTO profiles (name, cookie) VALUES (?, ?)
+            ON CONFLICT(name) DO UPDATE SET cookie = excluded.cookie",
+            params![profile, self.key.encrypt(cookie)],
+        )?;
         Ok(())
     }
 
@@ -285,16 +678,20 @@ impl TransactionManager {
         Ok((account, cookie))
     }
 
+    /// Account and cookie of the active profile, decrypted
+    ///
+    /// Mask them with [`mask_secret`] and [`mask_cookie`] before displaying.
     pub fn get_account_cookie_may_empty(&self) -> Result<(String, String)> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT account, cookie FROM cookies")?;
-        let mut rows = stmt.query([])?;
+        let profile = self.profile();
+        let conn = self.db.read()?;
+        let mut stmt = conn.prepare("SELECT account, cookie FROM profiles WHERE name = ?")?;
+        let mut rows = stmt.query([profile])?;
         let row = rows.next()?;
         match row {
             Some(row) => {
                 let account: String = row.get(0)?;
                 let cookie: String = row.get(1)?;
-                Ok((account, cookie))
+                Ok((self.key.decrypt(&account)?, self.key.decrypt(&cookie)?))
             }
             None => bail!("No account and cookie found"),
         }
@@ -308,11 +705,206 @@ pub struct FilterOptions {
     pub time: Option<(DateTime<FixedOffset>, DateTime<FixedOffset>)>, // Made pub
     /// Merchant name
     pub merchant: Option<String>, // Made pub
-    /// Amount range, closed on left, open on right
-    pub amount: Option<(f64, f64)>, // Made pub
+    /// How `merchant` is matched, the whole name by default
+    #[serde(default)]
+    pub merchant_match: MerchantMatch,
+    /// Only include transactions of one of these merchants, matched exactly
+    pub merchants: Option<Vec<String>>,
+    /// Leave out transactions of these merchants, matched exactly
+    pub exclude_merchants: Option<Vec<String>>,
+    /// Only include merchants of one of these categories
+    pub categories: Option<Vec<MerchantType>>,
+    /// Amount range, closed on left, open on right, a missing bound leaves
+    /// that side open
+    pub amount: Option<(Option<Money>, Option<Money>)>, // Made pub
+    /// Only include transactions of these kinds
+    pub kinds: Option<Vec<TransactionKind>>,
+    /// Only include transactions made on these days of the week
+    pub weekdays: Option<Vec<Weekday>>,
+    /// Only include transactions whose local time of day falls in one of these
+    /// windows, closed on left, open on right
+    ///
+    /// A window ending before it starts wraps past midnight.
+    pub time_of_day: Option<Vec<(NaiveTime, NaiveTime)>>,
+    /// Only include transactions with at least one of these tags
+    pub tags: Option<Vec<String>>,
+    /// Only include transactions whose note contains this text
+    pub note: Option<String>,
+    /// Only include transactions matching at least one of these filters,
+    /// on top of the other conditions
+    ///
+    /// Profile, hidden transactions, sorting and pagination of the inner
+    /// filters are ignored.
+    pub any_of: Option<Vec<FilterOptions>>,
+    /// Also include hidden transactions, which are left out by default
+    #[serde(default)]
+    pub include_hidden: bool,
+    /// Account profile to query instead of the active one
+    pub profile: Option<String>,
+    /// Sort order, newest first if not set
+    pub sort: Option<(SortKey, SortOrder)>,
+    /// Maximum number of transactions to return
+    pub limit: Option<u64>,
+    /// Number of transactions to skip, for paging through results with `limit`
+    pub offset: Option<u64>,
+}
+
+/// Column to sort transactions by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    Time,
+    Amount,
+    Merchant,
+}
+
+impl SortKey {
+    fn column(&self) -> &'static str {
+        match self {
+            SortKey::Time => "time",
+            SortKey::Amount => "amount",
+            SortKey::Merchant => "merchant",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl SortOrder {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
 }
 
 impl FilterOptions {
+    /// Append the SQL conditions of this filter, except the profile, to be joined with `AND`
+    fn push_conditions(&self, conditions: &mut Vec<String>, params: &mut Vec<Box<dyn ToSql>>) {
+        if let Some((start, end)) = &self.time {
+            conditions.push("time >= ? AND time < ?".to_string());
+            params.push(Box::new(start.timestamp()));
+            params.push(Box::new(end.timestamp()));
+        }
+
+        if let Some(merchant) = &self.merchant {
+            let (condition, param) = self.merchant_match.condition(merchant);
+            conditions.push(condition.to_string());
+            params.push(param);
+        }
+
+        if let Some(merchants) = &self.merchants {
+            conditions.push("merchant IN (SELECT value FROM json_each(?))".to_string());
+            params.push(Box::new(json_array(merchants)));
+        }
+
+        if let Some(merchants) = &self.exclude_merchants {
+            conditions.push("merchant NOT IN (SELECT value FROM json_each(?))".to_string());
+            params.push(Box::new(json_array(merchants)));
+        }
+
+        if let Some(categories) = &self.categories {
+            let names: Vec<_> = categories.iter().flat_map(|c| c.merchant_names()).collect();
+            if categories.contains(&MerchantType::Unknown) {
+                conditions.push(
+                    "(merchant IN (SELECT value FROM json_each(?)) \
+                    OR merchant NOT IN (SELECT value FROM json_each(?)))"
+                        .to_string(),
+                );
+                params.push(Box::new(json_array(&names)));
+                params.push(Box::new(json_array(&MerchantType::classified_merchants())));
+            } else {
+                conditions.push("merchant IN (SELECT value FROM json_each(?))".to_string());
+                params.push(Box::new(json_array(&names)));
+            }
+        }
+
+        if let Some((min, max)) = &self.amount {
+            if let Some(min) = min {
+                conditions.push("amount >= ?".to_string());
+                params.push(Box::new(*min));
+            }
+            if let Some(max) = max {
+                conditions.push("amount < ?".to_string());
+                params.push(Box::new(*max));
+            }
+        }
+
+        if let Some(kinds) = &self.kinds {
+            conditions.push(format!("kind IN ({})", vec!["?"; kinds.len()].join(", ")));
+            params.extend(kinds.iter().map(|k| Box::new(*k) as Box<dyn ToSql>));
+        }
+
+        if let Some(weekdays) = &self.weekdays {
+            // in local time, and %w counts from Sunday
+            conditions.push(
+                "CAST(strftime('%w', time + utc_offset, 'unixepoch') AS INTEGER) \
+                IN (SELECT value FROM json_each(?))"
+                    .to_string(),
+            );
+            let days: Vec<_> = weekdays.iter().map(|d| d.num_days_from_sunday()).collect();
+            params.push(Box::new(json_array(&days)));
+        }
+
+        if let Some(windows) = &self.time_of_day {
+            let windows: Vec<_> = windows
+                .iter()
+                .map(|(start, end)| {
+                    params.push(Box::new(start.format("%H:%M:%S%.f").to_string()));
+                    params.push(Box::new(end.format("%H:%M:%S%.f").to_string()));
+                    if start <= end {
+                        "(time(time + utc_offset, 'unixepoch') >= ? \
+                        AND time(time + utc_offset, 'unixepoch') < ?)"
+                    } else {
+                        "(time(time + utc_offset, 'unixepoch') >= ? \
+                        OR time(time + utc_offset, 'unixepoch') < ?)"
+                    }
+                })
+                .collect();
+            conditions.push(format!("({})", or_all(&windows)));
+        }
+
+        if let Some(tags) = &self.tags {
+            conditions.push(
+                "id IN (SELECT transaction_id FROM transaction_tags \
+                WHERE tag IN (SELECT value FROM json_each(?)))"
+                    .to_string(),
+            );
+            params.push(Box::new(json_array(tags)));
+        }
+
+        if let Some(note) = &self.note {
+            conditions.push(
+                "id IN (SELECT transaction_id FROM transaction_notes WHERE instr(note, ?) > 0)"
+                    .to_string(),
+            );
+            params.push(Box::new(note.clone()));
+        }
+
+        if let Some(any_of) = &self.any_of {
+            let groups: Vec<_> = any_of
+                .iter()
+                .map(|filter| {
+                    let mut group = Vec::new();
+                    filter.push_conditions(&mut group, params);
+                    match group.is_empty() {
+                        true => "1".to_string(),
+                        false => format!("({})", group.join(" AND ")),
+                    }
+                })
+                .collect();
+            conditions.push(format!("({})", or_all(&groups)));
+        }
+    }
+
     #[allow(dead_code)]
     pub fn start(mut self, start: DateTime<FixedOffset>) -> Self {
         // Made pub
@@ -341,27 +933,106 @@ impl FilterOptions {
         });
         self
     }
-    pub fn merchant<T: Into<String>>(mut self, merchant: T) -> Self {
+    /// Only include transactions of exactly this merchant
+    pub fn merchant<T: Into<String>>(self, merchant: T) -> Self {
         // Made pub
-        self.merchant = Some(merchant.into());
+        self.search_merchant(merchant, MerchantMatch::Exact)
+    }
+    /// Only include transactions whose merchant matches the query
+    pub fn search_merchant<T: Into<String>>(mut self, query: T, how: MerchantMatch) -> Self {
+        self.merchant = Some(query.into());
+        self.merchant_match = how;
+        self
+    }
+    /// Only include transactions of the given kind
+    ///
+    /// Can be called multiple times to allow several kinds.
+    pub fn kind(mut self, kind: TransactionKind) -> Self {
+        self.kinds.get_or_insert_with(Vec::new).push(kind);
+        self
+    }
+    /// Also include transactions of this merchant, matched exactly
+    pub fn or_merchant<T: Into<String>>(mut self, merchant: T) -> Self {
+        self.merchants
+            .get_or_insert_with(Vec::new)
+            .push(merchant.into());
+        self
+    }
+    /// Leave out transactions of this merchant, matched exactly
+    pub fn exclude_merchant<T: Into<String>>(mut self, merchant: T) -> Self {
+        self.exclude_merchants
+            .get_or_insert_with(Vec::new)
+            .push(merchant.into());
+        self
+    }
+    /// Also include merchants of the given category
+    pub fn category(mut self, category: MerchantType) -> Self {
+        self.categories.get_or_insert_with(Vec::new).push(category);
+        self
+    }
+    /// Also include transactions made on the given day of the week
+    pub fn weekday(mut self, weekday: Weekday) -> Self {
+        self.weekdays.get_or_insert_with(Vec::new).push(weekday);
+        self
+    }
+    /// Also include transactions in the given time-of-day window
+    pub fn time_of_day(mut self, start: NaiveTime, end: NaiveTime) -> Self {
+        self.time_of_day
+            .get_or_insert_with(Vec::new)
+            .push((start, end));
+        self
+    }
+    /// Also include transactions with the given tag
+    pub fn tag<T: Into<String>>(mut self, tag: T) -> Self {
+        self.tags.get_or_insert_with(Vec::new).push(tag.into());
+        self
+    }
+    /// Only include transactions whose note contains the given text
+    pub fn note_contains<T: Into<String>>(mut self, text: T) -> Self {
+        self.note = Some(text.into());
+        self
+    }
+    /// Also include transactions matching the given filter in the OR-group
+    pub fn or(mut self, filter: FilterOptions) -> Self {
+        self.any_of.get_or_insert_with(Vec::new).push(filter);
+        self
+    }
+    /// Also include hidden transactions
+    pub fn include_hidden(mut self) -> Self {
+        self.include_hidden = true;
+        self
+    }
+    /// Sort the results, ties are broken by ID
+    pub fn sort(mut self, key: SortKey, order: SortOrder) -> Self {
+        self.sort = Some((key, order));
+        self
+    }
+    /// Return at most `limit` transactions
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
         self
     }
+    /// Skip the first `offset` transactions
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+    /// Only include expenses, which is what meal analysis is about
+    pub fn expenses_only(self) -> Self {
+        self.kind(TransactionKind::Expense)
+    }
     #[allow(dead_code)]
-    pub fn min(mut self, amount: f64) -> Self {
+    pub fn min(mut self, amount: Money) -> Self {
         // Made pub
-        self.amount = Some(match self.amount {
-            Some((_, max)) => (amount, max),
-            None => (amount, f64::INFINITY), // Use a safe default for the maximum value
-        });
+        let max = self.amount.and_then(|(_, max)| max);
+        self.amount = Some((Some(amount), max));
         self
     }
     #[allow(dead_code)]
-    pub fn max(mut self, amount: f64) -> Self {
+    pub fn max(mut self, amount: Money) -> Self {
         // Made pub
-        self.amount = Some(match self.amount {
-            Some((min, _)) => (min, amount),
-            None => (f64::NEG_INFINITY, amount), // Use a safe default for the minimum value
-        });
+        let min = self.amount.and_then(|(min, _)| min);
+        self.amount = Some((min, Some(amount)));
         self
     }
 }
@@ -373,10 +1044,64 @@ impl std::fmt::Display for FilterOptions {
             result.push_str(&format!("Time: {} - {}\n", start, end));
         }
         if let Some(merchant) = &self.merchant {
-            result.push_str(&format!("Merchant: {}\n", merchant));
+            match self.merchant_match {
+                MerchantMatch::Exact => result.push_str(&format!("Merchant: {}\n", merchant)),
+                m => result.push_str(&format!("Merchant: {} ({})\n", merchant, m)),
+            }
+        }
+        if let Some(merchants) = &self.merchants {
+            result.push_str(&format!("Merchants: {}\n", merchants.join(", ")));
+        }
+        if let Some(merchants) = &self.exclude_merchants {
+            result.push_str(&format!("Excluding: {}\n", merchants.join(", ")));
+        }
+        if let Some(categories) = &self.categories {
+            result.push_str(&format!("Category: {}\n", join(categories)));
         }
         if let Some((min, max)) = &self.amount {
-            result.push_str(&format!("Amount: {} - {}\n", min, max));
+            let bound = |b: &Option<Money>| b.map(|m| m.to_string()).unwrap_or_default();
+            result.push_str(&format!("Amount: {} - {}\n", bound(min), bound(max)));
+        }
+        if let Some(kinds) = &self.kinds {
+            result.push_str(&format!(
+                "Kind: {}\n",
+                kinds
+                    .iter()
+                    .map(|k| k.label())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        if let Some(weekdays) = &self.weekdays {
+            result.push_str(&format!("Weekday: {}\n", join(weekdays)));
+        }
+        if let Some(windows) = &self.time_of_day {
+            let windows: Vec<_> = windows
+                .iter()
+                .map(|(start, end)| format!("{} - {}", start.format("%H:%M"), end.format("%H:%M")))
+                .collect();
+            result.push_str(&format!("Time of day: {}\n", windows.join(", ")));
+        }
+        if let Some(tags) = &self.tags {
+            result.push_str(&format!("Tag: {}\n", tags.join(", ")));
+        }
+        if let Some(note) = &self.note {
+            result.push_str(&format!("Note: {}\n", note));
+        }
+        if let Some(any_of) = &self.any_of {
+            for filter in any_of {
+                let inner = filter.to_string();
+                result.push_str(&format!("Or: {}\n", inner.trim_end().replace('\n', "; ")));
+            }
+        }
+        if self.include_hidden {
+            result.push_str("Including hidden\n");
+        }
+        if let Some(profile) = &self.profile {
+            result.push_str(&format!("Profile: {}\n", profile));
+        }
+        if let Some((key, order)) = &self.sort {
+            result.push_str(&format!("Sort: {} {}\n", key.column(), order.as_sql()));
         }
         if result.is_empty() {
             result.push_str("No filters applied\n");
@@ -385,6 +1110,31 @@ impl std::fmt::Display for FilterOptions {
     }
 }
 
+/// JSON array parameter for `json_each(?)`, which keeps the SQL the same for any length
+fn json_array<T: Serialize>(values: &[T]) -> String {
+    serde_json::to_string(values).expect("Failed to serialize filter values")
+}
+
+/// Conditions joined with `OR`, false if there are none
+fn or_all<T: AsRef<str>>(conditions: &[T]) -> String {
+    match conditions.is_empty() {
+        true => "0".to_string(),
+        false => conditions
+            .iter()
+            .map(|c| c.as_ref())
+            .collect::<Vec<_>>()
+            .join(" OR "),
+    }
+}
+
+fn join<T: std::fmt::Display>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::TimeZone;
@@ -409,11 +1159,82 @@ mod tests {
         let time = OFFSET_UTC_PLUS8
             .with_ymd_and_hms(2025, 3, 1, 0, 0, 0)
             .unwrap();
-        let transaction = Transaction::new(-100.0, "Amazon".to_string(), time);
-        assert_eq!(transaction.amount, -100.0);
+        let transaction = Transaction::new(Money::from_yuan(-100.0), "Amazon".to_string(), time);
+        assert_eq!(transaction.amount, Money::from_yuan(-100.0));
         assert_eq!(transaction.merchant, "Amazon");
         assert_eq!(transaction.time, time);
-        assert_eq!(transaction.id, 2865793625909541060);
+        assert_eq!(
+            transaction.id,
+            Transaction::stable_id(time.timestamp(), -10000, "Amazon", 0)
+        );
+        assert!(transaction.id >= 0);
+    }
+
+    #[test]
+    fn stable_id_is_fnv1a() {
+        // FNV-1a reference values for "v1|0|0||0" are fixed forever
+        assert_eq!(Transaction::stable_id(0, 0, "", 0), 0x02f8_12de_a664_8f90);
+        assert_eq!(
+            Transaction::stable_id(1740758400, -1872, "寿司", 0),
+            Transaction::stable_id(1740758400, -1872, "寿司", 0)
+        );
+        assert_ne!(
+            Transaction::stable_id(1740758400, -1872, "寿司", 0),
+            Transaction::stable_id(1740758400, -1872, "寿司", 1)
+        );
+    }
+
+    #[test]
+    fn identical_purchases_are_kept() {
+        let manager = TransactionManager::new(None).unwrap();
+        let time = OFFSET_UTC_PLUS8
+            .with_ymd_and_hms(2025, 3, 1, 12, 0, 0)
+            .unwrap();
+        let mut transactions = vec![
+            Transaction::new(Money::from_yuan(-1.5), "西14西15东12浴室".to_string(), time),
+            Transaction::new(Money::from_yuan(-1.5), "西14西15东12浴室".to_string(), time),
+            Transaction::new(Money::from_yuan(-2.0), "西14西15东12浴室".to_string(), time),
+        ];
+        IdSequencer::default().assign(&mut transactions);
+        assert_ne!(transactions[0].id, transactions[1].id);
+
+        // the same purchases split across two pages get the same IDs
+        let mut pages = transactions.clone();
+        let mut sequencer = IdSequencer::default();
+        sequencer.assign(&mut pages[..1]);
+        sequencer.assign(&mut pages[1..]);
+        assert!(pages.iter().zip(&transactions).all(|(a, b)| a.id == b.id));
+        // and so do they when the second page is assigned in a later fetch
+        let mut pages = transactions.clone();
+        let mut sequencer = IdSequencer::default();
+        sequencer.assign(&mut pages[..1]);
+        let counters = sequencer.counters(&pages[..1]);
+        IdSequencer::resume(&counters).assign(&mut pages[1..]);
+        assert!(pages.iter().zip(&transactions).all(|(a, b)| a.id == b.id));
+        assert_eq!(
+            transactions[2].id,
+            Transaction::new(Money::from_yuan(-2.0), "西14西15东12浴室".to_string(), time).id
+        );
+
+        let report = manager.insert(&transactions).unwrap();
+        assert_eq!(
+            report,
+            InsertReport {
+                new: 3,
+                ..Default::default()
+            }
+        );
+        // inserting the same batch again is a no-op
+        let report = manager.insert(&transactions).unwrap();
+        assert_eq!(
+            report,
+            InsertReport {
+                duplicate: 3,
+                ..Default::default()
+            }
+        );
+        assert_eq!(report.to_string(), "0 new, 3 already stored, 0 conflicting");
+        assert_eq!(manager.fetch_count().unwrap(), 3);
     }
 
     #[test]
@@ -428,16 +1249,18 @@ mod tests {
                 time: OFFSET_UTC_PLUS8
                     .with_ymd_and_hms(2025, 3, 1, 0, 0, 0)
                     .unwrap(),
-                amount: -100.0,
+                amount: Money::from_yuan(-100.0),
                 merchant: "Amazon".to_string(),
+                ..Default::default()
             },
             Transaction {
                 id: 2,
                 time: OFFSET_UTC_PLUS8
                     .with_ymd_and_hms(2025, 3, 1, 0, 0, 0)
                     .unwrap(),
-                amount: -200.0,
+                amount: Money::from_yuan(-200.0),
                 merchant: "Google".to_string(),
+                ..Default::default()
             },
         ];
 
@@ -446,11 +1269,11 @@ mod tests {
         let fetched = manager.fetch_all().unwrap();
         assert_eq!(fetched.len(), 2);
         assert_eq!(fetched[0].id, 1);
-        assert_eq!(fetched[0].amount, -100.0);
+        assert_eq!(fetched[0].amount, Money::from_yuan(-100.0));
         assert_eq!(fetched[0].merchant, "Amazon");
 
         assert_eq!(fetched[1].id, 2);
-        assert_eq!(fetched[1].amount, -200.0);
+        assert_eq!(fetched[1].amount, Money::from_yuan(-200.0));
         assert_eq!(fetched[1].merchant, "Google");
     }
 
@@ -474,6 +1297,34 @@ mod tests {
         assert_eq!(cookie, "test_cookie");
     }
 
+    #[test]
+    fn test_lost_key_file() {
+        use crate::libs::fetcher::test_utils::get_mock_data;
+
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("transactions.db");
+        let manager = TransactionManager::new(Some(db_path.clone())).unwrap();
+        manager.insert(&get_mock_data(5)).unwrap();
+        manager.update_account("test_account").unwrap();
+        manager.update_cookie("test_cookie").unwrap();
+        drop(manager);
+
+        std::fs::remove_file(db_path.with_extension("key")).unwrap();
+        let manager = TransactionManager::new(Some(db_path.clone())).unwrap();
+        assert_eq!(manager.fetch_count().unwrap(), 5);
+        let (account, cookie) = manager.get_account_cookie_may_empty().unwrap();
+        assert_eq!((account.as_str(), cookie.as_str()), ("", ""));
+
+        // new credentials are sealed with the new key file and survive a reopen
+        manager.update_cookie("new_cookie").unwrap();
+        drop(manager);
+        let manager = TransactionManager::new(Some(db_path)).unwrap();
+        assert_eq!(
+            manager.get_account_cookie_may_empty().unwrap().1,
+            "new_cookie"
+        );
+    }
+
     #[test]
     fn test_fetch_count() {
         let manager = TransactionManager::new(None).unwrap();
@@ -490,16 +1341,18 @@ mod tests {
                 time: OFFSET_UTC_PLUS8
                     .with_ymd_and_hms(2025, 3, 1, 0, 0, 0)
                     .unwrap(),
-                amount: -100.0,
+                amount: Money::from_yuan(-100.0),
                 merchant: "Amazon".to_string(),
+                ..Default::default()
             },
             Transaction {
                 id: 2,
                 time: OFFSET_UTC_PLUS8
                     .with_ymd_and_hms(2025, 3, 1, 0, 0, 0)
                     .unwrap(),
-                amount: -200.0,
+                amount: Money::from_yuan(-200.0),
                 merchant: "Google".to_string(),
+                ..Default::default()
             },
         ];
 
@@ -515,8 +1368,9 @@ mod tests {
             time: OFFSET_UTC_PLUS8
                 .with_ymd_and_hms(2025, 3, 1, 0, 0, 0)
                 .unwrap(),
-            amount: -300.0,
+            amount: Money::from_yuan(-300.0),
             merchant: "Apple".to_string(),
+            ..Default::default()
         }];
 
         manager.insert(&more_transactions).unwrap();
@@ -543,16 +1397,18 @@ mod tests {
                 time: OFFSET_UTC_PLUS8
                     .with_ymd_and_hms(2025, 3, 1, 0, 0, 0)
                     .unwrap(),
-                amount: -100.0,
+                amount: Money::from_yuan(-100.0),
                 merchant: "Amazon".to_string(),
+                ..Default::default()
             },
             Transaction {
                 id: 2,
                 time: OFFSET_UTC_PLUS8
                     .with_ymd_and_hms(2025, 3, 1, 0, 0, 0)
                     .unwrap(),
-                amount: -200.0,
+                amount: Money::from_yuan(-200.0),
                 merchant: "Google".to_string(),
+                ..Default::default()
             },
         ];
 
@@ -576,21 +1432,21 @@ mod tests {
         // Insert test data
         let transactions = vec![
             Transaction::new(
-                -100.0,
+                Money::from_yuan(-100.0),
                 "Amazon".to_string(),
                 OFFSET_UTC_PLUS8
                     .with_ymd_and_hms(2025, 3, 1, 0, 0, 0)
                     .unwrap(),
             ),
             Transaction::new(
-                -200.0,
+                Money::from_yuan(-200.0),
                 "Google".to_string(),
                 OFFSET_UTC_PLUS8
                     .with_ymd_and_hms(2025, 3, 2, 0, 0, 0)
                     .unwrap(),
             ),
             Transaction::new(
-                -300.0,
+                Money::from_yuan(-300.0),
                 "Amazon".to_string(),
                 OFFSET_UTC_PLUS8
                     .with_ymd_and_hms(2025, 3, 3, 0, 0, 0)
@@ -606,10 +1462,30 @@ mod tests {
         assert!(results.iter().all(|t| t.merchant == "Amazon"));
 
         // Test filtering by amount range
-        let filter = FilterOptions::default().min(-250.0).max(-100.0);
+        let filter = FilterOptions::default()
+            .min(Money::from_yuan(-250.0))
+            .max(Money::from_yuan(-100.0));
         let results = manager.fetch_filtered(&filter).unwrap();
         assert_eq!(results.len(), 1);
-        assert_eq!(results[0].amount, -200.0);
+        assert_eq!(results[0].amount, Money::from_yuan(-200.0));
+
+        // The lower bound is inclusive, down to the cent
+        let filter = FilterOptions::default()
+            .min(Money::from_yuan(-300.0))
+            .max(Money::from_yuan(-299.99));
+        let results = manager.fetch_filtered(&filter).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].amount, Money::from_cents(-30000));
+
+        // A single bound leaves the other side open
+        let filter = FilterOptions::default().max(Money::from_yuan(-250.0));
+        let results = manager.fetch_filtered(&filter).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].amount, Money::from_cents(-30000));
+        let filter = FilterOptions::default().min(Money::from_yuan(-250.0));
+        let results = manager.fetch_filtered(&filter).unwrap();
+        assert!(!results.is_empty());
+        assert!(results.iter().all(|t| t.amount >= Money::from_yuan(-250.0)));
 
         // Test filtering by time range
         let filter = FilterOptions::default()
@@ -627,4 +1503,361 @@ mod tests {
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].merchant, "Amazon");
     }
+
+    #[test]
+    fn test_sort_and_paginate() {
+        let manager = TransactionManager::new(None).unwrap();
+        let day = |d| {
+            OFFSET_UTC_PLUS8
+                .with_ymd_and_hms(2025, 3, d, 0, 0, 0)
+                .unwrap()
+        };
+        manager
+            .insert(&vec![
+                Transaction::new(Money::from_yuan(-3.0), "B".to_string(), day(1)),
+                Transaction::new(Money::from_yuan(-1.0), "C".to_string(), day(3)),
+                Transaction::new(Money::from_yuan(-2.0), "A".to_string(), day(2)),
+            ])
+            .unwrap();
+
+        let merchants = |filter: FilterOptions| {
+            manager
+                .fetch_filtered(&filter)
+                .unwrap()
+                .into_iter()
+                .map(|t| t.merchant)
+                .collect::<Vec<_>>()
+        };
+
+        // newest first by default
+        assert_eq!(merchants(FilterOptions::default()), ["C", "A", "B"]);
+        assert_eq!(
+            merchants(FilterOptions::default().sort(SortKey::Time, SortOrder::Asc)),
+            ["B", "A", "C"]
+        );
+        assert_eq!(
+            merchants(FilterOptions::default().sort(SortKey::Amount, SortOrder::Asc)),
+            ["B", "A", "C"]
+        );
+        assert_eq!(
+            merchants(FilterOptions::default().sort(SortKey::Merchant, SortOrder::Desc)),
+            ["C", "B", "A"]
+        );
+
+        assert_eq!(merchants(FilterOptions::default().limit(2)), ["C", "A"]);
+        assert_eq!(
+            merchants(FilterOptions::default().limit(2).offset(2)),
+            ["B"]
+        );
+        assert_eq!(merchants(FilterOptions::default().offset(1)), ["A", "B"]);
+
+        // counting ignores pagination
+        assert_eq!(
+            manager
+                .fetch_filtered_count(&FilterOptions::default().limit(1))
+                .unwrap(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_profiles() {
+        let manager = TransactionManager::new(None).unwrap();
+        let time = OFFSET_UTC_PLUS8
+            .with_ymd_and_hms(2025, 3, 1, 12, 0, 0)
+            .unwrap();
+        let purchase = vec![Transaction::new(
+            Money::from_yuan(-12.0),
+            "寿司".to_string(),
+            time,
+        )];
+        manager.update_account("123456").unwrap();
+        manager.insert(&purchase).unwrap();
+
+        // the same purchase on another card is kept apart
+        let roommate = manager.clone();
+        roommate.switch_profile("roommate").unwrap();
+        assert_eq!(manager.profile(), "roommate");
+        assert_eq!(roommate.fetch_count().unwrap(), 0);
+        roommate.update_account("654321").unwrap();
+        roommate.insert(&purchase).unwrap();
+        assert_eq!(roommate.fetch_count().unwrap(), 1);
+        assert_ne!(roommate.fetch_all().unwrap()[0].id, purchase[0].id);
+        assert_eq!(roommate.get_account_cookie_may_empty().unwrap().0, "654321");
+
+        // a scoped manager leaves the active profile alone
+        let default = manager.for_profile("default").unwrap();
+        assert_eq!(manager.profile(), "roommate");
+        assert_eq!(default.fetch_all().unwrap()[0].id, purchase[0].id);
+        assert_eq!(default.get_account_cookie_may_empty().unwrap().0, "123456");
+
+        let results = manager
+            .fetch_filtered(&FilterOptions {
+                profile: Some("default".into()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(results[0].id, purchase[0].id);
+
+        assert_eq!(
+            manager.list_profiles().unwrap(),
+            vec!["default", "roommate"]
+        );
+        assert!(manager.switch_profile(" ").is_err());
+    }
+
+    #[test]
+    fn test_search_merchant() {
+        let manager = TransactionManager::new(None).unwrap();
+        let time = OFFSET_UTC_PLUS8
+            .with_ymd_and_hms(2025, 3, 1, 0, 0, 0)
+            .unwrap();
+        manager
+            .insert(&vec![
+                Transaction::new(Money::from_yuan(-12.0), "梧桐苑食堂面食".to_string(), time),
+                Transaction::new(Money::from_yuan(-8.0), "康桥苑一楼面食".to_string(), time),
+                Transaction::new(Money::from_yuan(-5.0), "梧桐苑超市".to_string(), time),
+                Transaction::new(Money::from_yuan(-3.0), "西14西15东12浴室".to_string(), time),
+            ])
+            .unwrap();
+
+        let merchants = |query: &str, how| {
+            let mut names: Vec<_> = manager
+                .fetch_filtered(&FilterOptions::default().search_merchant(query, how))
+                .unwrap()
+                .into_iter()
+                .map(|t| t.merchant)
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert!(merchants("面食", MerchantMatch::Exact).is_empty());
+        assert_eq!(
+            merchants("梧桐苑", MerchantMatch::Prefix),
+            vec!["梧桐苑超市", "梧桐苑食堂面食"]
+        );
+        // short queries cannot use the trigram index
+        assert_eq!(
+            merchants("面", MerchantMatch::Substring),
+            vec!["康桥苑一楼面食", "梧桐苑食堂面食"]
+        );
+        assert_eq!(
+            merchants("西15东", MerchantMatch::Substring),
+            vec!["西14西15东12浴室"]
+        );
+        assert_eq!(merchants("wtycs", MerchantMatch::Fuzzy), vec!["梧桐苑超市"]);
+        assert_eq!(
+            merchants("康桥院一楼", MerchantMatch::Fuzzy),
+            vec!["康桥苑一楼面食"]
+        );
+
+        // merchants of transactions inserted later are searchable too
+        manager
+            .insert(&vec![Transaction::new(
+                Money::from_yuan(-1.0),
+                "康桥苑超市".to_string(),
+                time,
+            )])
+            .unwrap();
+        assert_eq!(
+            merchants("苑超市", MerchantMatch::Substring),
+            vec!["康桥苑超市", "梧桐苑超市"]
+        );
+    }
+
+    #[test]
+    fn test_fetch_filtered_sets() {
+        let manager = TransactionManager::new(None).unwrap();
+        // 2025-03-03 is a Monday
+        let at = |day, hour, minute| {
+            OFFSET_UTC_PLUS8
+                .with_ymd_and_hms(2025, 3, day, hour, minute, 0)
+                .unwrap()
+        };
+        manager
+            .insert(&vec![
+                Transaction::new(Money::from_yuan(-1.0), "炸吧".to_string(), at(3, 12, 0)),
+                Transaction::new(Money::from_yuan(-2.0), "时光水吧".to_string(), at(4, 8, 0)),
+                Transaction::new(
+                    Money::from_yuan(-3.0),
+                    "东区浴室-和风".to_string(),
+                    at(8, 21, 0),
+                ),
+                Transaction::new(Money::from_yuan(-4.0), "Nowhere".to_string(), at(9, 23, 30)),
+            ])
+            .unwrap();
+
+        let amounts = |filter: FilterOptions| {
+            let mut amounts: Vec<_> = manager
+                .fetch_filtered(&filter)
+                .unwrap()
+                .into_iter()
+                .map(|t| -t.amount.cents() / 100)
+                .collect();
+            amounts.sort();
+            amounts
+        };
+
+        assert_eq!(
+            amounts(
+                FilterOptions::default()
+                    .or_merchant("炸吧")
+                    .or_merchant("Nowhere")
+            ),
+            vec![1, 4]
+        );
+        assert_eq!(
+            amounts(FilterOptions::default().exclude_merchant("炸吧")),
+            vec![2, 3, 4]
+        );
+        assert_eq!(
+            amounts(
+                FilterOptions::default()
+                    .category(MerchantType::CanteenFood)
+                    .category(MerchantType::Unknown)
+            ),
+            vec![1, 4]
+        );
+        assert_eq!(
+            amounts(
+                FilterOptions::default()
+                    .weekday(Weekday::Mon)
+                    .weekday(Weekday::Sun)
+            ),
+            vec![1, 4]
+        );
+        let hm = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        assert_eq!(
+            amounts(FilterOptions::default().time_of_day(hm(11, 0), hm(13, 30))),
+            vec![1]
+        );
+        // wraps past midnight
+        assert_eq!(
+            amounts(FilterOptions::default().time_of_day(hm(21, 0), hm(8, 0))),
+            vec![3, 4]
+        );
+
+        // OR-groups are ANDed with the other conditions
+        let filter = FilterOptions::default()
+            .or(FilterOptions::default().category(MerchantType::Bathhouse))
+            .or(FilterOptions::default().merchant("时光水吧"));
+        assert_eq!(amounts(filter.clone()), vec![2, 3]);
+        assert_eq!(
+            amounts(filter.clone().exclude_merchant("时光水吧")),
+            vec![3]
+        );
+        assert!(
+            amounts(FilterOptions {
+                any_of: Some(vec![]),
+                ..Default::default()
+            })
+            .is_empty()
+        );
+
+        // the same filter round-trips through JSON for the web API and `--filter`
+        let filter = filter
+            .weekday(Weekday::Sat)
+            .time_of_day(hm(20, 0), hm(22, 0));
+        let json = serde_json::to_string(&filter).unwrap();
+        assert_eq!(
+            serde_json::from_str::<FilterOptions>(&json).unwrap(),
+            filter
+        );
+        assert_eq!(amounts(filter), vec![3]);
+    }
+
+    #[test]
+    fn test_time_range_across_offsets() {
+        let manager = TransactionManager::new(None).unwrap();
+        // 2025-02-28 17:00 in UTC
+        let time = OFFSET_UTC_PLUS8
+            .with_ymd_and_hms(2025, 3, 1, 1, 0, 0)
+            .unwrap();
+        manager
+            .insert(&vec![Transaction::new(
+                Money::from_yuan(-1.0),
+                "炸吧".to_string(),
+                time,
+            )])
+            .unwrap();
+
+        // the offset is preserved
+        assert_eq!(
+            manager.fetch_all().unwrap()[0].time.to_rfc3339(),
+            time.to_rfc3339()
+        );
+
+        // bounds in another offset compare by instant, not by text
+        let utc = |day, hour| {
+            FixedOffset::east_opt(0)
+                .unwrap()
+                .with_ymd_and_hms(2025, 2, day, hour, 0, 0)
+                .unwrap()
+        };
+        let count = |filter: FilterOptions| manager.fetch_filtered(&filter).unwrap().len();
+        assert_eq!(
+            count(FilterOptions::default().start(utc(28, 16)).end(utc(28, 18))),
+            1
+        );
+        assert_eq!(count(FilterOptions::default().start(utc(28, 18))), 0);
+    }
+
+    #[test]
+    fn test_fetch_filtered_kind() {
+        let manager = TransactionManager::new(None).unwrap();
+        let time = OFFSET_UTC_PLUS8
+            .with_ymd_and_hms(2025, 3, 1, 0, 0, 0)
+            .unwrap();
+        let mut refund = Transaction::new(Money::from_yuan(10.0), "Amazon".to_string(), time);
+        refund.kind = TransactionKind::Refund;
+        manager
+            .insert(&vec![
+                Transaction::new(Money::from_yuan(-100.0), "Amazon".to_string(), time),
+                Transaction::new(Money::from_yuan(200.0), "".to_string(), time),
+                refund,
+            ])
+            .unwrap();
+
+        assert_eq!(manager.fetch_all().unwrap().len(), 3);
+
+        let results = manager
+            .fetch_filtered(&FilterOptions::default().expenses_only())
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].amount, Money::from_yuan(-100.0));
+
+        let results = manager
+            .fetch_filtered(
+                &FilterOptions::default()
+                    .kind(TransactionKind::TopUp)
+                    .kind(TransactionKind::Refund),
+            )
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|t| t.amount > Money::ZERO));
+    }
+
+    #[test]
+    fn test_raw_row_fields_round_trip() {
+        let manager = TransactionManager::new(None).unwrap();
+        let mut transaction = Transaction::new(
+            Money::from_yuan(-4.0),
+            "库迪咖啡".to_string(),
+            OFFSET_UTC_PLUS8
+                .with_ymd_and_hms(2025, 3, 24, 13, 13, 28)
+                .unwrap(),
+        );
+        transaction.balance = Some(Money::from_yuan(96.5));
+        transaction.terminal = Some("10203".to_string());
+        transaction.tran_type = Some("电子账户消费".to_string());
+        transaction.raw = Some(r#"{"JDESC":"消费"}"#.to_string());
+        manager.insert(&vec![transaction]).unwrap();
+
+        let fetched = &manager.fetch_all().unwrap()[0];
+        assert_eq!(fetched.balance, Some(Money::from_yuan(96.5)));
+        assert_eq!(fetched.terminal.as_deref(), Some("10203"));
+        assert_eq!(fetched.tran_type.as_deref(), Some("电子账户消费"));
+        assert_eq!(fetched.raw.as_deref(), Some(r#"{"JDESC":"消费"}"#));
+    }
 }
@@ -0,0 +1,172 @@
+//! Grouped statistics computed by SQLite instead of folding transactions in Rust.
+
+use color_eyre::eyre::Result;
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
+
+use super::{FilterOptions, TransactionManager};
+use crate::libs::money::Money;
+
+/// How [`TransactionManager::aggregate`] buckets transactions
+///
+/// Times are bucketed in the offset they were recorded in, which is the
+/// campus's local time.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Display, EnumString,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum GroupBy {
+    /// `2025-03-01`
+    Day,
+    /// Monday of the week, `2025-02-24`
+    Week,
+    /// `2025-03`
+    #[default]
+    Month,
+    /// Merchant name
+    Merchant,
+    /// Hour of the day, `08`
+    Hour,
+    /// Start of the half hour of the day, `08:30`
+    HalfHour,
+}
+
+impl GroupBy {
+    /// SQL expression computing the group key of a row, from the local time
+    /// `time + utc_offset`
+    fn key_sql(&self) -> &'static str {
+        match self {
+            GroupBy::Day => "date(time + utc_offset, 'unixepoch')",
+            GroupBy::Week => "date(time + utc_offset, 'unixepoch', 'weekday 0', '-6 days')",
+            GroupBy::Month => "strftime('%Y-%m', time + utc_offset, 'unixepoch')",
+            GroupBy::Merchant => "merchant",
+            GroupBy::Hour => "strftime('%H', time + utc_offset, 'unixepoch')",
+            GroupBy::HalfHour => {
+                "strftime('%H:', time + utc_offset, 'unixepoch') \
+                || CASE WHEN strftime('%M', time + utc_offset, 'unixepoch') < '30' THEN '00' ELSE '30' END"
+            }
+        }
+    }
+}
+
+/// Statistics of the transactions sharing one group key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AggregateRow {
+    /// Group key, formatted as documented on [`GroupBy`]
+    pub key: String,
+    pub count: u64,
+    pub sum: Money,
+    /// Mean amount, rounded to the cent
+    pub avg: Money,
+    pub min: Money,
+    pub max: Money,
+}
+
+impl TransactionManager {
+    /// Group the transactions matching the filter and summarize their amounts
+    ///
+    /// Rows are ordered by key. Sorting and pagination in the filter are ignored.
+    pub fn aggregate(
+        &self,
+        filter_opt: &FilterOptions,
+        group_by: GroupBy,
+    ) -> Result<Vec<AggregateRow>> {
+        let (where_clause, params) = self.where_clause(filter_opt);
+        let query = format!(
+            "SELECT {key} AS grp, COUNT(*), SUM(amount), CAST(ROUND(AVG(amount)) AS INTEGER), MIN(amount), MAX(amount)
+            FROM transactions {where_clause} GROUP BY grp ORDER BY grp",
+            key = group_by.key_sql(),
+        );
+
+        let conn = self.db.read()?;
+        let mut stmt = conn.prepare(&query)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(params.iter()), |row| {
+            Ok(AggregateRow {
+                key: row.get(0)?,
+                count: row.get::<_, i64>(1)? as u64,
+                sum: row.get(2)?,
+                avg: row.get(3)?,
+                min: row.get(4)?,
+                max: row.get(5)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, FixedOffset};
+
+    use super::*;
+    use crate::libs::transactions::Transaction;
+
+    fn transaction(yuan: f64, merchant: &str, time: &str) -> Transaction {
+        Transaction::new(
+            Money::from_yuan(yuan),
+            merchant.to_string(),
+            DateTime::<FixedOffset>::parse_from_rfc3339(time).unwrap(),
+        )
+    }
+
+    fn test_manager() -> TransactionManager {
+        let manager = TransactionManager::new(None).unwrap();
+        manager
+            .insert(&vec![
+                transaction(-10.0, "食堂", "2025-03-03T08:10:00+08:00"),
+                transaction(-5.5, "食堂", "2025-03-03T12:40:00+08:00"),
+                transaction(-2.25, "超市", "2025-03-09T08:50:00+08:00"),
+                transaction(100.0, "充值", "2025-04-01T09:00:00+08:00"),
+            ])
+            .unwrap();
+        manager
+    }
+
+    fn keys(rows: &[AggregateRow]) -> Vec<&str> {
+        rows.iter().map(|r| r.key.as_str()).collect()
+    }
+
+    #[test]
+    fn test_group_keys() {
+        let manager = test_manager();
+        let filter = FilterOptions::default();
+        let keys_of = |group_by| keys(&manager.aggregate(&filter, group_by).unwrap()).join(" ");
+
+        assert_eq!(keys_of(GroupBy::Day), "2025-03-03 2025-03-09 2025-04-01");
+        // 03-09 is a Sunday, which still belongs to the week starting 03-03
+        assert_eq!(keys_of(GroupBy::Week), "2025-03-03 2025-03-31");
+        assert_eq!(keys_of(GroupBy::Month), "2025-03 2025-04");
+        assert_eq!(keys_of(GroupBy::Merchant), "充值 超市 食堂");
+        assert_eq!(keys_of(GroupBy::Hour), "08 09 12");
+        assert_eq!(keys_of(GroupBy::HalfHour), "08:00 08:30 09:00 12:30");
+    }
+
+    #[test]
+    fn test_statistics() {
+        let manager = test_manager();
+        let rows = manager
+            .aggregate(&FilterOptions::default().expenses_only(), GroupBy::Month)
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![AggregateRow {
+                key: "2025-03".to_string(),
+                count: 3,
+                sum: Money::from_cents(-1775),
+                // -5.9166... rounds to the nearest cent
+                avg: Money::from_cents(-592),
+                min: Money::from_cents(-1000),
+                max: Money::from_cents(-225),
+            }]
+        );
+
+        let other = manager.for_profile("other").unwrap();
+        assert!(
+            other
+                .aggregate(&FilterOptions::default(), GroupBy::Day)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
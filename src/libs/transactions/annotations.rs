@@ -0,0 +1,190 @@
+//! User notes and tags on transactions.
+//!
+//! Annotations are stored in `transaction_notes` and `transaction_tags`, keyed
+//! by the (profile-scoped) transaction ID, and loaded into
+//! [`Transaction::note`](super::Transaction::note) and
+//! [`Transaction::tags`](super::Transaction::tags).
+
+use color_eyre::eyre::Result;
+use rusqlite::{Connection, params};
+
+use super::TransactionManager;
+
+/// Columns loading the annotations of a row of `transactions`, read by
+/// [`TransactionManager::row_to_transaction`] after the regular columns
+///
+/// Tags are returned as a sorted JSON array.
+pub(super) const ANNOTATION_COLUMNS: &str = "\
+    (SELECT note FROM transaction_notes WHERE transaction_id = transactions.id), \
+    (SELECT json_group_array(tag) FROM (\
+        SELECT tag FROM transaction_tags WHERE transaction_id = transactions.id ORDER BY tag\
+    ))";
+
+/// Trimmed, non-empty and distinct tags, in their original order
+pub fn normalize_tags<T: AsRef<str>>(tags: &[T]) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.as_ref().trim();
+        if !tag.is_empty() && !normalized.iter().any(|t| t == tag) {
+            normalized.push(tag.to_string());
+        }
+    }
+    normalized
+}
+
+impl TransactionManager {
+    /// Whether the active profile has a transaction with this ID
+    fn has_transaction(&self, conn: &Connection, id: i64) -> Result<bool> {
+        Ok(conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM transactions WHERE id = ? AND profile = ?)",
+            params![id, self.profile()],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Set the note of a transaction of the active profile, removing it if blank
+    ///
+    /// Returns `false` if there is no such transaction.
+    pub fn set_note(&self, id: i64, note: &str) -> Result<bool> {
+        let conn = self.db.write();
+        if !self.has_transaction(&conn, id)? {
+            return Ok(false);
+        }
+        let note = note.trim();
+        if note.is_empty() {
+            conn.execute(
+                "DELETE FROM transaction_notes WHERE transaction_id = ?",
+                [id],
+            )?;
+        } else {
+            conn.execute(
+                "INSERT INTO transaction_notes (transaction_id, note) VALUES (?, ?)
+                ON CONFLICT(transaction_id) DO UPDATE SET note = excluded.note",
+                params![id, note],
+            )?;
+        }
+        Ok(true)
+    }
+
+    /// Replace the tags of a transaction of the active profile
+    ///
+    /// Tags are trimmed, and blank or repeated ones dropped. Returns `false`
+    /// if there is no such transaction.
+    pub fn set_tags<T: AsRef<str>>(&self, id: i64, tags: &[T]) -> Result<bool> {
+        let mut conn = self.db.write();
+        if !self.has_transaction(&conn, id)? {
+            return Ok(false);
+        }
+        let tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM transaction_tags WHERE transaction_id = ?",
+            [id],
+        )?;
+        for tag in normalize_tags(tags) {
+            tx.execute(
+                "INSERT INTO transaction_tags (transaction_id, tag) VALUES (?, ?)",
+                params![id, tag],
+            )?;
+        }
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Tags used in the active profile, sorted
+    pub fn list_tags(&self) -> Result<Vec<String>> {
+        let conn = self.db.read()?;
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT tag FROM transaction_tags
+            JOIN transactions ON transactions.id = transaction_id
+            WHERE profile = ? ORDER BY tag",
+        )?;
+        let tags = stmt.query_map([self.profile()], |row| row.get(0))?;
+        Ok(tags.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::{
+        fetcher::test_utils::get_mock_data,
+        transactions::{FilterOptions, Transaction},
+    };
+
+    fn manager_with_data() -> (TransactionManager, Vec<Transaction>) {
+        let manager = TransactionManager::new(None).unwrap();
+        manager.insert(&get_mock_data(10)).unwrap();
+        let transactions = manager.fetch_all().unwrap();
+        (manager, transactions)
+    }
+
+    #[test]
+    fn test_note_and_tags() {
+        let (manager, transactions) = manager_with_data();
+        let id = transactions[0].id;
+
+        assert!(manager.set_note(id, " with roommate ").unwrap());
+        assert!(
+            manager
+                .set_tags(id, &["exam week", "treated friend", " exam week", ""])
+                .unwrap()
+        );
+        let annotated = &manager.fetch_all().unwrap()[0];
+        assert_eq!(annotated.note.as_deref(), Some("with roommate"));
+        assert_eq!(annotated.tags, vec!["exam week", "treated friend"]);
+        assert!(manager.fetch_all().unwrap()[1].tags.is_empty());
+        assert_eq!(
+            manager.list_tags().unwrap(),
+            vec!["exam week", "treated friend"]
+        );
+
+        assert!(manager.set_note(id, "").unwrap());
+        assert!(manager.set_tags::<&str>(id, &[]).unwrap());
+        let cleared = &manager.fetch_all().unwrap()[0];
+        assert_eq!(cleared.note, None);
+        assert!(cleared.tags.is_empty());
+
+        // unknown IDs and other profiles' transactions are refused
+        assert!(!manager.set_note(-1, "nope").unwrap());
+        let other = manager.for_profile("other").unwrap();
+        assert!(!other.set_tags(id, &["nope"]).unwrap());
+    }
+
+    #[test]
+    fn test_filter_annotations() {
+        let (manager, transactions) = manager_with_data();
+        manager
+            .set_tags(transactions[0].id, &["exam week"])
+            .unwrap();
+        manager
+            .set_tags(transactions[1].id, &["treated friend"])
+            .unwrap();
+        manager
+            .set_note(transactions[2].id, "birthday dinner")
+            .unwrap();
+
+        let ids = |filter: FilterOptions| {
+            let mut ids: Vec<_> = manager
+                .fetch_filtered(&filter)
+                .unwrap()
+                .iter()
+                .map(|t| t.id)
+                .collect();
+            ids.sort();
+            ids
+        };
+        let mut expected = vec![transactions[0].id, transactions[1].id];
+        expected.sort();
+        assert_eq!(
+            ids(FilterOptions::default()
+                .tag("exam week")
+                .tag("treated friend")),
+            expected
+        );
+        assert_eq!(
+            ids(FilterOptions::default().note_contains("birthday")),
+            vec![transactions[2].id]
+        );
+        assert!(ids(FilterOptions::default().tag("missing")).is_empty());
+    }
+}
@@ -0,0 +1,217 @@
+//! Timestamped snapshots of the database file, and restoring from them.
+//!
+//! Snapshots use SQLite's online backup API, so they are consistent even while
+//! the TUI or the web server has the database open. They are written to a
+//! `backups` directory next to the database as `transactions-<time>.db`.
+//!
+//! The credential key file is not included: restoring into the same data
+//! directory keeps working, but a backup moved elsewhere needs the key file
+//! (or passphrase) too, or its credentials are cleared and asked for again.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::Local;
+use color_eyre::eyre::{Context, ContextCompat, Result, bail};
+use rusqlite::{Connection, DatabaseName, OpenFlags, backup::Progress};
+
+use super::migrations::{LATEST_VERSION, schema_version};
+
+/// Backups of one database file
+#[derive(Debug, Clone)]
+pub struct Backups {
+    db_path: PathBuf,
+    dir: PathBuf,
+}
+
+impl Backups {
+    /// Backups of the database at `db_path`, kept in `backups` next to it
+    pub fn new(db_path: PathBuf) -> Self {
+        let dir = db_path.parent().unwrap_or(Path::new(".")).join("backups");
+        Backups { db_path, dir }
+    }
+
+    fn stem(&self) -> String {
+        self.db_path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "transactions".to_string())
+    }
+
+    /// Snapshot the database and return the path of the new backup
+    ///
+    /// The snapshot is written under a temporary name and checked before it
+    /// gets its final name, so an interrupted backup never looks complete.
+    pub fn backup(&self) -> Result<PathBuf> {
+        if !self.db_path.exists() {
+            bail!("No database at {}", self.db_path.display());
+        }
+        fs::create_dir_all(&self.dir).context("Failed to create backup directory")?;
+
+        let time = Local::now().format("%Y%m%d-%H%M%S-%3f").to_string();
+        // several backups within a millisecond get a suffix, keeping the order
+        let path = (0..)
+            .map(|n| match n {
+                0 => self.dir.join(format!("{}-{}.db", self.stem(), time)),
+                n => self.dir.join(format!("{}-{}-{}.db", self.stem(), time, n)),
+            })
+            .find(|path| !path.exists())
+            .unwrap();
+        let partial = path.with_extension("db.partial");
+
+        let src = Connection::open_with_flags(&self.db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Failed to open {}", self.db_path.display()))?;
+        src.backup(DatabaseName::Main, &partial, None::<fn(Progress)>)
+            .context("Failed to snapshot database")?;
+        Backups::verify(&partial)?;
+        fs::rename(&partial, &path).context("Failed to move backup into place")?;
+        Ok(path)
+    }
+
+    /// Backups of this database, oldest first
+    pub fn list(&self) -> Result<Vec<PathBuf>> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+        let prefix = format!("{}-", self.stem());
+        let mut backups: Vec<PathBuf> = fs::read_dir(&self.dir)
+            .context("Failed to read backup directory")?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.extension().is_some_and(|ext| ext == "db")
+                    && path
+                        .file_name()
+                        .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+            })
+            .collect();
+        // the timestamps sort chronologically
+        backups.sort();
+        Ok(backups)
+    }
+
+    /// Delete all but the `keep` most recent backups, returning the deleted ones
+    pub fn rotate(&self, keep: usize) -> Result<Vec<PathBuf>> {
+        let backups = self.list()?;
+        let excess = backups.len().saturating_sub(keep);
+        let removed: Vec<PathBuf> = backups.into_iter().take(excess).collect();
+        for path in &removed {
+            fs::remove_file(path)
+                .with_context(|| format!("Failed to delete backup {}", path.display()))?;
+        }
+        Ok(removed)
+    }
+
+    /// Check that a backup is an intact database this build can open
+    pub fn verify(path: &Path) -> Result<()> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Failed to open backup {}", path.display()))?;
+        let result: String = conn
+            .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+            .with_context(|| format!("{} is not a valid database", path.display()))?;
+        if result != "ok" {
+            bail!("Backup {} is corrupted: {}", path.display(), result);
+        }
+
+        let version = schema_version(&conn)?;
+        if version > LATEST_VERSION {
+            bail!(
+                "Backup {} has schema version {}, newer than the latest version {} supported by this build",
+                path.display(),
+                version,
+                LATEST_VERSION
+            );
+        }
+        let has_transactions: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'transactions')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_transactions {
+            bail!("Backup {} has no transactions table", path.display());
+        }
+        Ok(())
+    }
+
+    /// Replace the database with a backup, the latest one if not given
+    ///
+    /// The backup is verified first, and the current database is backed up
+    /// before being overwritten. Returns the path of the restored backup.
+    pub fn restore(&self, backup: Option<PathBuf>) -> Result<PathBuf> {
+        let backup = match backup {
+            Some(path) => path,
+            None => self
+                .list()?
+                .pop()
+                .with_context(|| format!("No backups in {}", self.dir.display()))?,
+        };
+        Backups::verify(&backup)?;
+
+        if self.db_path.exists() {
+            self.backup()
+                .context("Failed to back up the current database before restoring")?;
+        }
+        let mut conn = Connection::open(&self.db_path)
+            .with_context(|| format!("Failed to open {}", self.db_path.display()))?;
+        conn.restore(DatabaseName::Main, &backup, None::<fn(Progress)>)
+            .with_context(|| format!("Failed to restore {}", backup.display()))?;
+        Ok(backup)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::{fetcher::test_utils::get_mock_data, transactions::TransactionManager};
+
+    #[test]
+    fn test_backup_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("transactions.db");
+        let manager = TransactionManager::new(Some(db_path.clone())).unwrap();
+        manager.insert(&get_mock_data(10)).unwrap();
+        let count = manager.fetch_count().unwrap();
+
+        // the manager keeps the database open meanwhile
+        let backups = Backups::new(db_path.clone());
+        let backup = backups.backup().unwrap();
+        assert_eq!(backups.list().unwrap(), vec![backup.clone()]);
+
+        manager.clear_db().unwrap();
+        assert_eq!(manager.fetch_count().unwrap(), 0);
+
+        assert_eq!(backups.restore(None).unwrap(), backup);
+        assert_eq!(manager.fetch_count().unwrap(), count);
+        // the cleared database was backed up before being replaced
+        assert_eq!(backups.list().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("transactions.db");
+        TransactionManager::new(Some(db_path.clone())).unwrap();
+
+        let backups = Backups::new(db_path);
+        let created: Vec<_> = (0..3).map(|_| backups.backup().unwrap()).collect();
+        assert_eq!(backups.rotate(2).unwrap(), vec![created[0].clone()]);
+        assert_eq!(backups.list().unwrap(), created[1..]);
+    }
+
+    #[test]
+    fn test_refuse_corrupted() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("transactions.db");
+        let manager = TransactionManager::new(Some(db_path.clone())).unwrap();
+        manager.insert(&get_mock_data(10)).unwrap();
+        let count = manager.fetch_count().unwrap();
+
+        let garbage = dir.path().join("garbage.db");
+        fs::write(&garbage, "not a database").unwrap();
+        let backups = Backups::new(db_path);
+        assert!(backups.restore(Some(garbage)).is_err());
+        assert!(backups.restore(None).is_err());
+        assert_eq!(manager.fetch_count().unwrap(), count);
+    }
+}
@@ -0,0 +1,287 @@
+//! Quarantine of fetched transactions conflicting with stored ones.
+//!
+//! A fetched transaction conflicts when a stored one has the same ID but a
+//! different time, amount or merchant, which the `prevent_transaction_conflict`
+//! trigger refuses. Instead of failing the import, both versions are kept in
+//! the `conflicts` table until the user keeps one of them.
+
+use chrono::{DateTime, TimeZone, Utc};
+use color_eyre::eyre::{Context, Result};
+use rusqlite::{ErrorCode, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use strum::{EnumString, IntoStaticStr};
+
+use super::{Transaction, TransactionManager, annotations::ANNOTATION_COLUMNS};
+
+/// A fetched transaction that conflicts with the stored one of the same ID
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Conflict {
+    /// ID of the conflict, not of the transactions
+    pub id: i64,
+    /// The stored transaction when the conflict was detected
+    pub existing: Transaction,
+    /// The fetched transaction that was refused
+    pub incoming: Transaction,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// Which version of a conflicting transaction to keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumString, IntoStaticStr)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Resolution {
+    KeepExisting,
+    UseIncoming,
+}
+
+/// Whether inserting failed because of the conflict trigger
+pub(super) fn is_conflict(e: &rusqlite::Error) -> bool {
+    matches!(
+        e,
+        rusqlite::Error::SqliteFailure(err, Some(message))
+            if err.code == ErrorCode::ConstraintViolation && message.starts_with("Conflict:")
+    )
+}
+
+/// Record a transaction refused by the conflict trigger, unless the same
+/// conflict was recorded before
+pub(super) fn quarantine(
+    tx: &rusqlite::Transaction,
+    incoming: &Transaction,
+    profile: &str,
+) -> Result<()> {
+    let existing = tx
+        .query_row(
+            &format!(
+                "SELECT {}, {} FROM transactions WHERE id = ?",
+                TransactionManager::COLUMNS,
+                ANNOTATION_COLUMNS
+            ),
+            [incoming.id],
+            TransactionManager::row_to_transaction,
+        )
+        .context("Failed to load the transaction conflicting with a fetched one")?;
+    tx.execute(
+        "INSERT OR IGNORE INTO conflicts
+            (transaction_id, profile, existing, incoming, detected_at)
+        VALUES (?, ?, ?, ?, ?)",
+        params![
+            incoming.id,
+            profile,
+            serde_json::to_string(&existing)?,
+            serde_json::to_string(incoming)?,
+            Utc::now().timestamp()
+        ],
+    )?;
+    Ok(())
+}
+
+fn row_to_conflict(row: &rusqlite::Row) -> rusqlite::Result<Conflict> {
+    let json = |index: usize| -> rusqlite::Result<Transaction> {
+        serde_json::from_str(&row.get::<_, String>(index)?).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, e.into())
+        })
+    };
+    let detected_at: i64 = row.get(3)?;
+    Ok(Conflict {
+        id: row.get(0)?,
+        existing: json(1)?,
+        incoming: json(2)?,
+        detected_at: Utc
+            .timestamp_opt(detected_at, 0)
+            .single()
+            .ok_or(rusqlite::Error::IntegralValueOutOfRange(3, detected_at))?,
+    })
+}
+
+impl TransactionManager {
+    /// Unresolved conflicts of the active profile, oldest first
+    pub fn list_conflicts(&self) -> Result<Vec<Conflict>> {
+        let conn = self.db.read()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, existing, incoming, detected_at FROM conflicts
+            WHERE profile = ? AND resolution IS NULL ORDER BY id",
+        )?;
+        let conflicts = stmt.query_map([self.profile()], row_to_conflict)?;
+        Ok(conflicts.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Number of unresolved conflicts of the active profile
+    pub fn count_conflicts(&self) -> Result<u64> {
+        let count: i64 = self.db.read()?.query_row(
+            "SELECT COUNT(*) FROM conflicts WHERE profile = ? AND resolution IS NULL",
+            [self.profile()],
+            |row| row.get(0),
+        )?;
+        Ok(count as u64)
+    }
+
+    /// Resolve a conflict of the active profile by keeping one version
+    ///
+    /// Using the incoming version replaces the stored transaction but keeps its
+    /// note, tags and hidden flag. Returns `false` if there is no such
+    /// unresolved conflict.
+    pub fn resolve_conflict(&self, id: i64, resolution: Resolution) -> Result<bool> {
+        let profile = self.profile();
+        let mut conn = self.db.write();
+        let tx = conn.transaction()?;
+        let conflict = tx
+            .query_row(
+                "SELECT id, existing, incoming, detected_at FROM conflicts
+                WHERE id = ? AND profile = ? AND resolution IS NULL",
+                params![id, profile],
+                row_to_conflict,
+            )
+            .optional()?;
+        let Some(conflict) = conflict else {
+            return Ok(false);
+        };
+
+        if resolution == Resolution::UseIncoming {
+            let hidden: Option<bool> = tx
+                .query_row(
+                    "SELECT hidden FROM transactions WHERE id = ?",
+                    [conflict.incoming.id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            tx.execute(
+                "DELETE FROM transactions WHERE id = ?",
+                [conflict.incoming.id],
+            )?;
+            let incoming = Transaction {
+                hidden: hidden.unwrap_or_default(),
+                ..conflict.incoming
+            };
+            TransactionManager::insert_row(
+                &mut TransactionManager::prepare_insert(&tx)?,
+                incoming.id,
+                &incoming,
+                &profile,
+            )
+            .context("Failed to replace the conflicting transaction")?;
+        }
+        tx.execute(
+            "UPDATE conflicts SET resolution = ? WHERE id = ?",
+            params![<&str>::from(resolution), id],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::{fetcher::test_utils::get_mock_data, money::Money};
+
+    #[test]
+    fn test_conflicts_are_quarantined() {
+        let manager = TransactionManager::new(None).unwrap();
+        let data = get_mock_data(10);
+        manager.insert(&data).unwrap();
+        let count = manager.fetch_count().unwrap();
+
+        // same ID, different amount, in the middle of a batch with new rows
+        let mut changed = data[1].clone();
+        changed.amount += Money::from_cents(-100);
+        let mut new = data[2].clone();
+        new.id = 42;
+        let batch = vec![data[0].clone(), changed.clone(), new];
+        let report = manager.insert(&batch).unwrap();
+        assert_eq!((report.new, report.duplicate, report.conflict), (1, 1, 1));
+        // the rest of the batch is still inserted
+        assert_eq!(manager.fetch_count().unwrap(), count + 1);
+
+        let conflicts = manager.list_conflicts().unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].existing.amount, data[1].amount);
+        assert_eq!(conflicts[0].incoming.amount, changed.amount);
+
+        // fetching the same conflict again does not report it twice
+        manager.insert(&batch).unwrap();
+        assert_eq!(manager.count_conflicts().unwrap(), 1);
+        assert_eq!(
+            manager
+                .for_profile("other")
+                .unwrap()
+                .count_conflicts()
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_resolve_conflicts() {
+        let manager = TransactionManager::new(None).unwrap();
+        let data = get_mock_data(10);
+        manager.insert(&data).unwrap();
+        manager.set_note(data[0].id, "keep me").unwrap();
+        manager.set_hidden(data[0].id, true).unwrap();
+
+        let mut changed = data[0].clone();
+        changed.merchant = "改名商家".to_string();
+        manager.insert(&vec![changed.clone()]).unwrap();
+        let mut kept = data[1].clone();
+        kept.amount = Money::from_cents(-1);
+        manager.insert(&vec![kept.clone()]).unwrap();
+        let conflicts = manager.list_conflicts().unwrap();
+        assert_eq!(conflicts.len(), 2);
+
+        assert!(
+            manager
+                .resolve_conflict(conflicts[0].id, Resolution::UseIncoming)
+                .unwrap()
+        );
+        let replaced = manager
+            .fetch_filtered(&crate::libs::transactions::FilterOptions::default().include_hidden())
+            .unwrap()
+            .into_iter()
+            .find(|t| t.id == data[0].id)
+            .unwrap();
+        assert_eq!(replaced.merchant, "改名商家");
+        assert_eq!(replaced.note.as_deref(), Some("keep me"));
+        assert!(replaced.hidden);
+
+        assert!(
+            manager
+                .resolve_conflict(conflicts[1].id, Resolution::KeepExisting)
+                .unwrap()
+        );
+        assert!(
+            !manager
+                .resolve_conflict(conflicts[1].id, Resolution::UseIncoming)
+                .unwrap()
+        );
+        assert_eq!(manager.count_conflicts().unwrap(), 0);
+
+        // a kept version is not reported again on the next fetch
+        manager.insert(&vec![kept]).unwrap();
+        assert_eq!(manager.count_conflicts().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_insert_is_atomic() {
+        let manager = TransactionManager::new(None).unwrap();
+        let mut data = get_mock_data(10);
+        manager.insert(&data[..2].to_vec()).unwrap();
+        let count = manager.fetch_count().unwrap();
+
+        // a row failing for another reason rolls back the whole batch
+        data[5].id = data[4].id;
+        data[5].merchant = data[4].merchant.clone();
+        data[5].time = data[4].time;
+        data[5].amount = data[4].amount;
+        manager
+            .db
+            .write()
+            .execute_batch("DROP TRIGGER prevent_transaction_conflict")
+            .unwrap();
+        // data[2..5] are new and written before data[5] fails
+        assert!(manager.insert(&data[2..].to_vec()).is_err());
+        assert_eq!(manager.fetch_count().unwrap(), count);
+        let stored: Vec<i64> = manager.fetch_all().unwrap().iter().map(|t| t.id).collect();
+        assert!(data[..2].iter().all(|t| stored.contains(&t.id)));
+        assert!(data[2..5].iter().all(|t| !stored.contains(&t.id)));
+    }
+}
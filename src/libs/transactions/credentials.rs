@@ -0,0 +1,311 @@
+//! Encryption at rest and masking of the account and hallticket cookie.
+//!
+//! Credentials are encrypted with ChaCha20-Poly1305 and stored as
+//! `enc1:<base64 of nonce and ciphertext>`. The key comes from one of:
+//!
+//! - a passphrase, stretched with Argon2 and a salt kept in the `settings` table
+//! - a random key file next to the database, created on first use
+//! - a random key kept in memory, for in-memory databases
+//!
+//! Values without the prefix are plaintext written by older versions; they
+//! are encrypted when the database is opened. Credentials encrypted with
+//! another key, e.g. after losing the key file, are cleared instead and have to
+//! be entered again; the transactions themselves are not encrypted.
+
+use std::{fs, path::Path};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce, aead::Aead};
+use color_eyre::eyre::{Context, Result, bail, eyre};
+use rusqlite::{Connection, OptionalExtension, params};
+use tracing::warn;
+
+const PREFIX: &str = "enc1:";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+
+/// `settings` key of the passphrase salt, base64 encoded
+const SALT_SETTING: &str = "credential_salt";
+/// `settings` key of a known value encrypted with the key, telling whether the
+/// stored credentials were encrypted with the current passphrase or key file
+const CHECK_SETTING: &str = "credential_check";
+const CHECK_VALUE: &str = "xjtu_mealflow";
+
+/// Key encrypting the credentials stored in the database
+#[derive(Clone)]
+pub struct CredentialKey([u8; KEY_LEN]);
+
+impl std::fmt::Debug for CredentialKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CredentialKey(..)")
+    }
+}
+
+impl CredentialKey {
+    /// A new random key
+    pub fn random() -> Self {
+        let mut key = [0; KEY_LEN];
+        rand::fill(&mut key);
+        CredentialKey(key)
+    }
+
+    /// Read the key file, or create it with a random key if it does not exist
+    ///
+    /// On Unix the file is only readable by its owner.
+    pub fn load_or_create(path: &Path) -> Result<Self> {
+        if path.exists() {
+            let bytes = fs::read(path)
+                .with_context(|| format!("Failed to read key file {}", path.display()))?;
+            let key = BASE64
+                .decode(bytes.trim_ascii())
+                .ok()
+                .and_then(|key| <[u8; KEY_LEN]>::try_from(key).ok())
+                .ok_or_else(|| eyre!("Key file {} is corrupted", path.display()))?;
+            return Ok(CredentialKey(key));
+        }
+
+        let key = CredentialKey::random();
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(path)
+            .with_context(|| format!("Failed to create key file {}", path.display()))?;
+        std::io::Write::write_all(&mut file, BASE64.encode(key.0).as_bytes())
+            .with_context(|| format!("Failed to write key file {}", path.display()))?;
+        Ok(key)
+    }
+
+    /// Derive the key from a passphrase and the salt stored in the database,
+    /// generating the salt on first use
+    pub(super) fn from_passphrase(conn: &Connection, passphrase: &str) -> Result<Self> {
+        let salt = match get_setting(conn, SALT_SETTING)? {
+            Some(salt) => BASE64
+                .decode(salt)
+                .context("Passphrase salt is corrupted")?,
+            None => {
+                let mut salt = [0; SALT_LEN];
+                rand::fill(&mut salt);
+                set_setting(conn, SALT_SETTING, &BASE64.encode(salt))?;
+                salt.to_vec()
+            }
+        };
+        let mut key = [0; KEY_LEN];
+        argon2::Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| eyre!("Failed to derive key from passphrase: {}", e))?;
+        Ok(CredentialKey(key))
+    }
+
+    /// Encrypt a credential for storage, keeping empty values empty
+    pub fn encrypt(&self, plaintext: &str) -> String {
+        if plaintext.is_empty() {
+            return String::new();
+        }
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&self.0));
+        let mut nonce = [0; NONCE_LEN];
+        rand::fill(&mut nonce);
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            cipher
+                .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+                .expect("Encrypting into a Vec cannot fail"),
+        );
+        format!("{}{}", PREFIX, BASE64.encode(sealed))
+    }
+
+    /// Decrypt a stored credential, passing plaintext from older versions through
+    pub fn decrypt(&self, stored: &str) -> Result<String> {
+        let Some(encoded) = stored.strip_prefix(PREFIX) else {
+            return Ok(stored.to_string());
+        };
+        let sealed = BASE64
+            .decode(encoded)
+            .context("Stored credential is corrupted")?;
+        if sealed.len() < NONCE_LEN {
+            bail!("Stored credential is corrupted");
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let plaintext = ChaCha20Poly1305::new(Key::from_slice(&self.0))
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                eyre!("Failed to decrypt credentials, the passphrase or key file may be wrong")
+            })?;
+        String::from_utf8(plaintext).context("Stored credential is corrupted")
+    }
+
+    /// Check the key against the database and encrypt plaintext credentials
+    /// left by older versions
+    ///
+    /// Credentials encrypted with another passphrase or key file cannot be
+    /// read anymore, so they are cleared and the database is re-sealed with
+    /// this key. The active profile then asks for them again.
+    pub(super) fn seal_database(&self, conn: &Connection) -> Result<()> {
+        match get_setting(conn, CHECK_SETTING)? {
+            Some(check) if self.decrypt(&check).ok().as_deref() == Some(CHECK_VALUE) => {}
+            Some(_) => {
+                warn!(
+                    "Stored credentials were encrypted with another passphrase or key file, clearing them"
+                );
+                conn.execute(
+                    "UPDATE profiles SET
+                        account = CASE WHEN account LIKE ?1 THEN '' ELSE account END,
+                        cookie = CASE WHEN cookie LIKE ?1 THEN '' ELSE cookie END",
+                    [format!("{}%", PREFIX)],
+                )?;
+                set_setting(conn, CHECK_SETTING, &self.encrypt(CHECK_VALUE))?;
+            }
+            None => set_setting(conn, CHECK_SETTING, &self.encrypt(CHECK_VALUE))?,
+        }
+
+        let mut stmt = conn.prepare("SELECT name, account, cookie FROM profiles")?;
+        let plaintext: Vec<(String, String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        for (name, account, cookie) in plaintext {
+            let seal = |value: String| {
+                if value.starts_with(PREFIX) {
+                    value
+                } else {
+                    self.encrypt(&value)
+                }
+            };
+            conn.execute(
+                "UPDATE profiles SET account = ?, cookie = ? WHERE name = ?",
+                params![seal(account), seal(cookie), name],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn get_setting(conn: &Connection, key: &str) -> Result<Option<String>> {
+    Ok(conn
+        .query_row("SELECT value FROM settings WHERE key = ?", [key], |row| {
+            row.get(0)
+        })
+        .optional()?)
+}
+
+fn set_setting(conn: &Connection, key: &str, value: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO settings (key, value) VALUES (?, ?)
+        ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        params![key, value],
+    )?;
+    Ok(())
+}
+
+/// Hide a secret for display, keeping the last 4 characters of long values
+pub fn mask_secret(secret: &str) -> String {
+    let len = secret.chars().count();
+    let shown = if len > 8 { 4 } else { 0 };
+    let mut masked = "*".repeat(len - shown);
+    masked.extend(secret.chars().skip(len - shown));
+    masked
+}
+
+/// Hide the values of a cookie string like `hallticket=abc; other=def`,
+/// keeping the names
+pub fn mask_cookie(cookie: &str) -> String {
+    cookie
+        .split("; ")
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) => format!("{}={}", name, mask_secret(value)),
+            None => mask_secret(pair),
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE settings (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+            CREATE TABLE profiles (name TEXT PRIMARY KEY, account TEXT, cookie TEXT);
+            INSERT INTO profiles VALUES ('default', '123456', 'hallticket=abc');",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let key = CredentialKey::random();
+        let stored = key.encrypt("hallticket=abc");
+        assert!(stored.starts_with(PREFIX));
+        assert!(!stored.contains("abc"));
+        assert_eq!(key.decrypt(&stored).unwrap(), "hallticket=abc");
+        // plaintext from older versions passes through
+        assert_eq!(key.decrypt("hallticket=abc").unwrap(), "hallticket=abc");
+        assert_eq!(key.encrypt(""), "");
+
+        assert!(CredentialKey::random().decrypt(&stored).is_err());
+    }
+
+    #[test]
+    fn test_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transactions.key");
+        let key = CredentialKey::load_or_create(&path).unwrap();
+        let stored = key.encrypt("123456");
+        let reloaded = CredentialKey::load_or_create(&path).unwrap();
+        assert_eq!(reloaded.decrypt(&stored).unwrap(), "123456");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_passphrase_and_seal() {
+        let conn = settings_db();
+        let key = CredentialKey::from_passphrase(&conn, "correct horse").unwrap();
+        key.seal_database(&conn).unwrap();
+
+        let (account, cookie): (String, String) = conn
+            .query_row("SELECT account, cookie FROM profiles", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert!(account.starts_with(PREFIX));
+        assert_eq!(key.decrypt(&cookie).unwrap(), "hallticket=abc");
+
+        // the same passphrase derives the same key
+        let again = CredentialKey::from_passphrase(&conn, "correct horse").unwrap();
+        again.seal_database(&conn).unwrap();
+        assert_eq!(again.decrypt(&account).unwrap(), "123456");
+
+        // another one clears the credentials it cannot read and takes over
+        let wrong = CredentialKey::from_passphrase(&conn, "battery staple").unwrap();
+        wrong.seal_database(&conn).unwrap();
+        let cleared: (String, String) = conn
+            .query_row("SELECT account, cookie FROM profiles", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(cleared, (String::new(), String::new()));
+        let check = get_setting(&conn, CHECK_SETTING).unwrap().unwrap();
+        assert_eq!(wrong.decrypt(&check).unwrap(), CHECK_VALUE);
+    }
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(mask_secret("123456"), "******");
+        assert_eq!(mask_secret("abcdefgh1234"), "********1234");
+        assert_eq!(mask_secret(""), "");
+        assert_eq!(
+            mask_cookie("hallticket=abcdefgh1234; a=b"),
+            "hallticket=********1234; a=*"
+        );
+    }
+}
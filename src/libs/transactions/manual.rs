@@ -0,0 +1,182 @@
+//! Transactions entered by hand, and hiding transactions.
+//!
+//! Spending the card system never sees (cash at a stall) is added as a manual
+//! transaction. Manual transactions get negative IDs, which
+//! [`Transaction::stable_id`] and [`Transaction::scoped_id`] never produce, so
+//! they cannot collide with fetched ones.
+//!
+//! Hidden transactions (e.g. card replacement fees) stay in the database but
+//! are left out of listings and analysis unless
+//! [`FilterOptions::include_hidden`](super::FilterOptions::include_hidden) is set.
+
+use color_eyre::eyre::{Context, Result, bail};
+use rusqlite::params;
+
+use super::{Transaction, TransactionManager};
+
+impl TransactionManager {
+    /// Add a transaction entered by hand to the active profile
+    ///
+    /// The ID of `transaction` is ignored and a new negative one assigned.
+    /// Returns the stored transaction.
+    pub fn add_manual(&self, transaction: &Transaction) -> Result<Transaction> {
+        if transaction.merchant.trim().is_empty() {
+            bail!("Merchant of a manual transaction must not be empty");
+        }
+        let profile = self.profile();
+        let mut conn = self.db.write();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR IGNORE INTO profiles (name) VALUES (?)",
+            [&profile],
+        )?;
+        // IDs are unique across profiles, so count down from the lowest one
+        let id: i64 = tx.query_row(
+            "SELECT MIN(COALESCE(MIN(id), 0), 0) - 1 FROM transactions",
+            [],
+            |row| row.get(0),
+        )?;
+        let manual = Transaction {
+            id,
+            merchant: transaction.merchant.trim().to_string(),
+            raw: None,
+            note: None,
+            tags: vec![],
+            manual: true,
+            ..transaction.clone()
+        };
+        TransactionManager::insert_row(
+            &mut TransactionManager::prepare_insert(&tx)?,
+            id,
+            &manual,
+            &profile,
+        )
+        .context("Failed to insert manual transaction")?;
+        tx.commit()?;
+        Ok(manual)
+    }
+
+    /// Delete a manual transaction of the active profile and its annotations
+    ///
+    /// Returns `false` if there is no such manual transaction; fetched
+    /// transactions can only be hidden.
+    pub fn delete_manual(&self, id: i64) -> Result<bool> {
+        let mut conn = self.db.write();
+        let tx = conn.transaction()?;
+        let deleted = tx.execute(
+            "DELETE FROM transactions WHERE id = ? AND id < 0 AND profile = ?",
+            params![id, self.profile()],
+        )?;
+        if deleted == 0 {
+            return Ok(false);
+        }
+        tx.execute(
+            "DELETE FROM transaction_notes WHERE transaction_id = ?",
+            [id],
+        )?;
+        tx.execute(
+            "DELETE FROM transaction_tags WHERE transaction_id = ?",
+            [id],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Hide or unhide a transaction of the active profile
+    ///
+    /// Returns `false` if there is no such transaction.
+    pub fn set_hidden(&self, id: i64, hidden: bool) -> Result<bool> {
+        let updated = self.db.write().execute(
+            "UPDATE transactions SET hidden = ? WHERE id = ? AND profile = ?",
+            params![hidden, id, self.profile()],
+        )?;
+        Ok(updated > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::{
+        fetcher::test_utils::get_mock_data,
+        money::Money,
+        transactions::{FilterOptions, TransactionKind},
+    };
+
+    #[test]
+    fn test_manual_transactions() {
+        let manager = TransactionManager::new(None).unwrap();
+        manager.insert(&get_mock_data(10)).unwrap();
+        let count = manager.fetch_count().unwrap();
+
+        let cash = Transaction::new(
+            Money::from_cents(-800),
+            "煎饼摊".to_string(),
+            Transaction::parse_to_fixed_utc_plus8("2025-03-01 08:00:00", "%Y-%m-%d %H:%M:%S")
+                .unwrap(),
+        );
+        let first = manager.add_manual(&cash).unwrap();
+        let second = manager
+            .for_profile("roommate")
+            .unwrap()
+            .add_manual(&cash)
+            .unwrap();
+        assert_eq!((first.id, second.id), (-1, -2));
+        assert_eq!(manager.fetch_count().unwrap(), count + 1);
+
+        let stored = manager
+            .fetch_filtered(&FilterOptions::default().merchant("煎饼摊"))
+            .unwrap();
+        assert_eq!(stored.len(), 1);
+        assert!(stored[0].manual);
+        assert_eq!(stored[0].kind, TransactionKind::Expense);
+        assert_eq!(stored[0].time, cash.time);
+        assert!(!manager.fetch_all().unwrap()[0].manual);
+
+        // fetched transactions and other profiles' ones cannot be deleted
+        let fetched = manager.fetch_all().unwrap()[0].id;
+        assert!(!manager.delete_manual(fetched).unwrap());
+        assert!(!manager.delete_manual(second.id).unwrap());
+        manager.set_tags(first.id, &["cash"]).unwrap();
+        assert!(manager.delete_manual(first.id).unwrap());
+        assert_eq!(manager.fetch_count().unwrap(), count);
+        // its annotations are deleted with it
+        let tags: i64 = manager
+            .db
+            .read()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM transaction_tags", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(tags, 0);
+
+        assert_eq!(manager.add_manual(&cash).unwrap().id, -3);
+        assert!(manager.add_manual(&Transaction::default()).is_err());
+    }
+
+    #[test]
+    fn test_hidden_transactions() {
+        let manager = TransactionManager::new(None).unwrap();
+        let data = get_mock_data(10);
+        manager.insert(&data).unwrap();
+        let count = manager.fetch_count().unwrap();
+        let id = manager.fetch_all().unwrap()[0].id;
+
+        assert!(manager.set_hidden(id, true).unwrap());
+        assert_eq!(manager.fetch_count().unwrap(), count - 1);
+        assert!(manager.fetch_all().unwrap().iter().all(|t| t.id != id));
+        let all = manager
+            .fetch_filtered(&FilterOptions::default().include_hidden())
+            .unwrap();
+        assert!(all.iter().find(|t| t.id == id).unwrap().hidden);
+
+        // fetching it again keeps it hidden
+        manager.insert(&data).unwrap();
+        assert_eq!(manager.fetch_count().unwrap(), count - 1);
+
+        assert!(manager.set_hidden(id, false).unwrap());
+        assert_eq!(manager.fetch_count().unwrap(), count);
+        assert!(!manager.set_hidden(-1, true).unwrap());
+    }
+}
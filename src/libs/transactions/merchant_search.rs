@@ -0,0 +1,169 @@
+//! Prefix, substring and fuzzy matching of merchant names.
+//!
+//! Every merchant name seen in `transactions` is recorded once in the
+//! `merchants` table, which has an FTS5 trigram index (`merchant_search`).
+//! Matching runs against those few hundred names, and the transactions are
+//! then selected with `merchant IN (...)`.
+
+use rusqlite::{Connection, ToSql, functions::FunctionFlags};
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
+
+/// How [`FilterOptions::merchant`](super::FilterOptions::merchant) is matched
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Display, EnumString,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum MerchantMatch {
+    /// The whole name
+    #[default]
+    Exact,
+    /// The start of the name
+    Prefix,
+    /// Anywhere in the name
+    Substring,
+    /// Anywhere in the name, in the pinyin initials of the name (`st` for 食堂),
+    /// or with a typo every three characters
+    Fuzzy,
+}
+
+/// Shortest query the trigram index can look up
+const TRIGRAM_LEN: usize = 3;
+
+impl MerchantMatch {
+    /// Condition on the `merchant` column selecting names matching the query
+    pub(super) fn condition(&self, query: &str) -> (&'static str, Box<dyn ToSql>) {
+        match self {
+            MerchantMatch::Exact => ("merchant = ?", Box::new(query.to_string())),
+            MerchantMatch::Prefix => (
+                "merchant IN (SELECT name FROM merchants WHERE instr(name, ?) = 1)",
+                Box::new(query.to_string()),
+            ),
+            MerchantMatch::Substring if query.chars().count() >= TRIGRAM_LEN => (
+                "merchant IN (SELECT name FROM merchant_search WHERE merchant_search MATCH ?)",
+                // a quoted FTS5 string is matched literally
+                Box::new(format!("\"{}\"", query.replace('"', "\"\""))),
+            ),
+            MerchantMatch::Substring => (
+                "merchant IN (SELECT name FROM merchants WHERE instr(name, ?) > 0)",
+                Box::new(query.to_string()),
+            ),
+            MerchantMatch::Fuzzy => (
+                "merchant IN (SELECT name FROM merchants WHERE merchant_fuzzy_match(name, ?))",
+                Box::new(query.to_string()),
+            ),
+        }
+    }
+}
+
+/// Register the SQL functions used by [`MerchantMatch::condition`]
+pub(super) fn register_functions(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        "merchant_fuzzy_match",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(fuzzy_match(&ctx.get::<String>(0)?, &ctx.get::<String>(1)?)),
+    )
+}
+
+/// Whether the merchant name matches the query as described on [`MerchantMatch::Fuzzy`]
+pub fn fuzzy_match(name: &str, query: &str) -> bool {
+    let name = name.to_lowercase();
+    let query = query.trim().to_lowercase();
+    if name.contains(&query) {
+        return true;
+    }
+    if query.chars().all(|c| c.is_ascii_alphanumeric()) && pinyin_initials(&name).contains(&query) {
+        return true;
+    }
+
+    let max_edits = query.chars().count() / 3;
+    if max_edits == 0 {
+        return false;
+    }
+    // compare against every part of the name as long as the query
+    let name: Vec<char> = name.chars().collect();
+    let window = query.chars().count().min(name.len());
+    name.windows(window.max(1))
+        .any(|part| strsim::levenshtein(&part.iter().collect::<String>(), &query) <= max_edits)
+}
+
+/// First letter of the pinyin of each character, `st` for 食堂
+///
+/// Characters outside the first level of GB2312, which is sorted by pinyin
+/// and covers the common characters, are kept as is.
+pub fn pinyin_initials(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if c.is_ascii() {
+                return c.to_ascii_lowercase();
+            }
+            let mut buf = [0; 4];
+            let (bytes, _, unmappable) = encoding_rs::GBK.encode(c.encode_utf8(&mut buf));
+            if unmappable || bytes.len() != 2 {
+                return c;
+            }
+            let code = u16::from_be_bytes([bytes[0], bytes[1]]);
+            PINYIN_BOUNDARIES
+                .iter()
+                .rev()
+                .find(|(start, _)| code >= *start)
+                .filter(|_| code <= GB2312_LEVEL1_END)
+                .map_or(c, |(_, initial)| *initial)
+        })
+        .collect()
+}
+
+/// First GB2312 code of each pinyin initial (no words start with i, u or v)
+const PINYIN_BOUNDARIES: [(u16, char); 23] = [
+    (0xB0A1, 'a'),
+    (0xB0C5, 'b'),
+    (0xB2C1, 'c'),
+    (0xB4EE, 'd'),
+    (0xB6EA, 'e'),
+    (0xB7A2, 'f'),
+    (0xB8C1, 'g'),
+    (0xB9FE, 'h'),
+    (0xBBF7, 'j'),
+    (0xBFA6, 'k'),
+    (0xC0AC, 'l'),
+    (0xC2E8, 'm'),
+    (0xC4C3, 'n'),
+    (0xC5B6, 'o'),
+    (0xC5BE, 'p'),
+    (0xC6DA, 'q'),
+    (0xC8BB, 'r'),
+    (0xC8F6, 's'),
+    (0xCBFA, 't'),
+    (0xCDDA, 'w'),
+    (0xCEF4, 'x'),
+    (0xD1B9, 'y'),
+    (0xD4D1, 'z'),
+];
+
+/// Last code of the first level of GB2312
+const GB2312_LEVEL1_END: u16 = 0xD7F9;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pinyin_initials() {
+        assert_eq!(pinyin_initials("梧桐苑食堂"), "wtyst");
+        assert_eq!(pinyin_initials("康桥苑一楼A区"), "kqyylaq");
+        // outside GB2312 level 1
+        assert_eq!(pinyin_initials("㵘"), "㵘");
+    }
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert!(fuzzy_match("梧桐苑食堂", "食堂"));
+        assert!(fuzzy_match("梧桐苑食堂", "WTY"));
+        // one typo in three characters
+        assert!(fuzzy_match("梧桐苑食堂", "梧同苑"));
+        assert!(!fuzzy_match("梧桐苑食堂", "梧同院"));
+        assert!(!fuzzy_match("梧桐苑食堂", "超市"));
+    }
+}
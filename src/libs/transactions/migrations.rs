@@ -0,0 +1,615 @@
+//! Schema migrations for the local cache database.
+//!
+//! The schema version is stored in SQLite's `user_version` pragma. Migration
+//! `n` (1-based position in [`MIGRATIONS`]) upgrades a database from version
+//! `n - 1` to version `n`, so new steps must only ever be appended.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, FixedOffset};
+use color_eyre::eyre::{Context, Result, bail};
+use rusqlite::{Connection, params};
+use tracing::info;
+
+use super::Transaction;
+
+pub(super) struct Migration {
+    /// Short description, used in logs and error messages
+    description: &'static str,
+    /// Applies the migration inside the given transaction
+    up: fn(&rusqlite::Transaction) -> rusqlite::Result<()>,
+}
+
+pub(super) const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "create transactions and cookies tables",
+        up: initial_schema,
+    },
+    Migration {
+        description: "add transaction kind",
+        up: add_transaction_kind,
+    },
+    Migration {
+        description: "add balance, terminal, type and raw row",
+        up: add_raw_row_fields,
+    },
+    Migration {
+        description: "re-key transactions with stable IDs",
+        up: rekey_stable_ids,
+    },
+    Migration {
+        description: "store amounts as integer cents",
+        up: amounts_in_cents,
+    },
+    Migration {
+        description: "add account profiles",
+        up: add_profiles,
+    },
+    Migration {
+        description: "add merchant search index",
+        up: add_merchant_index,
+    },
+    Migration {
+        description: "store times as indexed Unix timestamps",
+        up: epoch_timestamps,
+    },
+    Migration {
+        description: "add settings",
+        up: add_settings,
+    },
+    Migration {
+        description: "add notes and tags",
+        up: add_annotations,
+    },
+    Migration {
+        description: "add hidden flag",
+        up: add_hidden_flag,
+    },
+    Migration {
+        description: "add conflict quarantine",
+        up: add_conflicts,
+    },
+    Migration {
+        description: "add sync state",
+        up: add_sync_state,
+    },
+    Migration {
+        description: "add fetch checkpoints",
+        up: add_fetch_checkpoints,
+    },
+    Migration {
+        description: "add ID counters to fetch checkpoints",
+        up: add_checkpoint_counters,
+    },
+];
+
+/// The schema version this binary writes
+pub(super) const LATEST_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Read the schema version of the database
+pub(super) fn schema_version(conn: &Connection) -> rusqlite::Result<u32> {
+    conn.pragma_query_value(None, "user_version", |row| row.get(0))
+}
+
+/// Upgrade the database to [`LATEST_VERSION`]
+///
+/// Each migration runs in its own transaction together with the version bump,
+/// so an interrupted upgrade leaves the database at the last completed version.
+///
+/// Refuses to touch a database whose version is newer than this binary knows about.
+pub(super) fn migrate(conn: &mut Connection) -> Result<()> {
+    let current = schema_version(conn).context("Failed to read database schema version")?;
+
+    if current > LATEST_VERSION {
+        bail!(
+            "Database schema version {} is newer than the latest version {} supported by this build. \
+            Please upgrade xjtu_mealflow.",
+            current,
+            LATEST_VERSION
+        );
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let version = index as u32 + 1;
+        let tx = conn.transaction()?;
+        (migration.up)(&tx)
+            .and_then(|_| tx.pragma_update(None, "user_version", version))
+            .with_context(|| {
+                format!(
+                    "Failed to migrate database to version {} ({})",
+                    version, migration.description
+                )
+            })?;
+        tx.commit()?;
+        info!(
+            "Migrated database to version {} ({})",
+            version, migration.description
+        );
+    }
+
+    Ok(())
+}
+
+/// Version 1: the schema used before migrations were introduced
+///
+/// Uses `IF NOT EXISTS` so databases created by older builds (which are at
+/// version 0 but already have these tables) are adopted as-is.
+fn initial_schema(tx: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS transactions (
+            id INTEGER PRIMARY KEY,
+            time TEXT NOT NULL,
+            amount REAL NOT NULL,
+            merchant TEXT NOT NULL
+        );
+        CREATE TRIGGER IF NOT EXISTS prevent_transaction_conflict
+            BEFORE INSERT ON transactions
+            FOR EACH ROW
+            BEGIN
+                SELECT CASE
+                WHEN EXISTS (
+                    SELECT 1 FROM transactions
+                    WHERE id = NEW.id
+                    AND time = NEW.time
+                    AND amount = NEW.amount
+                    AND merchant = NEW.merchant
+                ) THEN
+                    RAISE(IGNORE)  -- 完全相同的记录则静默跳过
+                WHEN EXISTS (
+                    SELECT 1 FROM transactions
+                    WHERE id = NEW.id
+                ) THEN
+                    RAISE(ABORT, 'Conflict: Existing transaction with different data')  -- ID存在但数据不同时终止
+                END;
+            END;
+        CREATE TABLE IF NOT EXISTS cookies (
+            account TEXT PRIMARY KEY,
+            cookie TEXT NOT NULL
+        );",
+    )
+}
+
+/// Version 2: record whether a transaction is an expense, top-up, refund or subsidy
+///
+/// Older builds only stored expenses, so existing rows default to `expense`.
+fn add_transaction_kind(tx: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE transactions ADD COLUMN kind TEXT NOT NULL DEFAULT 'expense';")
+}
+
+/// Version 3: keep the extra fields the card system returns for each row
+fn add_raw_row_fields(tx: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE transactions ADD COLUMN balance REAL;
+        ALTER TABLE transactions ADD COLUMN terminal TEXT;
+        ALTER TABLE transactions ADD COLUMN tran_type TEXT;
+        ALTER TABLE transactions ADD COLUMN raw TEXT;",
+    )
+}
+
+/// Version 4: replace IDs derived from `std::hash::DefaultHasher` with
+/// [`Transaction::stable_id`]
+///
+/// The rows are copied into a fresh table so old and new IDs never clash
+/// while re-keying. The conflict trigger lives on the table and is recreated.
+fn rekey_stable_ids(tx: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE transactions_rekeyed (
+            id INTEGER PRIMARY KEY,
+            time TEXT NOT NULL,
+            amount REAL NOT NULL,
+            merchant TEXT NOT NULL,
+            kind TEXT NOT NULL DEFAULT 'expense',
+            balance REAL,
+            terminal TEXT,
+            tran_type TEXT,
+            raw TEXT
+        );",
+    )?;
+
+    {
+        let mut select = tx.prepare(
+            "SELECT id, time, amount, merchant, kind, balance, terminal, tran_type, raw
+            FROM transactions ORDER BY time DESC, id",
+        )?;
+        let mut insert = tx.prepare(
+            "INSERT INTO transactions_rekeyed
+            (id, time, amount, merchant, kind, balance, terminal, tran_type, raw)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )?;
+        let mut seen: HashMap<(i64, i64, String), u32> = HashMap::new();
+        let mut rows = select.query([])?;
+        while let Some(row) = rows.next()? {
+            let time: DateTime<FixedOffset> = row.get(1)?;
+            let amount: f64 = row.get(2)?;
+            let merchant: String = row.get(3)?;
+            let cents = (amount * 100.0).round() as i64;
+
+            let seq = seen
+                .entry((time.timestamp(), cents, merchant.clone()))
+                .or_insert(0);
+            let id = Transaction::stable_id(time.timestamp(), cents, &merchant, *seq);
+            *seq += 1;
+
+            insert.execute(params![
+                id,
+                time,
+                amount,
+                merchant,
+                row.get::<_, String>(4)?,
+                row.get::<_, Option<f64>>(5)?,
+                row.get::<_, Option<String>>(6)?,
+                row.get::<_, Option<String>>(7)?,
+                row.get::<_, Option<String>>(8)?,
+            ])?;
+        }
+    }
+
+    tx.execute_batch(
+        "DROP TABLE transactions;
+        ALTER TABLE transactions_rekeyed RENAME TO transactions;",
+    )?;
+    tx.execute_batch(CONFLICT_TRIGGER)
+}
+
+/// Version 5: store amounts and balances as integer cents instead of `REAL` yuan
+///
+/// SQLite cannot change a column type in place, so the table is rebuilt and
+/// the conflict trigger recreated.
+fn amounts_in_cents(tx: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE transactions_cents (
+            id INTEGER PRIMARY KEY,
+            time TEXT NOT NULL,
+            amount INTEGER NOT NULL,
+            merchant TEXT NOT NULL,
+            kind TEXT NOT NULL DEFAULT 'expense',
+            balance INTEGER,
+            terminal TEXT,
+            tran_type TEXT,
+            raw TEXT
+        );
+        INSERT INTO transactions_cents
+            SELECT id, time, CAST(ROUND(amount * 100) AS INTEGER), merchant, kind,
+                CAST(ROUND(balance * 100) AS INTEGER), terminal, tran_type, raw
+            FROM transactions;
+        DROP TABLE transactions;
+        ALTER TABLE transactions_cents RENAME TO transactions;",
+    )?;
+    tx.execute_batch(CONFLICT_TRIGGER)
+}
+
+/// Version 6: named account profiles, each with its own credentials and transactions
+///
+/// The single row of the old `cookies` table becomes the default profile, and
+/// existing transactions are assigned to it.
+fn add_profiles(tx: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE profiles (
+            name TEXT PRIMARY KEY,
+            account TEXT NOT NULL DEFAULT '',
+            cookie TEXT NOT NULL DEFAULT ''
+        );
+        INSERT INTO profiles (name, account, cookie)
+            SELECT 'default', account, cookie FROM cookies LIMIT 1;
+        INSERT OR IGNORE INTO profiles (name) VALUES ('default');
+        DROP TABLE cookies;
+        ALTER TABLE transactions ADD COLUMN profile TEXT NOT NULL DEFAULT 'default';
+        CREATE INDEX transactions_profile ON transactions (profile);",
+    )
+}
+
+/// Version 7: a trigram FTS5 index over the distinct merchant names, for
+/// prefix, substring and fuzzy merchant search
+///
+/// `merchants` holds each name once and feeds `merchant_search`;
+/// [`MERCHANT_TRIGGER`] records the merchant of every inserted transaction.
+fn add_merchant_index(tx: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE merchants (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE
+        );
+        CREATE VIRTUAL TABLE merchant_search USING fts5(
+            name, content = 'merchants', content_rowid = 'id', tokenize = 'trigram'
+        );
+        CREATE TRIGGER merchants_index AFTER INSERT ON merchants BEGIN
+            INSERT INTO merchant_search (rowid, name) VALUES (NEW.id, NEW.name);
+        END;
+        INSERT INTO merchants (name) SELECT DISTINCT merchant FROM transactions ORDER BY merchant;",
+    )?;
+    tx.execute_batch(MERCHANT_TRIGGER)
+}
+
+/// Version 8: store times as integer Unix timestamps with the UTC offset they
+/// were recorded in, and index the columns filters use
+///
+/// Text times only compared correctly while every row used the same format.
+/// The table is rebuilt, recreating its triggers.
+fn epoch_timestamps(tx: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE transactions_epoch (
+            id INTEGER PRIMARY KEY,
+            time INTEGER NOT NULL,
+            utc_offset INTEGER NOT NULL,
+            amount INTEGER NOT NULL,
+            merchant TEXT NOT NULL,
+            kind TEXT NOT NULL DEFAULT 'expense',
+            balance INTEGER,
+            terminal TEXT,
+            tran_type TEXT,
+            raw TEXT,
+            profile TEXT NOT NULL DEFAULT 'default'
+        );",
+    )?;
+
+    {
+        let mut select = tx.prepare(
+            "SELECT id, time, amount, merchant, kind, balance, terminal, tran_type, raw, profile
+            FROM transactions",
+        )?;
+        let mut insert = tx.prepare(
+            "INSERT INTO transactions_epoch
+            (id, time, utc_offset, amount, merchant, kind, balance, terminal, tran_type, raw, profile)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )?;
+        let mut rows = select.query([])?;
+        while let Some(row) = rows.next()? {
+            // parses both the `+08:00` and legacy ` +08:00` suffixes
+            let time: DateTime<FixedOffset> = row.get(1)?;
+            insert.execute(params![
+                row.get::<_, i64>(0)?,
+                time.timestamp(),
+                time.offset().local_minus_utc(),
+                row.get::<_, i64>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, Option<i64>>(5)?,
+                row.get::<_, Option<String>>(6)?,
+                row.get::<_, Option<String>>(7)?,
+                row.get::<_, Option<String>>(8)?,
+                row.get::<_, String>(9)?,
+            ])?;
+        }
+    }
+
+    tx.execute_batch(
+        "DROP TABLE transactions;
+        ALTER TABLE transactions_epoch RENAME TO transactions;
+        CREATE INDEX transactions_profile_time ON transactions (profile, time);
+        CREATE INDEX transactions_merchant ON transactions (merchant);
+        CREATE INDEX transactions_amount ON transactions (amount);",
+    )?;
+    tx.execute_batch(CONFLICT_TRIGGER)?;
+    tx.execute_batch(MERCHANT_TRIGGER)
+}
+
+/// Version 9: key-value settings, holding the credential encryption salt and
+/// key check
+fn add_settings(tx: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );",
+    )
+}
+
+/// Version 10: user notes and tags, keyed by transaction ID
+///
+/// They live in side tables so that re-fetching or clearing transactions
+/// never loses them; a transaction fetched again gets its annotations back.
+fn add_annotations(tx: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE transaction_notes (
+            transaction_id INTEGER PRIMARY KEY,
+            note TEXT NOT NULL
+        );
+        CREATE TABLE transaction_tags (
+            transaction_id INTEGER NOT NULL,
+            tag TEXT NOT NULL,
+            PRIMARY KEY (transaction_id, tag)
+        );
+        CREATE INDEX transaction_tags_tag ON transaction_tags (tag);",
+    )
+}
+
+/// Version 11: hide transactions from listings and analysis without deleting them
+///
+/// Re-fetching a hidden transaction is ignored by the conflict trigger, so it
+/// stays hidden.
+fn add_hidden_flag(tx: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE transactions ADD COLUMN hidden INTEGER NOT NULL DEFAULT 0;")
+}
+
+/// Version 12: fetched transactions conflicting with stored ones, kept for
+/// review instead of failing the import
+///
+/// Both versions are stored as JSON. Resolved conflicts are kept so that the
+/// same conflict seen again on a later fetch is not reported twice.
+fn add_conflicts(tx: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE conflicts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            transaction_id INTEGER NOT NULL,
+            profile TEXT NOT NULL,
+            existing TEXT NOT NULL,
+            incoming TEXT NOT NULL,
+            detected_at INTEGER NOT NULL,
+            resolution TEXT,
+            UNIQUE (transaction_id, incoming)
+        );
+        CREATE INDEX conflicts_profile ON conflicts (profile);",
+    )
+}
+
+/// Version 13: outcome of the last fetch of each profile
+///
+/// The newest stored transaction is not recorded here, it is read from
+/// `transactions` so that it never goes stale.
+fn add_sync_state(tx: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE sync_state (
+            profile TEXT PRIMARY KEY,
+            last_success INTEGER,
+            last_error TEXT,
+            last_error_at INTEGER
+        );",
+    )
+}
+
+/// Version 14: where an interrupted fetch of each profile can resume
+///
+/// Times are stored like in `transactions`, as Unix timestamps with their
+/// UTC offset in seconds.
+fn add_fetch_checkpoints(tx: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE fetch_checkpoints (
+            profile TEXT PRIMARY KEY,
+            end_time INTEGER NOT NULL,
+            end_offset INTEGER NOT NULL,
+            page INTEGER NOT NULL,
+            before_time INTEGER,
+            before_offset INTEGER,
+            saved_at INTEGER NOT NULL
+        );",
+    )
+}
+
+/// Version 15: the ID counters a resumed fetch numbers on from
+///
+/// Stored as a JSON array of [`SeqCounter`](super::SeqCounter), `NULL` for
+/// none.
+fn add_checkpoint_counters(tx: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE fetch_checkpoints ADD COLUMN counters TEXT;")
+}
+
+/// Trigger that records every merchant name in `merchants` for searching
+///
+/// Like [`CONFLICT_TRIGGER`], migrations that rebuild `transactions` recreate it.
+const MERCHANT_TRIGGER: &str = "CREATE TRIGGER record_merchant
+    AFTER INSERT ON transactions
+    FOR EACH ROW
+    BEGIN
+        INSERT OR IGNORE INTO merchants (name) VALUES (NEW.merchant);
+    END;";
+
+/// Trigger that silently skips re-inserting an identical transaction and
+/// aborts when a different transaction reuses an existing ID
+///
+/// Triggers are dropped together with their table, so migrations that rebuild
+/// `transactions` recreate it with this statement.
+const CONFLICT_TRIGGER: &str = "CREATE TRIGGER prevent_transaction_conflict
+    BEFORE INSERT ON transactions
+    FOR EACH ROW
+    BEGIN
+        SELECT CASE
+        WHEN EXISTS (
+            SELECT 1 FROM transactions
+            WHERE id = NEW.id
+            AND time = NEW.time
+            AND amount = NEW.amount
+            AND merchant = NEW.merchant
+        ) THEN
+            RAISE(IGNORE)
+        WHEN EXISTS (
+            SELECT 1 FROM transactions
+            WHERE id = NEW.id
+        ) THEN
+            RAISE(ABORT, 'Conflict: Existing transaction with different data')
+        END;
+    END;";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fresh_database_is_at_latest_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), LATEST_VERSION);
+
+        // running again is a no-op
+        migrate(&mut conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), LATEST_VERSION);
+    }
+
+    #[test]
+    fn legacy_database_is_adopted() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE transactions (
+                id INTEGER PRIMARY KEY,
+                time TEXT NOT NULL,
+                amount REAL NOT NULL,
+                merchant TEXT NOT NULL
+            );
+            CREATE TABLE cookies (
+                account TEXT PRIMARY KEY,
+                cookie TEXT NOT NULL
+            );
+            INSERT INTO transactions VALUES (1, '2025-03-01 12:00:00 +08:00', -10.5, '寿司');
+            INSERT INTO cookies VALUES ('123456', 'hallticket=abc');",
+        )
+        .unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), 0);
+
+        migrate(&mut conn).unwrap();
+
+        assert_eq!(schema_version(&conn).unwrap(), LATEST_VERSION);
+        let (count, id, amount): (i64, i64, i64) = conn
+            .query_row(
+                "SELECT COUNT(*), MAX(id), MAX(amount) FROM transactions",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(count, 1);
+        // re-keyed with the stable ID scheme
+        assert_eq!(id, Transaction::stable_id(1740801600, -1050, "寿司", 0));
+        // converted to cents
+        assert_eq!(amount, -1050);
+        // credentials and transactions belong to the default profile
+        let (profile, account): (String, String) = conn
+            .query_row(
+                "SELECT p.name, p.account FROM transactions t JOIN profiles p ON p.name = t.profile",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(profile, "default");
+        assert_eq!(account, "123456");
+        // times are Unix timestamps, keeping the offset
+        let (time, utc_offset): (i64, i64) = conn
+            .query_row("SELECT time, utc_offset FROM transactions", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((time, utc_offset), (1740801600, 8 * 3600));
+        // nothing is hidden
+        let hidden: bool = conn
+            .query_row("SELECT hidden FROM transactions", [], |row| row.get(0))
+            .unwrap();
+        assert!(!hidden);
+        // existing merchants are searchable
+        let merchant: String = conn
+            .query_row(
+                "SELECT name FROM merchant_search WHERE name LIKE '寿%'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(merchant, "寿司");
+    }
+
+    #[test]
+    fn newer_database_is_refused() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", LATEST_VERSION + 1)
+            .unwrap();
+
+        let err = migrate(&mut conn).unwrap_err();
+        assert!(err.to_string().contains("newer"));
+        assert_eq!(schema_version(&conn).unwrap(), LATEST_VERSION + 1);
+    }
+}
@@ -0,0 +1,167 @@
+//! Connections to the local cache database.
+//!
+//! A file database is opened in WAL mode with one writer connection and a
+//! pool of read-only connections, so that readers (analysis queries, web
+//! requests) neither wait for each other nor for a fetch being inserted.
+//! SQLite only allows one writer at a time anyway, so writes go through a
+//! single connection behind a mutex.
+//!
+//! An in-memory database only exists for the connection that created it, so
+//! reads share the writer connection there.
+
+use std::{
+    ops::Deref,
+    path::PathBuf,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use color_eyre::eyre::{Context, Result};
+use rusqlite::{Connection, OpenFlags};
+
+use super::merchant_search;
+
+/// Maximum number of read connections kept open
+const MAX_READERS: u32 = 8;
+
+/// How long a connection waits for a lock held by another process, e.g. a
+/// backup taken while the TUI is fetching
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Opens the read-only connections of the pool
+#[derive(Debug)]
+pub(super) struct ReaderManager {
+    path: PathBuf,
+}
+
+impl r2d2::ManageConnection for ReaderManager {
+    type Connection = Connection;
+    type Error = rusqlite::Error;
+
+    fn connect(&self) -> rusqlite::Result<Connection> {
+        let conn = Connection::open_with_flags(
+            &self.path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        merchant_search::register_functions(&conn)?;
+        Ok(conn)
+    }
+
+    fn is_valid(&self, conn: &mut Connection) -> rusqlite::Result<()> {
+        conn.execute_batch("")
+    }
+
+    fn has_broken(&self, _conn: &mut Connection) -> bool {
+        false
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct Storage {
+    writer: Mutex<Connection>,
+    /// `None` for an in-memory database
+    readers: Option<r2d2::Pool<ReaderManager>>,
+}
+
+/// A connection for reading, either from the pool or the writer
+pub(super) enum ReadConnection<'a> {
+    Pooled(r2d2::PooledConnection<ReaderManager>),
+    Writer(MutexGuard<'a, Connection>),
+}
+
+impl Deref for ReadConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        match self {
+            ReadConnection::Pooled(conn) => conn,
+            ReadConnection::Writer(conn) => conn,
+        }
+    }
+}
+
+impl Storage {
+    /// Wrap the writer connection of an already migrated database
+    ///
+    /// For a file database, switches it to WAL mode and starts the reader pool.
+    pub(super) fn new(writer: Connection, db_path: Option<&PathBuf>) -> Result<Self> {
+        let readers = match db_path {
+            Some(path) => {
+                writer
+                    .pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))
+                    .context("Failed to enable WAL mode")?;
+                // durable at checkpoints, which is enough for a cache of server data
+                writer.pragma_update(None, "synchronous", "NORMAL")?;
+                writer.busy_timeout(BUSY_TIMEOUT)?;
+                let pool = r2d2::Pool::builder()
+                    .max_size(MAX_READERS)
+                    .min_idle(Some(1))
+                    .build(ReaderManager { path: path.clone() })
+                    .context("Failed to open read connections")?;
+                Some(pool)
+            }
+            None => None,
+        };
+        Ok(Storage {
+            writer: Mutex::new(writer),
+            readers,
+        })
+    }
+
+    /// The connection for writing
+    ///
+    /// Held until dropped, so keep it for one statement or transaction only.
+    pub(super) fn write(&self) -> MutexGuard<'_, Connection> {
+        self.writer.lock().unwrap()
+    }
+
+    /// A connection for reading, which does not wait for writes
+    pub(super) fn read(&self) -> Result<ReadConnection<'_>> {
+        Ok(match &self.readers {
+            Some(pool) => ReadConnection::Pooled(
+                pool.get()
+                    .context("Timed out waiting for a database connection")?,
+            ),
+            None => ReadConnection::Writer(self.write()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::{fetcher::test_utils::get_mock_data, transactions::TransactionManager};
+
+    #[test]
+    fn test_reads_do_not_wait_for_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = TransactionManager::new(Some(dir.path().join("transactions.db"))).unwrap();
+        let data = get_mock_data(10);
+        manager.insert(&data[..5].to_vec()).unwrap();
+
+        let journal_mode: String = manager
+            .db
+            .read()
+            .unwrap()
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+
+        // a long read keeps its snapshot while a write goes through
+        let reader = manager.db.read().unwrap();
+        reader.execute_batch("BEGIN").unwrap();
+        let count = |conn: &rusqlite::Connection| -> i64 {
+            conn.query_row("SELECT COUNT(*) FROM transactions", [], |row| row.get(0))
+                .unwrap()
+        };
+        let before = count(&reader);
+        manager.insert(&data).unwrap();
+        assert_eq!(count(&reader), before);
+        assert!(manager.fetch_count().unwrap() > before as u64);
+        reader.execute_batch("COMMIT").unwrap();
+        assert_eq!(count(&reader) as u64, manager.fetch_count().unwrap());
+
+        // read connections cannot write
+        assert!(reader.execute("DELETE FROM transactions", []).is_err());
+    }
+}
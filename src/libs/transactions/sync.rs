@@ -0,0 +1,546 @@
+//! Sync state of each profile, incremental and resumable fetching.
+//!
+//! Every fetch records its outcome in `sync_state`, so the UIs can show when
+//! the profile was last synced and why the last attempt failed. An
+//! incremental fetch starts from the newest stored transaction instead of a
+//! date picked by the user, and stops as soon as it reaches it.
+//!
+//! Fetched pages are stored as they arrive, each followed by a
+//! [`FetchCheckpoint`] in `fetch_checkpoints`. A fetch that fails halfway
+//! keeps what it stored and can be resumed from its checkpoint, which is
+//! removed once a fetch completes.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, FixedOffset, Local, TimeZone, Utc};
+use color_eyre::eyre::Result;
+use futures::StreamExt;
+use rusqlite::{OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use super::{InsertReport, SeqCounter, TransactionManager};
+use crate::{
+    libs::fetcher::{self, TransactionSource},
+    page::fetch::FetchProgress,
+};
+
+/// How far back an incremental fetch goes when nothing is stored yet
+pub const FIRST_SYNC_DAYS: i64 = 365;
+
+/// Outcome of the fetches of a profile
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncState {
+    /// When fetched transactions were last stored successfully
+    pub last_success: Option<DateTime<Utc>>,
+    /// Time of the newest fetched transaction stored
+    pub newest_transaction: Option<DateTime<FixedOffset>>,
+    /// Error of the last fetch, cleared by the next successful one
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+    /// Where the last fetch stopped, if it did not complete
+    pub checkpoint: Option<FetchCheckpoint>,
+}
+
+/// Where a fetch resumes, see [`fetcher::fetch_pages`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FetchCheckpoint {
+    /// Transactions at or before this time are not fetched
+    pub end_time: DateTime<FixedOffset>,
+    /// First page to request
+    pub page: u32,
+    /// Transactions newer than this are already stored and skipped, `None`
+    /// keeping all of them
+    pub before: Option<DateTime<FixedOffset>>,
+    /// ID counters of the last stored page, for sources that are not
+    /// newest first
+    #[serde(skip)]
+    pub counters: Vec<SeqCounter>,
+}
+
+impl FetchCheckpoint {
+    /// A fetch of everything newer than `end_time`, from the first page
+    pub fn start(end_time: DateTime<FixedOffset>) -> Self {
+        Self {
+            end_time,
+            page: 1,
+            before: None,
+            counters: Vec::new(),
+        }
+    }
+}
+
+fn from_timestamp(timestamp: Option<i64>) -> Option<DateTime<Utc>> {
+    timestamp.and_then(|t| Utc.timestamp_opt(t, 0).single())
+}
+
+fn from_timestamp_offset(time: i64, offset: i32) -> Option<DateTime<FixedOffset>> {
+    FixedOffset::east_opt(offset).and_then(|o| o.timestamp_opt(time, 0).single())
+}
+
+impl TransactionManager {
+    /// Sync state of the active profile
+    pub fn sync_state(&self) -> Result<SyncState> {
+        let profile = self.profile();
+        let conn = self.db.read()?;
+        let recorded: Option<(Option<i64>, Option<String>, Option<i64>)> = conn
+            .query_row(
+                "SELECT last_success, last_error, last_error_at FROM sync_state WHERE profile = ?",
+                [&profile],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+        let (last_success, last_error, last_error_at) = recorded.unwrap_or_default();
+
+        // manual transactions say nothing about what was fetched
+        let newest: Option<(i64, i32)> = conn
+            .query_row(
+                "SELECT time, utc_offset FROM transactions
+                WHERE profile = ? AND id >= 0 ORDER BY time DESC LIMIT 1",
+                [&profile],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let newest_transaction =
+            newest.and_then(|(time, offset)| from_timestamp_offset(time, offset));
+        drop(conn);
+
+        Ok(SyncState {
+            last_success: from_timestamp(last_success),
+            newest_transaction,
+            last_error,
+            last_error_at: from_timestamp(last_error_at),
+            checkpoint: self.fetch_checkpoint()?,
+        })
+    }
+
+    /// Where an incremental fetch of the active profile stops
+    ///
+    /// One second before the newest stored transaction, so that all
+    /// transactions of that second are fetched again together and keep their
+    /// IDs. Without stored transactions, [`FIRST_SYNC_DAYS`] ago.
+    ///
+    /// An interrupted fetch only stored its newest pages, so its end time is
+    /// used instead if earlier.
+    pub fn incremental_start(&self) -> Result<DateTime<FixedOffset>> {
+        let state = self.sync_state()?;
+        let start = match state.newest_transaction {
+            Some(newest) => newest - Duration::seconds(1),
+            None => Local::now().fixed_offset() - Duration::days(FIRST_SYNC_DAYS),
+        };
+        Ok(match state.checkpoint {
+            Some(checkpoint) => start.min(checkpoint.end_time),
+            None => start,
+        })
+    }
+
+    /// Checkpoint of the interrupted fetch of the active profile, if any
+    pub fn fetch_checkpoint(&self) -> Result<Option<FetchCheckpoint>> {
+        let checkpoint = self
+            .db
+            .read()?
+            .query_row(
+                "SELECT end_time, end_offset, page, before_time, before_offset, counters
+                FROM fetch_checkpoints WHERE profile = ?",
+                [self.profile()],
+                |row| {
+                    let page = row.get(2)?;
+                    let before = match (row.get(3)?, row.get(4)?) {
+                        (Some(time), Some(offset)) => from_timestamp_offset(time, offset),
+                        _ => None,
+                    };
+                    let counters = row
+                        .get::<_, Option<String>>(5)?
+                        .and_then(|json| serde_json::from_str(&json).ok())
+                        .unwrap_or_default();
+                    Ok(
+                        from_timestamp_offset(row.get(0)?, row.get(1)?).map(|end_time| {
+                            FetchCheckpoint {
+                                end_time,
+                                page,
+                                before,
+                                counters,
+                            }
+                        }),
+                    )
+                },
+            )
+            .optional()?;
+        Ok(checkpoint.flatten())
+    }
+
+    fn save_checkpoint(&self, checkpoint: &FetchCheckpoint) -> Result<()> {
+        self.db.write().execute(
+            "INSERT OR REPLACE INTO fetch_checkpoints
+            (profile, end_time, end_offset, page, before_time, before_offset, counters, saved_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                self.profile(),
+                checkpoint.end_time.timestamp(),
+                checkpoint.end_time.offset().local_minus_utc(),
+                checkpoint.page,
+                checkpoint.before.map(|t| t.timestamp()),
+                checkpoint.before.map(|t| t.offset().local_minus_utc()),
+                (!checkpoint.counters.is_empty())
+                    .then(|| serde_json::to_string(&checkpoint.counters))
+                    .transpose()?,
+                Utc::now().timestamp(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Fetch from a checkpoint, storing every page as it arrives
+    ///
+    /// After each page, the checkpoint to resume from is saved, see
+    /// [`TransactionManager::fetch_checkpoint`]. On success it is removed and
+    /// the success recorded. Errors are returned without being recorded, so
+    /// that callers can tell a cancelled fetch from a failed one.
+    pub async fn fetch_and_store<F>(
+        &self,
+        checkpoint: FetchCheckpoint,
+        source: Arc<dyn TransactionSource>,
+        cancel: CancellationToken,
+        mut progress_cb: F,
+    ) -> Result<InsertReport>
+    where
+        F: FnMut(FetchProgress) -> Result<()>,
+    {
+        let mut report = InsertReport::default();
+        progress_cb(FetchProgress::default())?;
+
+        let mut pages = std::pin::pin!(fetcher::fetch_pages(checkpoint, source, cancel));
+        while let Some(page) = pages.next().await {
+            let page = page?;
+            report += self.insert(&page.transactions)?;
+            self.save_checkpoint(&page.checkpoint)?;
+            progress_cb(page.progress)?;
+        }
+
+        self.record_sync_success()?;
+        Ok(report)
+    }
+
+    /// Record a completed fetch of the active profile
+    pub fn record_sync_success(&self) -> Result<()> {
+        let profile = self.profile();
+        let mut conn = self.db.write();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO sync_state (profile, last_success) VALUES (?, ?)
+            ON CONFLICT(profile) DO UPDATE SET
+                last_success = excluded.last_success,
+                last_error = NULL,
+                last_error_at = NULL",
+            params![profile, Utc::now().timestamp()],
+        )?;
+        tx.execute(
+            "DELETE FROM fetch_checkpoints WHERE profile = ?",
+            [&profile],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Record a failed fetch of the active profile
+    pub fn record_sync_error(&self, error: &str) -> Result<()> {
+        self.db.write().execute(
+            "INSERT INTO sync_state (profile, last_error, last_error_at) VALUES (?, ?, ?)
+            ON CONFLICT(profile) DO UPDATE SET
+                last_error = excluded.last_error,
+                last_error_at = excluded.last_error_at",
+            params![self.profile(), error, Utc::now().timestamp()],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::{
+        fetcher::{
+            FetchError, FetchLimits, MockMealFetcher, SourceCapabilities,
+            test_utils::{fetch, get_mock_data},
+        },
+        money::Money,
+        transactions::OFFSET_UTC_PLUS8,
+    };
+    use color_eyre::eyre::eyre;
+    use futures::{FutureExt, future::BoxFuture};
+
+    /// Mock data failing from a page on, like a session expiring halfway
+    #[derive(Debug, Clone)]
+    struct FailingSource {
+        inner: MockMealFetcher,
+        fail_from: u32,
+        newest_first: bool,
+    }
+
+    impl TransactionSource for FailingSource {
+        fn name(&self) -> &'static str {
+            "failing mock data"
+        }
+
+        fn capabilities(&self) -> SourceCapabilities {
+            SourceCapabilities {
+                newest_first: self.newest_first,
+                ..self.inner.capabilities()
+            }
+        }
+
+        fn with_credentials(&self, _account: &str, _cookie: &str) -> Arc<dyn TransactionSource> {
+            Arc::new(self.clone())
+        }
+
+        fn fetch_page(&self, page: u32) -> BoxFuture<'_, Result<String>> {
+            if page >= self.fail_from {
+                async { Err(eyre!("session expired")) }.boxed()
+            } else {
+                self.inner.fetch_page(page)
+            }
+        }
+    }
+
+    #[test]
+    fn test_sync_state() {
+        let manager = TransactionManager::new(None).unwrap();
+        assert_eq!(manager.sync_state().unwrap(), SyncState::default());
+        let first_sync = manager.incremental_start().unwrap();
+        assert!(first_sync < Local::now().fixed_offset() - Duration::days(FIRST_SYNC_DAYS - 1));
+
+        manager.record_sync_error("session expired").unwrap();
+        let state = manager.sync_state().unwrap();
+        assert_eq!(state.last_error.as_deref(), Some("session expired"));
+        assert!(state.last_error_at.is_some() && state.last_success.is_none());
+
+        let data = get_mock_data(10);
+        manager.insert(&data).unwrap();
+        manager.record_sync_success().unwrap();
+        let newest = data.iter().map(|t| t.time).max().unwrap();
+        let state = manager.sync_state().unwrap();
+        assert!(state.last_success.is_some());
+        assert_eq!(state.last_error, None);
+        assert_eq!(state.newest_transaction, Some(newest));
+        assert_eq!(
+            manager.incremental_start().unwrap(),
+            newest - Duration::seconds(1)
+        );
+
+        // manual transactions do not move the sync point
+        let mut cash = data[0].clone();
+        cash.time = newest + Duration::days(1);
+        cash.amount = Money::from_cents(-500);
+        manager.add_manual(&cash).unwrap();
+        assert_eq!(
+            manager.sync_state().unwrap().newest_transaction,
+            Some(newest)
+        );
+
+        // each profile has its own state
+        let other = manager.for_profile("other").unwrap();
+        assert_eq!(other.sync_state().unwrap(), SyncState::default());
+    }
+
+    #[tokio::test]
+    async fn test_incremental_fetch() {
+        let manager = TransactionManager::new(None).unwrap();
+        let client = || Arc::new(MockMealFetcher::default());
+        let since = OFFSET_UTC_PLUS8
+            .with_ymd_and_hms(2025, 1, 1, 0, 0, 0)
+            .unwrap();
+        let all = fetch(since, client(), CancellationToken::new())
+            .await
+            .unwrap();
+        // drop the newest ones, as if they happened after the last fetch
+        manager.insert(&all[5..].to_vec()).unwrap();
+
+        let start = FetchCheckpoint::start(manager.incremental_start().unwrap());
+        let report = manager
+            .fetch_and_store(start, client(), CancellationToken::new(), |_| Ok(()))
+            .await
+            .unwrap();
+        // stops at the newest stored transaction
+        assert!(report.new + report.duplicate < all.len() as u64);
+        assert_eq!(report.new, 5);
+        assert_eq!(report.conflict, 0);
+        assert_eq!(manager.fetch_count().unwrap(), all.len() as u64);
+        assert!(manager.sync_state().unwrap().last_success.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_resume_fetch() {
+        let mock = || MockMealFetcher::default().per_page(3);
+        let since = OFFSET_UTC_PLUS8
+            .with_ymd_and_hms(2024, 6, 1, 0, 0, 0)
+            .unwrap();
+        let ids = |manager: &TransactionManager| {
+            let mut ids: Vec<i64> = manager.fetch_all().unwrap().iter().map(|t| t.id).collect();
+            ids.sort();
+            ids
+        };
+
+        let uninterrupted = TransactionManager::new(None).unwrap();
+        uninterrupted
+            .fetch_and_store(
+                FetchCheckpoint::start(since),
+                Arc::new(mock()),
+                CancellationToken::new(),
+                |_| Ok(()),
+            )
+            .await
+            .unwrap();
+
+        let manager = TransactionManager::new(None).unwrap();
+        let failing = FailingSource {
+            inner: mock(),
+            fail_from: 5,
+            newest_first: true,
+        };
+        let result = manager
+            .fetch_and_store(
+                FetchCheckpoint::start(since),
+                Arc::new(failing),
+                CancellationToken::new(),
+                |_| Ok(()),
+            )
+            .await;
+        assert!(result.is_err());
+
+        // the pages before the failure are kept
+        let checkpoint = manager.fetch_checkpoint().unwrap().unwrap();
+        assert_eq!(checkpoint.end_time, since);
+        assert!(checkpoint.page <= 4);
+        let stored = manager.fetch_count().unwrap();
+        assert!(stored > 0 && stored < uninterrupted.fetch_count().unwrap());
+        assert_eq!(
+            manager.sync_state().unwrap().checkpoint,
+            Some(checkpoint.clone())
+        );
+        // an incremental fetch would skip the pages not fetched yet
+        assert_eq!(manager.incremental_start().unwrap(), since);
+
+        let report = manager
+            .fetch_and_store(
+                checkpoint,
+                Arc::new(mock()),
+                CancellationToken::new(),
+                |_| Ok(()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.conflict, 0);
+        assert_eq!(ids(&manager), ids(&uninterrupted));
+        assert_eq!(manager.fetch_checkpoint().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_resume_numbers_identical_rows_on() {
+        // oldest first, with identical purchases on both sides of a page boundary
+        let row = |time: &str, merchant: &str| serde_json::json!({ "OCCTIME": time, "TRANAMT": -1.5, "MERCNAME": merchant });
+        let rows = vec![
+            row("2025-03-01 08:00:00", "时光水吧"),
+            row("2025-03-01 12:00:00", "西14西15东12浴室"),
+            row("2025-03-01 12:00:00", "西14西15东12浴室"),
+            row("2025-03-01 12:00:00", "西14西15东12浴室"),
+            row("2025-03-02 08:00:00", "时光水吧"),
+        ];
+        let source = |fail_from| FailingSource {
+            inner: MockMealFetcher::default().per_page(2).rows(rows.clone()),
+            fail_from,
+            newest_first: false,
+        };
+        let since = OFFSET_UTC_PLUS8
+            .with_ymd_and_hms(2025, 1, 1, 0, 0, 0)
+            .unwrap();
+        let ids = |manager: &TransactionManager| {
+            let mut ids: Vec<i64> = manager.fetch_all().unwrap().iter().map(|t| t.id).collect();
+            ids.sort();
+            ids
+        };
+
+        let uninterrupted = TransactionManager::new(None).unwrap();
+        let report = uninterrupted
+            .fetch_and_store(
+                FetchCheckpoint::start(since),
+                Arc::new(source(u32::MAX)),
+                CancellationToken::new(),
+                |_| Ok(()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.new, 5);
+
+        let manager = TransactionManager::new(None).unwrap();
+        let result = manager
+            .fetch_and_store(
+                FetchCheckpoint::start(since),
+                Arc::new(source(2)),
+                CancellationToken::new(),
+                |_| Ok(()),
+            )
+            .await;
+        assert!(result.is_err());
+        let checkpoint = manager.fetch_checkpoint().unwrap().unwrap();
+        assert_eq!(checkpoint.page, 2);
+        assert_eq!(checkpoint.counters.len(), 2);
+
+        let report = manager
+            .fetch_and_store(
+                checkpoint,
+                Arc::new(source(u32::MAX)),
+                CancellationToken::new(),
+                |_| Ok(()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            InsertReport {
+                new: 3,
+                ..Default::default()
+            }
+        );
+        assert_eq!(ids(&manager), ids(&uninterrupted));
+    }
+
+    #[tokio::test]
+    async fn test_page_limit_keeps_checkpoint() {
+        let since = OFFSET_UTC_PLUS8
+            .with_ymd_and_hms(2024, 6, 1, 0, 0, 0)
+            .unwrap();
+        let capped = MockMealFetcher::default().per_page(3).limits(FetchLimits {
+            max_pages: 2,
+            ..Default::default()
+        });
+        let manager = TransactionManager::new(None).unwrap();
+        let err = manager
+            .fetch_and_store(
+                FetchCheckpoint::start(since),
+                Arc::new(capped),
+                CancellationToken::new(),
+                |_| Ok(()),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(FetchError::of(&err), Some(&FetchError::PageLimit(2)));
+
+        // the older transactions are not skipped by the next incremental fetch
+        let state = manager.sync_state().unwrap();
+        assert_eq!(state.last_success, None);
+        let checkpoint = state.checkpoint.unwrap();
+        assert_eq!(checkpoint.end_time, since);
+        assert_eq!(manager.incremental_start().unwrap(), since);
+
+        manager
+            .fetch_and_store(
+                checkpoint,
+                Arc::new(MockMealFetcher::default().per_page(3)),
+                CancellationToken::new(),
+                |_| Ok(()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(manager.fetch_checkpoint().unwrap(), None);
+    }
+}
@@ -10,19 +10,21 @@ mod server;
 mod tui;
 mod utils;
 
+use std::sync::Arc;
+
 use actix_web::{HttpServer, middleware::Logger, web};
 use app::{App, RootState};
 use clap::Parser;
 use color_eyre::eyre::Result;
 use dotenv::dotenv;
 use libs::export_csv::CsvExporter;
-use libs::transactions::TransactionManager;
+use libs::fetcher::{FetchError, TransactionSource};
+use libs::transactions::{FetchCheckpoint, Resolution, TransactionManager};
 
 #[cfg(not(tarpaulin_include))]
 async fn run() -> Result<()> {
     use cli::{ClapSource, Commands};
-    use color_eyre::eyre::Context;
-    use libs::transactions::TransactionManager;
+    use color_eyre::eyre::{Context, ContextCompat};
     let args = cli::Cli::parse();
 
     // application state
@@ -32,46 +34,255 @@ async fn run() -> Result<()> {
 
     match &args.command {
         Some(Commands::ClearDb) => {
-            let manager = TransactionManager::new(config.config.db_path())
-                .context("Error when connecting to Database")?;
+            let manager = open_manager(&config)?;
             manager.clear_db().context("Error when clearing database")?;
             println!("Database cleared");
             Ok(())
         }
+        Some(Commands::Backup { keep, list }) => {
+            let backups = open_backups(&config)?;
+            if *list {
+                for path in backups.list()? {
+                    println!("{}", path.display());
+                }
+                return Ok(());
+            }
+            let path = backups.backup().context("Error when backing up database")?;
+            println!("Backed up to {}", path.display());
+            for path in backups.rotate(*keep)? {
+                println!("Deleted old backup {}", path.display());
+            }
+            Ok(())
+        }
+        Some(Commands::Restore { file }) => {
+            let backups = open_backups(&config)?;
+            let path = backups
+                .restore(file.clone())
+                .context("Error when restoring database")?;
+            // upgrade the restored schema and re-seal its credentials
+            open_manager(&config)?;
+            println!("Restored {}", path.display());
+            Ok(())
+        }
+        Some(Commands::Conflicts { keep, use_incoming }) => {
+            let manager = open_manager(&config)?;
+            let resolution = match (keep, use_incoming) {
+                (Some(id), _) => Some((*id, Resolution::KeepExisting)),
+                (_, Some(id)) => Some((*id, Resolution::UseIncoming)),
+                _ => None,
+            };
+            if let Some((id, resolution)) = resolution {
+                if !manager
+                    .resolve_conflict(id, resolution)
+                    .context("Error when resolving conflict")?
+                {
+                    color_eyre::eyre::bail!("No unresolved conflict with ID {}", id);
+                }
+                println!("Resolved conflict {}", id);
+                return Ok(());
+            }
+
+            let conflicts = manager
+                .list_conflicts()
+                .context("Error when listing conflicts")?;
+            if conflicts.is_empty() {
+                println!("No conflicts");
+            }
+            let describe = |t: &libs::transactions::Transaction| {
+                format!(
+                    "{} {:>8} {}",
+                    t.time.format("%Y-%m-%d %H:%M:%S"),
+                    t.amount,
+                    t.merchant
+                )
+            };
+            for conflict in conflicts {
+                println!(
+                    "#{} (transaction {})\n  existing: {}\n  incoming: {}",
+                    conflict.id,
+                    conflict.existing.id,
+                    describe(&conflict.existing),
+                    describe(&conflict.incoming)
+                );
+            }
+            Ok(())
+        }
+        Some(Commands::Fetch { since, resume }) => {
+            let manager = open_manager(&config)?;
+            if let Some(account) = &config.fetch.account {
+                manager.update_account(account)?;
+            }
+            if let Some(hallticket) = &config.fetch.hallticket {
+                manager.update_hallticket(hallticket)?;
+            }
+            let checkpoint = match (since, resume) {
+                (_, true) => manager
+                    .fetch_checkpoint()?
+                    .context("There is no interrupted fetch to resume")?,
+                (Some(date), _) => FetchCheckpoint::start(CsvExporter::parse_date(date)?),
+                (None, _) => FetchCheckpoint::start(manager.incremental_start()?),
+            };
+            let mut client = config.fetch.source();
+            if client.capabilities().needs_credentials {
+                let (account, cookie) = manager
+                    .get_account_cookie()
+                    .context("Set an account and hallticket before fetching")?;
+                client = client.with_credentials(&account, &cookie);
+            }
+            // Ctrl-C stops the fetch, keeping the pages stored so far
+            let cancel = tokio_util::sync::CancellationToken::new();
+            let cancel_on_ctrl_c = cancel.clone();
+            tokio::spawn(async move {
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    eprintln!("Cancelling fetch...");
+                    cancel_on_ctrl_c.cancel();
+                }
+            });
+            let report = manager
+                .fetch_and_store(checkpoint, client, cancel.clone(), |progress| {
+                    eprintln!(
+                        "Page {}, {} transactions fetched",
+                        progress.current_page, progress.total_entries_fetched
+                    );
+                    Ok(())
+                })
+                .await;
+            let report = match report {
+                Ok(report) => report,
+                Err(e) => {
+                    if !cancel.is_cancelled() {
+                        manager.record_sync_error(&format!("{:#}", e))?;
+                    }
+                    if manager.fetch_checkpoint()?.is_some() {
+                        eprintln!("Fetched pages were stored, run `fetch --resume` to continue");
+                    }
+                    if cancel.is_cancelled() {
+                        color_eyre::eyre::bail!("Fetch cancelled");
+                    }
+                    if FetchError::of(&e) == Some(&FetchError::SessionExpired) {
+                        eprintln!("The session expired, pass a fresh one with `--hallticket`");
+                    }
+                    return Err(e.wrap_err("Error when fetching transactions"));
+                }
+            };
+            println!(
+                "Fetched {} transactions: {}",
+                report.new + report.duplicate + report.conflict,
+                report
+            );
+            if report.conflict > 0 {
+                println!("Run `conflicts` to review the conflicting ones");
+            }
+            Ok(())
+        }
+        Some(Commands::SyncStatus) => {
+            let state = open_manager(&config)?.sync_state()?;
+            let format_time = |time: Option<chrono::DateTime<chrono::Utc>>| {
+                time.map_or("never".to_string(), |t| {
+                    t.with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M:%S")
+                        .to_string()
+                })
+            };
+            println!("Last synced: {}", format_time(state.last_success));
+            println!(
+                "Newest transaction: {}",
+                state.newest_transaction.map_or("none".to_string(), |t| t
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string())
+            );
+            if let Some(error) = state.last_error {
+                println!(
+                    "Last error ({}): {}",
+                    format_time(state.last_error_at),
+                    error
+                );
+            }
+            if let Some(checkpoint) = state.checkpoint {
+                println!(
+                    "Interrupted fetch: stopped at page {}{}, run `fetch --resume` to continue",
+                    checkpoint.page,
+                    checkpoint.before.map_or(String::new(), |t| format!(
+                        ", reached {}",
+                        t.format("%Y-%m-%d %H:%M:%S")
+                    ))
+                );
+            }
+            Ok(())
+        }
         Some(Commands::Web) => {
             println!("Visit http://localhost:8080 to view the web interface");
-            let manager = TransactionManager::new(config.config.db_path())
-                .context("Error when connecting to Database")?;
-            web_main(manager).await?;
+            let manager = open_manager(&config)?;
+            web_main(manager, config.fetch.source()).await?;
             Ok(())
         }
         Some(Commands::ExportCsv {
             output,
             merchant,
+            merchant_match,
             min_amount,
             max_amount,
             time_start,
             time_end,
+            filter,
         }) => {
-            let manager = TransactionManager::new(config.config.db_path())
-                .context("Error when connecting to Database")?;
+            let manager = open_manager(&config)?;
 
             let export_options = libs::export_csv::ExportOptions {
                 output: output.clone(),
                 merchant: merchant.clone(),
+                merchant_match: *merchant_match,
                 min_amount: *min_amount,
                 max_amount: *max_amount,
                 time_start: time_start.clone(),
                 time_end: time_end.clone(),
+                base_filter: filter.build(Default::default()),
             };
 
             CsvExporter::execute_export(&manager, &export_options)
                 .context("Error when exporting transactions to CSV")?;
             Ok(())
         }
+        Some(Commands::Stats {
+            group_by,
+            merchant,
+            merchant_match,
+            time_start,
+            time_end,
+            filter,
+        }) => {
+            let manager = open_manager(&config)?;
+
+            let mut filter =
+                filter.build(libs::transactions::FilterOptions::default().expenses_only());
+            if let Some(merchant) = merchant {
+                filter = filter.search_merchant(merchant, *merchant_match);
+            }
+            if let Some(start) = time_start {
+                filter = filter.start(CsvExporter::parse_date(start)?);
+            }
+            if let Some(end) = time_end {
+                filter = filter.end(CsvExporter::parse_end_date(end)?);
+            }
+
+            let rows = manager
+                .aggregate(&filter, *group_by)
+                .context("Error when aggregating transactions")?;
+            println!(
+                "{:<16} {:>6} {:>10} {:>8} {:>8} {:>8}",
+                group_by, "count", "sum", "avg", "min", "max"
+            );
+            for row in rows {
+                println!(
+                    "{:<16} {:>6} {:>10} {:>8} {:>8} {:>8}",
+                    row.key, row.count, row.sum, row.avg, row.min, row.max
+                );
+            }
+            Ok(())
+        }
 
         None => {
-            let state = RootState::new(config);
+            let state = RootState::new(config)?;
             let mut app = App::new(
                 state,
                 tui::Tui::new()?
@@ -86,13 +297,42 @@ async fn run() -> Result<()> {
     }
 }
 
-async fn web_main(manager: TransactionManager) -> std::io::Result<()> {
+/// Connect to the database, scoped to the configured account profile
+#[cfg(not(tarpaulin_include))]
+fn open_manager(config: &config::Config) -> Result<TransactionManager> {
+    use color_eyre::eyre::Context;
+
+    let manager = TransactionManager::open(config.config.db_path(), config.config.passphrase())
+        .context("Error when connecting to Database")?;
+    manager
+        .switch_profile(config.config.account_profile())
+        .context("Error when selecting account profile")?;
+    Ok(manager)
+}
+
+/// Backups of the configured database file
+#[cfg(not(tarpaulin_include))]
+fn open_backups(config: &config::Config) -> Result<libs::transactions::Backups> {
+    match config.config.db_path() {
+        Some(db_path) => Ok(libs::transactions::Backups::new(db_path)),
+        None => color_eyre::eyre::bail!("An in-memory database cannot be backed up or restored"),
+    }
+}
+
+async fn web_main(
+    manager: TransactionManager,
+    source: Arc<dyn TransactionSource>,
+) -> std::io::Result<()> {
     let transaction_manager = web::Data::new(manager);
+    let source: web::Data<dyn TransactionSource> = web::Data::from(source);
+    let fetch_jobs = web::Data::new(server::api::FetchJobs::default());
 
     HttpServer::new(move || {
         actix_web::App::new()
             .wrap(Logger::default()) // Add Logger middleware
             .app_data(transaction_manager.clone()) // Add TransactionManager to app data
+            .app_data(source.clone())
+            .app_data(fetch_jobs.clone())
             .configure(server::api::config_routes) // Configure routes from server.rs
             .default_service(web::route().to(server::serve_frontend)) // Serve frontend
     })
@@ -5,10 +5,12 @@ use ratatui::Frame;
 use ratatui::layout::Rect;
 
 pub(crate) mod analysis;
+pub(crate) mod conflicts;
 pub(crate) mod cookie_input;
 pub(crate) mod fetch;
 pub(crate) mod help_popup;
 pub(crate) mod home;
+pub(crate) mod profiles;
 pub(crate) mod transactions;
 
 /// A trait that represents a UI layer/page in the application.
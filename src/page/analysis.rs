@@ -13,7 +13,7 @@ use time_series::TimeSeriesData;
 use crate::{
     actions::{ActionSender, LayerManageAction},
     app::layer_manager::EventHandlingStatus,
-    libs::transactions::{Transaction, TransactionManager},
+    libs::transactions::{AggregateRow, FilterOptions, GroupBy, TransactionManager},
     tui::Event,
     utils::help_msg::{HelpEntry, HelpMsg},
 };
@@ -30,7 +30,8 @@ pub(crate) struct Analysis {
     tx: ActionSender,
 
     analysis_type: AnalysisType,
-    data: Vec<Transaction>,
+    /// Transactions covered by every tab
+    filter: FilterOptions,
 }
 
 #[derive(Display, EnumIter)]
@@ -46,20 +47,48 @@ enum AnalysisType {
 }
 
 impl AnalysisType {
-    fn next(&self, data: &[Transaction]) -> Self {
+    fn time_period(manager: &TransactionManager, filter: &FilterOptions) -> Self {
+        Self::TimePeriod(TimePeriodData::new(&aggregate(
+            manager,
+            filter,
+            GroupBy::HalfHour,
+        )))
+    }
+    fn time_series(manager: &TransactionManager, filter: &FilterOptions) -> Self {
+        Self::TimeSeries(TimeSeriesData::new(&aggregate(
+            manager,
+            filter,
+            GroupBy::Month,
+        )))
+    }
+    fn merchant(manager: &TransactionManager, filter: &FilterOptions) -> Self {
+        Self::Merchant(MerchantData::new(&aggregate(
+            manager,
+            filter,
+            GroupBy::Merchant,
+        )))
+    }
+    fn merchant_category(manager: &TransactionManager, filter: &FilterOptions) -> Self {
+        Self::MerchantCategory(MerchantCategoryData::new(&aggregate(
+            manager,
+            filter,
+            GroupBy::Merchant,
+        )))
+    }
+    fn next(&self, manager: &TransactionManager, filter: &FilterOptions) -> Self {
         match self {
-            Self::TimePeriod(_) => Self::TimeSeries(TimeSeriesData::new(data)),
-            Self::TimeSeries(_) => Self::Merchant(MerchantData::new(data)),
-            Self::Merchant(_) => Self::MerchantCategory(MerchantCategoryData::new(data)),
-            Self::MerchantCategory(_) => Self::TimePeriod(TimePeriodData::new(data)),
+            Self::TimePeriod(_) => Self::time_series(manager, filter),
+            Self::TimeSeries(_) => Self::merchant(manager, filter),
+            Self::Merchant(_) => Self::merchant_category(manager, filter),
+            Self::MerchantCategory(_) => Self::time_period(manager, filter),
         }
     }
-    fn previous(&self, data: &[Transaction]) -> Self {
+    fn previous(&self, manager: &TransactionManager, filter: &FilterOptions) -> Self {
         match self {
-            Self::TimePeriod(_) => Self::MerchantCategory(MerchantCategoryData::new(data)),
-            Self::MerchantCategory(_) => Self::Merchant(MerchantData::new(data)),
-            Self::TimeSeries(_) => Self::TimePeriod(TimePeriodData::new(data)),
-            Self::Merchant(_) => Self::TimeSeries(TimeSeriesData::new(data)),
+            Self::TimePeriod(_) => Self::merchant_category(manager, filter),
+            Self::MerchantCategory(_) => Self::merchant(manager, filter),
+            Self::TimeSeries(_) => Self::time_period(manager, filter),
+            Self::Merchant(_) => Self::time_series(manager, filter),
         }
     }
     fn to_index(&self) -> usize {
@@ -80,20 +109,27 @@ impl AnalysisType {
     }
 }
 
+fn aggregate(
+    manager: &TransactionManager,
+    filter: &FilterOptions,
+    group_by: GroupBy,
+) -> Vec<AggregateRow> {
+    manager
+        .aggregate(filter, group_by)
+        .expect("Failed to aggregate transactions")
+}
+
 impl Analysis {
     pub fn new(tx: ActionSender, manager: TransactionManager) -> Self {
-        let mut new = Self {
+        // top-ups, refunds and subsidies are not meals
+        let filter = FilterOptions::default().expenses_only();
+        let analysis_type = AnalysisType::time_period(&manager, &filter);
+        Self {
             manager,
             tx,
-            analysis_type: AnalysisType::TimePeriod(Default::default()),
-            data: vec![],
-        };
-        new.data = new
-            .manager
-            .fetch_all()
-            .expect("Failed to load transactions");
-        new.analysis_type = AnalysisType::TimePeriod(TimePeriodData::new(&new.data));
-        new
+            analysis_type,
+            filter,
+        }
     }
 }
 
@@ -108,11 +144,11 @@ impl EventLoopParticipant for Analysis {
                     status.consumed();
                 }
                 KeyCode::Char('h') | KeyCode::Left => {
-                    self.analysis_type = self.analysis_type.previous(&self.data);
+                    self.analysis_type = self.analysis_type.previous(&self.manager, &self.filter);
                     status.consumed();
                 }
                 KeyCode::Char('l') | KeyCode::Right => {
-                    self.analysis_type = self.analysis_type.next(&self.data);
+                    self.analysis_type = self.analysis_type.next(&self.manager, &self.filter);
                     status.consumed();
                 }
                 KeyCode::Char('j') | KeyCode::Down => {
@@ -184,7 +220,14 @@ impl Analysis {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{actions::Action, libs::fetcher};
+    use crate::{
+        actions::Action,
+        libs::{
+            fetcher,
+            money::Money,
+            transactions::{Transaction, TransactionKind},
+        },
+    };
     use insta::assert_snapshot;
     use ratatui::backend::TestBackend;
     use tokio::sync::mpsc::{self, UnboundedReceiver};
@@ -201,8 +244,33 @@ mod test {
     #[test]
     fn test_initial_state() {
         let (_, page) = get_test_objs();
-        assert!(matches!(page.analysis_type, AnalysisType::TimePeriod(_)));
-        assert!(!page.data.is_empty());
+        let AnalysisType::TimePeriod(data) = &page.analysis_type else {
+            panic!("Should be time period")
+        };
+        let total: u32 = data.into_iter().map(|(_, count)| count).sum();
+        assert_eq!(
+            total as u64,
+            page.manager
+                .fetch_filtered_count(&FilterOptions::default().expenses_only())
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_only_expenses() {
+        let (_, page) = get_test_objs();
+        assert_eq!(page.filter.kinds, Some(vec![TransactionKind::Expense]));
+
+        // a top-up must not show up as a merchant
+        page.manager
+            .insert(&vec![Transaction::new(
+                Money::from_yuan(100.0),
+                "充值".to_string(),
+                chrono::DateTime::parse_from_rfc3339("2025-03-01T12:00:00+08:00").unwrap(),
+            )])
+            .unwrap();
+        let rows = aggregate(&page.manager, &page.filter, GroupBy::Merchant);
+        assert!(rows.iter().all(|r| r.key != "充值"));
     }
 
     #[test]
@@ -232,7 +300,7 @@ mod test {
 
     fn get_merchant_data(analysis_type: &AnalysisType) -> MerchantData {
         if let AnalysisType::Merchant(data) = analysis_type {
-            return data.clone();
+            data.clone()
         } else {
             panic!("Should be merchant")
         }
@@ -1,5 +1,3 @@
-use std::collections::HashMap;
-
 use ratatui::{
     Frame,
     layout::{Rect, Size},
@@ -10,26 +8,20 @@ use ratatui::{
 };
 use tui_scrollview::{ScrollView, ScrollViewState, ScrollbarVisibility};
 
-use crate::libs::transactions::Transaction;
+use crate::libs::{money::Money, transactions::AggregateRow};
 
 #[derive(Debug, Default, Clone)]
 pub(super) struct MerchantData {
-    data: Vec<(String, f64)>,
+    data: Vec<(String, Money)>,
     pub scroll_state: ScrollViewState,
 }
 impl MerchantData {
-    pub fn new(data: &[Transaction]) -> Self {
-        let hash_map = data.iter().fold(HashMap::new(), |mut acc, entry| {
-            match acc.get(&entry.merchant) {
-                Some(v) => acc.insert(&entry.merchant, *v + entry.amount),
-                None => acc.insert(&entry.merchant, entry.amount),
-            };
-            acc
-        });
-        let mut entries: Vec<_> = hash_map.iter().collect();
-        entries.sort_by(|a, b| a.1.total_cmp(b.1));
+    /// Build from rows grouped by [`GroupBy::Merchant`](crate::libs::transactions::GroupBy::Merchant)
+    pub fn new(rows: &[AggregateRow]) -> Self {
+        let mut entries: Vec<_> = rows.iter().map(|r| (r.key.clone(), r.sum)).collect();
+        entries.sort_by_key(|e| e.1);
         MerchantData {
-            data: entries.iter().map(|e| ((*e.0).to_string(), *e.1)).collect(),
+            data: entries,
             scroll_state: ScrollViewState::default(),
         }
     }
@@ -62,8 +54,8 @@ impl MerchantData {
             .into_iter()
             .map(|(name, value)| {
                 Bar::default()
-                    .value(((value.abs() * 100.0).round() as u64) / 100)
-                    .text_value(format!("{:.2}", value.abs()))
+                    .value(value.abs().cents() as u64 / 100)
+                    .text_value(value.abs().to_string())
                     .label(Line::from(name))
                     .style(style)
                     .value_style(style.reversed())
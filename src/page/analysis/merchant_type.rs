@@ -6,7 +6,7 @@ use ratatui::{
     widgets::{Bar, BarChart, BarGroup, Block, Padding, Paragraph},
 };
 
-use crate::libs::transactions::Transaction;
+use crate::libs::transactions::AggregateRow;
 use crate::utils::merchant_class::MerchantType; // 引入商家分类
 
 #[derive(Debug, Default, Clone)]
@@ -20,16 +20,18 @@ pub(super) struct MerchantCategoryData {
 }
 
 impl MerchantCategoryData {
-    pub(super) fn new(data: &[Transaction]) -> Self {
-        data.iter().fold(Self::default(), |mut acc, entry| {
-            let merchant_type = MerchantType::from_str(&entry.merchant);
+    /// Build from rows grouped by [`GroupBy::Merchant`](crate::libs::transactions::GroupBy::Merchant)
+    pub(super) fn new(rows: &[AggregateRow]) -> Self {
+        rows.iter().fold(Self::default(), |mut acc, row| {
+            let count = row.count as u32;
+            let merchant_type = MerchantType::of_merchant(&row.key);
             match merchant_type {
-                MerchantType::CanteenFood => acc.canteen_food += 1,
-                MerchantType::CanteenDrink => acc.canteen_drink += 1,
-                MerchantType::Supermarket => acc.supermarket += 1,
-                MerchantType::Bathhouse => acc.bathhouse += 1,
-                MerchantType::Other => acc.other += 1,
-                MerchantType::Unknown => acc.unknown += 1,
+                MerchantType::CanteenFood => acc.canteen_food += count,
+                MerchantType::CanteenDrink => acc.canteen_drink += count,
+                MerchantType::Supermarket => acc.supermarket += count,
+                MerchantType::Bathhouse => acc.bathhouse += count,
+                MerchantType::Other => acc.other += count,
+                MerchantType::Unknown => acc.unknown += count,
             }
             acc
         })
@@ -7,7 +7,7 @@ use ratatui::{
     widgets::{Bar, BarChart, BarGroup, Block, Padding, Paragraph},
 };
 
-use crate::libs::transactions::Transaction;
+use crate::libs::transactions::AggregateRow;
 
 #[derive(Debug, Default, Clone)]
 pub(super) struct TimePeriodData {
@@ -22,17 +22,23 @@ pub(super) struct TimePeriodData {
 }
 
 impl TimePeriodData {
-    pub(super) fn new(data: &[Transaction]) -> Self {
-        data.iter().fold(Self::default(), |mut acc, entry| {
-            let time = entry.time.time();
+    /// Build from rows grouped by [`GroupBy::HalfHour`](crate::libs::transactions::GroupBy::HalfHour)
+    ///
+    /// Every meal boundary is on the half hour, so each bucket falls in exactly one period.
+    pub(super) fn new(rows: &[AggregateRow]) -> Self {
+        rows.iter().fold(Self::default(), |mut acc, row| {
+            let Ok(time) = NaiveTime::parse_from_str(&row.key, "%H:%M") else {
+                return acc;
+            };
+            let count = row.count as u32;
             if Self::check_time_in(time, (5, 0), (10, 30)) {
-                acc.breakfast += 1;
+                acc.breakfast += count;
             } else if Self::check_time_in(time, (10, 30), (13, 30)) {
-                acc.lunch += 1;
+                acc.lunch += count;
             } else if Self::check_time_in(time, (16, 30), (19, 30)) {
-                acc.dinner += 1;
+                acc.dinner += count;
             } else {
-                acc.unknown += 1;
+                acc.unknown += count;
             }
             acc
         })
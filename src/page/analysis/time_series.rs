@@ -1,6 +1,3 @@
-use std::collections::HashMap;
-
-use chrono::Datelike;
 use ratatui::{
     Frame,
     style::{Style, Stylize as _, palette::tailwind},
@@ -10,7 +7,7 @@ use ratatui::{
 };
 use tracing::info;
 
-use crate::libs::transactions::Transaction;
+use crate::libs::{money::Money, transactions::AggregateRow};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 struct YearMonth {
@@ -46,35 +43,31 @@ impl std::fmt::Display for YearMonth {
 
 #[derive(Debug, Default, Clone)]
 pub(super) struct TimeSeriesData {
-    data: Vec<(YearMonth, f64)>,
+    data: Vec<(YearMonth, Money)>,
 }
 
 impl TimeSeriesData {
-    pub(super) fn new(data: &[Transaction]) -> Self {
-        let mut processed_data: Vec<(YearMonth, f64)> = data
+    /// Build from rows grouped by [`GroupBy::Month`](crate::libs::transactions::GroupBy::Month)
+    pub(super) fn new(rows: &[AggregateRow]) -> Self {
+        // rows are ordered by key, which sorts chronologically
+        let processed_data: Vec<(YearMonth, Money)> = rows
             .iter()
-            .fold(HashMap::new(), |mut acc, entry| {
-                let year = entry.time.year() as u16;
-                let month = entry.time.month() as u16;
-                match acc.get(&(year, month)) {
-                    Some(v) => acc.insert((year, month), *v + entry.amount.abs()),
-                    None => acc.insert((year, month), entry.amount.abs()),
-                };
-                acc
+            .filter_map(|row| {
+                let (year, month) = row.key.split_once('-')?;
+                Some((
+                    YearMonth::new(year.parse().ok()?, month.parse().ok()?),
+                    row.sum.abs(),
+                ))
             })
-            .iter()
-            .map(|((year, month), amount)| (YearMonth::new(*year, *month), *amount))
             .collect();
 
-        processed_data.sort_by(|a, b| a.0.cmp(&b.0));
-
         let processed_data = processed_data
             .into_iter()
             .fold(Vec::new(), |mut acc, entry| {
                 let mut last_ym = acc.last().unwrap_or(&entry).0;
                 while last_ym.next() < entry.0 {
                     info!("Missing data for {:?}, {:?}", last_ym, entry.0);
-                    acc.push((last_ym, 0.0));
+                    acc.push((last_ym, Money::ZERO));
                     last_ym = last_ym.next();
                 }
                 acc.push(entry);
@@ -113,7 +106,7 @@ impl TimeSeriesData {
             .iter()
             .map(|(ym, value)| {
                 Bar::default()
-                    .value(value.round() as u64)
+                    .value(value.to_yuan().round() as u64)
                     .label(Line::from(ym.to_string()))
                     .style(style)
                     .value_style(style.reversed())
@@ -0,0 +1,216 @@
+use crossterm::event::KeyCode;
+use ratatui::{
+    Frame,
+    layout::{Constraint, Layout, Rect},
+    style::{Modifier, Style, palette::tailwind},
+    text::{Line, Text},
+    widgets::{Block, BorderType, Borders, HighlightSpacing, List, ListItem, ListState, Padding},
+};
+
+use crate::{
+    actions::{ActionSender, LayerManageAction, Layers},
+    app::layer_manager::EventHandlingStatus,
+    libs::transactions::{Conflict, Resolution, Transaction, TransactionManager},
+    tui::Event,
+    utils::help_msg::{HelpEntry, HelpMsg},
+};
+
+use super::{EventLoopParticipant, Layer, WidgetExt};
+
+/// Review of fetched transactions conflicting with stored ones
+///
+/// Lists the unresolved conflicts of the active profile, showing both
+/// versions, and keeps one of them.
+pub struct Conflicts {
+    tx: ActionSender,
+    manager: TransactionManager,
+
+    conflicts: Vec<Conflict>,
+    list_state: ListState,
+}
+
+impl Conflicts {
+    pub fn new(tx: ActionSender, manager: TransactionManager) -> Self {
+        Self {
+            tx,
+            manager,
+            conflicts: Vec::new(),
+            list_state: ListState::default(),
+        }
+    }
+
+    /// Reload the conflicts, keeping the selection in place
+    fn reload(&mut self) {
+        self.conflicts = self.manager.list_conflicts().unwrap_or_default();
+        let selected = match self.conflicts.len() {
+            0 => None,
+            len => Some(self.list_state.selected().unwrap_or(0).min(len - 1)),
+        };
+        self.list_state.select(selected);
+    }
+
+    fn resolve_selected(&mut self, resolution: Resolution) {
+        if let Some(conflict) = self
+            .list_state
+            .selected()
+            .and_then(|i| self.conflicts.get(i))
+        {
+            self.manager
+                .resolve_conflict(conflict.id, resolution)
+                .unwrap();
+            self.reload();
+        }
+    }
+
+    fn get_help_msg(&self) -> HelpMsg {
+        vec![
+            HelpEntry::new('j', "Go Down"),
+            HelpEntry::new('k', "Go Up"),
+            HelpEntry::new('e', "Keep existing"),
+            HelpEntry::new('i', "Use incoming"),
+            HelpEntry::new('?', "Help"),
+            HelpEntry::new(KeyCode::Esc, "Back"),
+        ]
+        .into()
+    }
+}
+
+/// One version of a conflicting transaction
+fn describe(label: &str, t: &Transaction) -> Line<'static> {
+    Line::raw(format!(
+        "  {} {} {:>8} {}",
+        label,
+        t.time.format("%Y-%m-%d %H:%M:%S"),
+        t.amount,
+        t.merchant
+    ))
+}
+
+impl Layer for Conflicts {
+    fn init(&mut self) {
+        self.reload();
+    }
+}
+
+impl WidgetExt for Conflicts {
+    fn render(&mut self, frame: &mut Frame, area: Rect) {
+        let [list_area, help_area] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(3)]).areas(area);
+
+        let items = self.conflicts.iter().map(|c| {
+            ListItem::new(Text::from(vec![
+                Line::raw(format!(
+                    "Transaction {} (detected {})",
+                    c.existing.id,
+                    c.detected_at.format("%Y-%m-%d %H:%M")
+                )),
+                describe("existing:", &c.existing).style(tailwind::GRAY.c400),
+                describe("incoming:", &c.incoming).style(tailwind::AMBER.c300),
+            ]))
+        });
+        let title = match self.conflicts.len() {
+            0 => "No conflicts".to_string(),
+            n => format!("{} conflicting transactions", n),
+        };
+        let list = List::new(items)
+            .block(
+                Block::new()
+                    .title(Line::raw(title).centered())
+                    .border_type(BorderType::Rounded)
+                    .borders(Borders::ALL)
+                    .padding(Padding::horizontal(1)),
+            )
+            .highlight_style(
+                Style::default()
+                    .add_modifier(Modifier::REVERSED)
+                    .fg(tailwind::INDIGO.c300),
+            )
+            .highlight_spacing(HighlightSpacing::Always);
+        frame.render_stateful_widget(list, list_area, &mut self.list_state);
+
+        self.get_help_msg().render(frame, help_area);
+    }
+}
+
+impl EventLoopParticipant for Conflicts {
+    fn handle_events(&mut self, event: &Event) -> EventHandlingStatus {
+        let mut status = EventHandlingStatus::default();
+        if let Event::Key(key) = event {
+            match key.code {
+                KeyCode::Char('j') => {
+                    self.list_state.select_next();
+                    status.consumed();
+                }
+                KeyCode::Char('k') => {
+                    self.list_state.select_previous();
+                    status.consumed();
+                }
+                KeyCode::Char('e') => {
+                    self.resolve_selected(Resolution::KeepExisting);
+                    status.consumed();
+                }
+                KeyCode::Char('i') => {
+                    self.resolve_selected(Resolution::UseIncoming);
+                    status.consumed();
+                }
+                KeyCode::Char('?') => {
+                    self.tx.send(LayerManageAction::Push(
+                        Layers::Help(self.get_help_msg()).into_push_config(true),
+                    ));
+                    status.consumed();
+                }
+                KeyCode::Esc => {
+                    self.tx.send(LayerManageAction::Pop);
+                    status.consumed();
+                }
+                _ => {}
+            }
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::libs::{fetcher::test_utils::get_mock_data, money::Money};
+
+    fn get_test_page() -> Conflicts {
+        let (tx, _) = tokio::sync::mpsc::unbounded_channel();
+        let manager = TransactionManager::new(None).unwrap();
+        let data = get_mock_data(10);
+        manager.insert(&data).unwrap();
+        let changed: Vec<_> = data[..2]
+            .iter()
+            .map(|t| Transaction {
+                amount: t.amount + Money::from_cents(-100),
+                ..t.clone()
+            })
+            .collect();
+        manager.insert(&changed).unwrap();
+
+        let mut page = Conflicts::new(tx.into(), manager);
+        page.init();
+        page
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut page = get_test_page();
+        assert_eq!(page.conflicts.len(), 2);
+        let (first, second) = (page.conflicts[0].clone(), page.conflicts[1].clone());
+
+        page.handle_event_with_status_check(&'j'.into());
+        page.handle_event_with_status_check(&'i'.into());
+        assert_eq!(page.conflicts.len(), 1);
+        assert_eq!(page.list_state.selected(), Some(0));
+        let stored = page.manager.fetch_all().unwrap();
+        let find = |id| stored.iter().find(|t| t.id == id).unwrap().amount;
+        assert_eq!(find(second.incoming.id), second.incoming.amount);
+
+        page.handle_event_with_status_check(&'e'.into());
+        assert!(page.conflicts.is_empty());
+        assert_eq!(page.list_state.selected(), None);
+        assert_eq!(find(first.existing.id), first.existing.amount);
+    }
+}
@@ -8,7 +8,7 @@ use crate::actions::LayerManageAction;
 use crate::actions::Layers;
 use crate::app::layer_manager::EventHandlingStatus;
 use crate::component::input::{InputComp, InputMode};
-use crate::libs::transactions::TransactionManager;
+use crate::libs::transactions::{TransactionManager, mask_secret};
 use crate::utils::help_msg::{HelpEntry, HelpMsg};
 
 use super::{EventLoopParticipant, Layer, WidgetExt};
@@ -22,26 +22,51 @@ pub struct CookieInput {
 
     cookie_input: InputComp,
     account_input: InputComp,
+
+    /// Whether the stored account and hallticket are shown in plain text
+    revealed: bool,
 }
 
 impl CookieInput {
     pub fn new(action_tx: ActionSender, manager: TransactionManager) -> Self {
-        let (account, mut cookie) = manager.get_account_cookie_may_empty().unwrap_or_default();
-        if cookie.starts_with("hallticket=") {
-            cookie.replace_range(..11, "");
-        }
-        Self {
+        let mut page = Self {
             state: Default::default(),
             manager,
-            cookie_input: InputComp::new().init_text(cookie).title("Hallticket"),
-            account_input: InputComp::new().init_text(account).title("Account"),
+            cookie_input: InputComp::new().title("Hallticket"),
+            account_input: InputComp::new().title("Account"),
             tx: action_tx,
+            revealed: false,
+        };
+        page.refresh();
+        page
+    }
+
+    /// Stored account and hallticket, masked unless revealed
+    fn display_texts(&self) -> (String, String) {
+        let (account, mut cookie) = self
+            .manager
+            .get_account_cookie_may_empty()
+            .unwrap_or_default();
+        if cookie.starts_with("hallticket=") {
+            cookie.replace_range(..11, "");
+        }
+        if self.revealed {
+            (account, cookie)
+        } else {
+            (mask_secret(&account), mask_secret(&cookie))
         }
     }
 
+    fn refresh(&mut self) {
+        let (account, cookie) = self.display_texts();
+        self.account_input.set_text(account);
+        self.cookie_input.set_text(cookie);
+    }
+
     pub fn get_help_msg(&self) -> crate::utils::help_msg::HelpMsg {
         let help_msg: HelpMsg = vec![
             HelpEntry::new_plain("hjkl", "Move focus"),
+            HelpEntry::new('r', if self.revealed { "Hide" } else { "Reveal" }),
             HelpEntry::new('?', "Help"),
             HelpEntry::new(KeyCode::Esc, "Back"),
         ]
@@ -97,25 +122,36 @@ impl EventLoopParticipant for CookieInput {
     fn handle_events(&mut self, event: &crate::tui::Event) -> EventHandlingStatus {
         let mut status = EventHandlingStatus::default();
 
+        let was_inputting = self.account_input.is_inputting();
         let (account_state, account_result) = self.account_input.handle_events(event);
+        if !self.revealed && !was_inputting && self.account_input.is_inputting() {
+            // a masked value cannot be edited, so start over
+            self.account_input.set_text("");
+        }
         if let Some(result) = account_result {
             self.manager.update_account(&result).unwrap();
+            self.refresh();
         }
         if matches!(account_state, EventHandlingStatus::Consumed) {
             return account_state;
         }
 
+        let was_inputting = self.cookie_input.is_inputting();
         let (cookie_state, cookie_result) = self.cookie_input.handle_events(event);
-        if let Some(result) = cookie_result {
-            if !result.is_empty() {
-                if result.starts_with("hallticket=") {
-                    self.manager.update_cookie(&result).unwrap();
-                } else {
-                    self.manager
-                        .update_cookie(&format!("hallticket={}", result))
-                        .unwrap();
-                }
+        if !self.revealed && !was_inputting && self.cookie_input.is_inputting() {
+            self.cookie_input.set_text("");
+        }
+        if let Some(result) = cookie_result
+            && !result.is_empty()
+        {
+            if result.starts_with("hallticket=") {
+                self.manager.update_cookie(&result).unwrap();
+            } else {
+                self.manager
+                    .update_cookie(&format!("hallticket={}", result))
+                    .unwrap();
             }
+            self.refresh();
         }
         if matches!(cookie_state, EventHandlingStatus::Consumed) {
             return cookie_state;
@@ -131,6 +167,11 @@ impl EventLoopParticipant for CookieInput {
                     self.change_focus(self.state.next());
                     status.consumed();
                 }
+                (_, KeyCode::Char('r')) => {
+                    self.revealed = !self.revealed;
+                    self.refresh();
+                    status.consumed();
+                }
                 (_, KeyCode::Esc) => self.tx.send(LayerManageAction::Swap(Layers::Fetch)),
                 (_, KeyCode::Char('?')) => {
                     self.tx.send(LayerManageAction::Push(
@@ -216,7 +257,10 @@ mod test {
         page.handle_event_with_status_check(&'j'.into());
         page.handle_event_with_status_check(&KeyCode::Enter.into());
         assert_eq!(page.manager.get_account_cookie_may_empty().unwrap().0, "aj");
+        assert_eq!(page.account_input.get_text(), "**");
 
+        // editing keeps the stored value only once revealed
+        page.handle_event_with_status_check(&'r'.into());
         page.handle_event_with_status_check(&KeyCode::Enter.into());
         page.handle_event_with_status_check(&KeyCode::Left.into());
         page.handle_event_with_status_check(&Event::Paste("kl".into()));
@@ -251,7 +295,30 @@ mod test {
         let (tx, _) = mpsc::unbounded_channel();
         let mut page = CookieInput::new(tx.clone().into(), manager);
         page.init();
+        assert_eq!(page.cookie_input.get_text(), "***");
+
+        page.handle_event_with_status_check(&'r'.into());
         assert_eq!(page.cookie_input.get_text(), "abc");
+        page.handle_event_with_status_check(&'r'.into());
+        assert_eq!(page.cookie_input.get_text(), "***");
+    }
+
+    #[test]
+    fn test_masked_edit_starts_empty() {
+        let manager = TransactionManager::new(None).unwrap();
+        manager.update_account("123456").unwrap();
+        let (tx, _) = mpsc::unbounded_channel();
+        let mut page = CookieInput::new(tx.into(), manager);
+        page.init();
+
+        page.handle_event_with_status_check(&KeyCode::Enter.into());
+        assert_eq!(page.account_input.get_text(), "");
+        page.handle_event_with_status_check(&Event::Paste("654321".into()));
+        page.handle_event_with_status_check(&KeyCode::Enter.into());
+        assert_eq!(
+            page.manager.get_account_cookie_may_empty().unwrap().0,
+            "654321"
+        );
     }
 
     #[test]
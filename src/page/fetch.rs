@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use chrono::{DateTime, FixedOffset, Local};
 use color_eyre::eyre::Context;
 use crossterm::event::KeyCode;
@@ -8,20 +10,21 @@ use ratatui::{
     widgets::{Block, BorderType, Borders, Paragraph},
 };
 use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
 use tracing::{info, instrument, warn};
 
 use crate::{
     actions::{ActionSender, LayerManageAction, Layers},
     app::layer_manager::EventHandlingStatus,
     component::input::InputComp,
-    libs::{fetcher::MealFetcher, transactions::OFFSET_UTC_PLUS8},
+    libs::{
+        fetcher::{FetchError, RealMealFetcher, TransactionSource},
+        transactions::{FetchCheckpoint, OFFSET_UTC_PLUS8},
+    },
     tui::Event,
     utils::help_msg::{HelpEntry, HelpMsg},
 };
-use crate::{
-    component::input::InputMode,
-    libs::{fetcher, transactions},
-};
+use crate::{component::input::InputMode, libs::transactions};
 
 use super::{EventLoopParticipant, Layer, WidgetExt};
 
@@ -42,13 +45,25 @@ pub struct FetchProgress {
 #[derive(Clone, Debug)]
 pub enum FetchingAction {
     UpdateFetchStatus(FetchingState),
-    InsertTransaction(Vec<transactions::Transaction>),
+    /// Fetching completed, with the outcome of storing its pages
+    Finished(transactions::InsertReport),
+    /// Fetching failed with this error
+    Failed(String),
+    /// Fetching failed because the hallticket expired, with this error
+    SessionExpired(String),
+    /// Fetching stopped because it was cancelled
+    Cancelled,
 }
 
 #[derive(Debug)]
 pub struct Fetch {
     fetching_state: FetchingState,
     local_db_cnt: u64,
+    /// Unresolved conflicts between fetched and stored transactions
+    conflict_cnt: u64,
+    /// Outcome of inserting the last fetched transactions
+    last_report: Option<transactions::InsertReport>,
+    sync_state: transactions::SyncState,
     fetch_start_date: Option<DateTime<FixedOffset>>,
     current_focus: Focus,
 
@@ -59,7 +74,16 @@ pub struct Fetch {
     tx: ActionSender,
     manager: transactions::TransactionManager,
 
-    client: MealFetcher,
+    client: Arc<dyn TransactionSource>,
+    /// Cancels the running fetch, if any
+    cancel: CancellationToken,
+}
+
+impl Drop for Fetch {
+    fn drop(&mut self) {
+        // nobody is left to store what it fetches
+        self.cancel.cancel();
+    }
 }
 
 impl Fetch {
@@ -68,6 +92,9 @@ impl Fetch {
         Self {
             fetching_state: Default::default(),
             local_db_cnt: Default::default(),
+            conflict_cnt: Default::default(),
+            last_report: None,
+            sync_state: Default::default(),
             fetch_start_date: Default::default(),
             current_focus: Default::default(),
 
@@ -80,7 +107,8 @@ impl Fetch {
             tx,
             manager,
 
-            client: Default::default(),
+            client: Arc::new(RealMealFetcher::default()),
+            cancel: CancellationToken::new(),
         }
     }
 }
@@ -100,26 +128,37 @@ impl Fetch {
             HelpEntry::new(' ', "Start fetch"),
         ]
         .into();
+        if self.conflict_cnt > 0 {
+            help.push(HelpEntry::new('c', "Resolve conflicts"));
+        }
+        if self.sync_state.checkpoint.is_some() {
+            help.push(HelpEntry::new('R', "Resume interrupted fetch"));
+        }
+        if let FetchingState::Fetching(_) = self.fetching_state {
+            help.push(HelpEntry::new('x', "Cancel fetch"));
+        }
         if let Focus::UserInput = self.current_focus {
             help.extend(&self.input.get_help_msg())
         }
         help
     }
 
-    pub fn client<T: Into<MealFetcher>>(mut self, client: T) -> Self {
-        self.client = client.into();
+    pub fn client(mut self, client: Arc<dyn TransactionSource>) -> Self {
+        self.client = client;
         self
     }
 
     #[cfg(test)]
-    pub fn get_client(&self) -> MealFetcher {
+    pub fn get_client(&self) -> Arc<dyn TransactionSource> {
         self.client.clone()
     }
 }
 
 #[derive(Clone, Default, Debug)]
 pub enum Focus {
+    /// Since the newest stored transaction
     #[default]
+    Incremental,
     P1Year,
     P3Months,
     P1Month,
@@ -129,16 +168,18 @@ pub enum Focus {
 impl Focus {
     fn next(&self) -> Self {
         match self {
+            Focus::Incremental => Focus::P1Year,
             Focus::P1Year => Focus::P3Months,
             Focus::P3Months => Focus::P1Month,
             Focus::P1Month => Focus::UserInput,
-            Focus::UserInput => Focus::P1Year,
+            Focus::UserInput => Focus::Incremental,
         }
     }
 
     fn prev(&self) -> Self {
         match self {
-            Focus::P1Year => Focus::UserInput,
+            Focus::Incremental => Focus::UserInput,
+            Focus::P1Year => Focus::Incremental,
             Focus::P3Months => Focus::P1Year,
             Focus::P1Month => Focus::P3Months,
             Focus::UserInput => Focus::P1Month,
@@ -161,6 +202,7 @@ impl WidgetExt for Fetch {
             Constraint::Fill(1),
             Constraint::Fill(1),
             Constraint::Fill(1),
+            Constraint::Fill(1),
         ])
         .flex(Flex::SpaceAround)
         .split(area[0]);
@@ -176,12 +218,20 @@ impl WidgetExt for Fetch {
                 .style(Style::default().fg(if focused { Color::Cyan } else { Color::Reset }))
         };
 
+        frame.render_widget(
+            render_button(
+                matches!(self.current_focus, Focus::Incremental),
+                "Since last sync".to_string(),
+            ),
+            top_areas[0],
+        );
+
         frame.render_widget(
             render_button(
                 matches!(self.current_focus, Focus::P1Year),
                 "Past 1 year".to_string(),
             ),
-            top_areas[0],
+            top_areas[1],
         );
 
         frame.render_widget(
@@ -189,7 +239,7 @@ impl WidgetExt for Fetch {
                 matches!(self.current_focus, Focus::P3Months),
                 "Past 3 months".to_string(),
             ),
-            top_areas[1],
+            top_areas[2],
         );
 
         frame.render_widget(
@@ -197,7 +247,7 @@ impl WidgetExt for Fetch {
                 matches!(self.current_focus, Focus::P1Month),
                 "Past 1 month".to_string(),
             ),
-            top_areas[2],
+            top_areas[3],
         );
 
         self.input.render(frame, area[1]);
@@ -205,14 +255,45 @@ impl WidgetExt for Fetch {
         // 修改这里：显示获取结果
         match &self.fetching_state {
             FetchingState::Idle => {
+                let mut text = format!(
+                    "Currently {} records locally stored.\n Press \"Space\" to fetch transactions since {}",
+                    self.local_db_cnt,
+                    self.fetch_start_date.map_or("N/A".to_string(), |date| date
+                        .format("%Y-%m-%d")
+                        .to_string()),
+                );
+                if let Some(report) = &self.last_report {
+                    text.push_str(&format!("\nLast fetch: {}", report));
+                }
+                if let Some(last_success) = self.sync_state.last_success {
+                    text.push_str(&format!(
+                        "\nLast synced at {}",
+                        last_success.with_timezone(&Local).format("%Y-%m-%d %H:%M")
+                    ));
+                }
+                if let Some(error) = &self.sync_state.last_error {
+                    text.push_str(&format!("\nLast fetch failed: {}", error));
+                }
+                if let Some(checkpoint) = &self.sync_state.checkpoint {
+                    text.push_str(&format!(
+                        "\nAn interrupted fetch stopped at page {}{}, press \"R\" to resume it",
+                        checkpoint.page,
+                        checkpoint.before.map_or(String::new(), |date| format!(
+                            " ({})",
+                            date.format("%Y-%m-%d")
+                        )),
+                    ));
+                }
+                if self.conflict_cnt > 0 {
+                    text.push_str(&format!(
+                        "\n{} fetched records conflict with stored ones, press \"c\" to resolve them",
+                        self.conflict_cnt
+                    ));
+                }
                 frame.render_widget(
-                    Text::raw(format!(
-                        "Currently {} records locally stored.\n Press \"Space\" to fetch transactions since {}",
-                        self.local_db_cnt,
-                        self.fetch_start_date.map_or("N/A".to_string(), |date| date.format("%Y-%m-%d").to_string()),
-                    ))
-                    .style(Style::default().fg(Color::Gray))
-                    .centered(),
+                    Text::raw(text)
+                        .style(Style::default().fg(Color::Gray))
+                        .centered(),
                     area[2],
                 );
             }
@@ -259,7 +340,20 @@ impl EventLoopParticipant for Fetch {
             Event::Key(key) => match (key.modifiers, key.code) {
                 (_, KeyCode::Char(' ')) => {
                     if let Some(date) = self.fetch_start_date {
-                        self.start_fetch(date);
+                        self.start_fetch(FetchCheckpoint::start(date));
+                        status.consumed();
+                    }
+                }
+                (_, KeyCode::Char('x')) => {
+                    if let FetchingState::Fetching(_) = self.fetching_state {
+                        // the pages stored so far are kept
+                        self.cancel.cancel();
+                        status.consumed();
+                    }
+                }
+                (_, KeyCode::Char('R')) => {
+                    if let Some(checkpoint) = self.sync_state.checkpoint.clone() {
+                        self.start_fetch(checkpoint);
                         status.consumed();
                     }
                 }
@@ -272,9 +366,15 @@ impl EventLoopParticipant for Fetch {
                     status.consumed();
                 }
                 (_, KeyCode::Char('r')) => {
-                    self.local_db_cnt = self.manager.fetch_count().unwrap();
+                    self.refresh_counts();
                     status.consumed()
                 }
+                (_, KeyCode::Char('c')) if self.conflict_cnt > 0 => {
+                    self.tx.send(LayerManageAction::Push(
+                        Layers::Conflicts.into_push_config(false),
+                    ));
+                    status.consumed();
+                }
                 (_, KeyCode::Char('e')) => {
                     self.tx.send(LayerManageAction::Swap(Layers::CookieInput));
                     status.consumed();
@@ -301,7 +401,7 @@ impl EventLoopParticipant for Fetch {
 
 impl Layer for Fetch {
     fn init(&mut self) {
-        self.local_db_cnt = self.manager.fetch_count().unwrap();
+        self.refresh_counts();
 
         // make sure to load start_fetch_date
         self.move_focus(self.current_focus.clone());
@@ -322,13 +422,13 @@ impl Fetch {
             })
     }
 
-    fn fetch<T: Into<MealFetcher>>(
+    fn fetch(
         tx: UnboundedSender<FetchingAction>,
-        client: T,
-        date: DateTime<FixedOffset>,
+        manager: transactions::TransactionManager,
+        client: Arc<dyn TransactionSource>,
+        checkpoint: FetchCheckpoint,
+        cancel: CancellationToken,
     ) {
-        let client = client.into();
-
         let tx2 = tx.clone();
         let update_progress = move |progress: FetchProgress| {
             tx.send(FetchingAction::UpdateFetchStatus(FetchingState::Fetching(
@@ -337,41 +437,83 @@ impl Fetch {
             .context("Updating progress failed because layer was dropped while fetching")
         };
 
-        tokio::task::spawn_blocking(move || {
-            let records = match fetcher::fetch(date, client, update_progress)
+        tokio::spawn(async move {
+            // pages are stored as they arrive, so that a failed fetch can resume
+            let report = match manager
+                .fetch_and_store(checkpoint, client, cancel.clone(), update_progress)
+                .await
                 .context("Error fetching in Fetch page")
             {
-                Ok(data) => data,
+                Ok(report) => report,
+                Err(_) if cancel.is_cancelled() => {
+                    info!("Fetch cancelled");
+                    let _ = tx2.send(FetchingAction::Cancelled);
+                    return;
+                }
                 Err(e) => {
                     warn!("Error fetching data: {}", e);
+                    let error = format!("{:#}", e);
+                    let _ = tx2.send(match FetchError::of(&e) {
+                        Some(FetchError::SessionExpired) => FetchingAction::SessionExpired(error),
+                        _ => FetchingAction::Failed(error),
+                    });
                     return;
                 }
             };
 
-            info!("Fetch stopped with {} records", records.len());
+            info!("Fetch stopped, stored fetched transactions: {}", report);
 
             // This may fail if the layer is dropped while fetching
             // but we don't care about the error here
             let _ = tx2.send(FetchingAction::UpdateFetchStatus(FetchingState::Idle));
-            let _ = tx2.send(FetchingAction::InsertTransaction(records));
+            let _ = tx2.send(FetchingAction::Finished(report));
         });
     }
 
     fn update(&mut self, action: FetchingAction) {
         match action {
-            FetchingAction::InsertTransaction(transactions) => {
-                self.manager
-                    .insert(&transactions)
-                    .context("Error when inserting fetched transactions into database")
-                    .unwrap();
-                self.local_db_cnt = self.manager.fetch_count().unwrap();
+            FetchingAction::Finished(report) => {
+                self.last_report = Some(report);
+                self.refresh_counts();
+            }
+
+            FetchingAction::Failed(error) => {
+                self.fetching_state = FetchingState::Idle;
+                self.manager.record_sync_error(&error).unwrap();
+                self.refresh_counts();
+            }
+
+            FetchingAction::SessionExpired(error) => {
+                self.fetching_state = FetchingState::Idle;
+                self.manager.record_sync_error(&error).unwrap();
+                self.refresh_counts();
+                // the fetch can be resumed once the hallticket is updated
+                self.tx.send(LayerManageAction::Swap(Layers::CookieInput));
+            }
+
+            // a superseded fetch is cancelled too, but the page is busy with
+            // the new one then
+            FetchingAction::Cancelled if self.cancel.is_cancelled() => {
+                self.fetching_state = FetchingState::Idle;
+                self.refresh_counts();
             }
+            FetchingAction::Cancelled => {}
 
             FetchingAction::UpdateFetchStatus(state) => {
                 self.fetching_state = state.clone();
             }
         }
     }
+    /// Reload what the page shows about the local database
+    fn refresh_counts(&mut self) {
+        self.local_db_cnt = self.manager.fetch_count().unwrap();
+        self.conflict_cnt = self.manager.count_conflicts().unwrap();
+        self.sync_state = self.manager.sync_state().unwrap();
+        if let Focus::Incremental = self.current_focus {
+            self.fetch_start_date = self.manager.incremental_start().ok();
+        }
+    }
+
     fn move_focus(&mut self, focus: Focus) {
         self.current_focus = focus.clone();
 
@@ -383,6 +525,7 @@ impl Fetch {
         };
 
         self.fetch_start_date = match &self.current_focus {
+            Focus::Incremental => self.manager.incremental_start().ok(),
             Focus::P1Year => Some(get_date_from_now(365)),
             Focus::P1Month => Some(get_date_from_now(30)),
             Focus::P3Months => Some(get_date_from_now(90)),
@@ -396,21 +539,29 @@ impl Fetch {
         }
     }
 
-    fn start_fetch(&mut self, date: DateTime<FixedOffset>) {
-        let tx = self.self_tx.clone();
-
-        match &self.client {
-            MealFetcher::Real(c) => {
-                if let Ok((account, cookie)) = self.manager.get_account_cookie() {
-                    Fetch::fetch(tx, c.clone().account(account).cookie(cookie), date);
-                } else {
+    fn start_fetch(&mut self, checkpoint: FetchCheckpoint) {
+        let client = if self.client.capabilities().needs_credentials {
+            match self.manager.get_account_cookie() {
+                Ok((account, cookie)) => self.client.with_credentials(&account, &cookie),
+                Err(_) => {
                     self.tx.send(LayerManageAction::Swap(Layers::CookieInput));
+                    return;
                 }
             }
-            MealFetcher::Mock(c) => {
-                Fetch::fetch(tx, c.clone(), date);
-            }
-        }
+        } else {
+            self.client.clone()
+        };
+
+        // a fetch still running is superseded
+        self.cancel.cancel();
+        self.cancel = CancellationToken::new();
+        Fetch::fetch(
+            self.self_tx.clone(),
+            self.manager.clone(),
+            client,
+            checkpoint,
+            self.cancel.clone(),
+        );
     }
 }
 
@@ -439,18 +590,19 @@ mod test {
     #[test]
     fn test_navigation() {
         let (_, mut page) = get_test_objs();
-        assert!(matches!(page.current_focus, Focus::P1Year));
+        assert!(matches!(page.current_focus, Focus::Incremental));
 
         let event_result = [
+            ('j', Focus::P1Year),
             ('j', Focus::P3Months),
             ('j', Focus::P1Month),
             ('l', Focus::UserInput),
-            ('l', Focus::P1Year),
-            ('l', Focus::UserInput),
+            ('l', Focus::Incremental),
             ('k', Focus::UserInput),
             ('k', Focus::P1Month),
             ('h', Focus::P3Months),
             ('h', Focus::P1Year),
+            ('h', Focus::Incremental),
         ];
 
         for (key, _result) in event_result.into_iter() {
@@ -459,6 +611,87 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_incremental_start() {
+        let (_, mut page) = get_test_objs();
+        let data = crate::libs::fetcher::test_utils::get_mock_data(10);
+        page.manager.insert(&data).unwrap();
+        page.manager.record_sync_success().unwrap();
+        let newest = data.iter().map(|t| t.time).max().unwrap();
+
+        page.handle_event_with_status_check(&'r'.into());
+        assert_eq!(
+            page.fetch_start_date,
+            Some(newest - chrono::Duration::seconds(1))
+        );
+        assert!(page.sync_state.last_success.is_some());
+
+        page.update(FetchingAction::Failed("session expired".to_string()));
+        assert!(matches!(page.fetching_state, FetchingState::Idle));
+        assert_eq!(
+            page.sync_state.last_error.as_deref(),
+            Some("session expired")
+        );
+    }
+
+    #[test]
+    fn test_session_expired() {
+        let (mut rx, mut page) = get_test_objs();
+        page.update(FetchingAction::SessionExpired(
+            "The session has expired".to_string(),
+        ));
+        assert!(matches!(page.fetching_state, FetchingState::Idle));
+        assert!(page.sync_state.last_error.is_some());
+
+        let mut swapped = false;
+        while let Ok(action) = rx.try_recv() {
+            if let Action::Layer(LayerManageAction::Swap(Layers::CookieInput)) = action {
+                swapped = true;
+            }
+        }
+        assert!(swapped, "Should ask for a new hallticket");
+    }
+
+    #[tokio::test]
+    async fn test_cancel_fetch() {
+        let (_, page) = get_test_objs();
+        let mut page = page.client(Arc::new(
+            crate::libs::fetcher::MockMealFetcher::default()
+                .per_page(5)
+                .set_sim_delay(std::time::Duration::from_millis(200)),
+        ));
+        let since = OFFSET_UTC_PLUS8
+            .with_ymd_and_hms(2022, 1, 1, 0, 0, 0)
+            .unwrap();
+        page.start_fetch(FetchCheckpoint::start(since));
+
+        let timeout = tokio::time::sleep(std::time::Duration::from_secs(10));
+        tokio::pin!(timeout);
+        loop {
+            tokio::select! {
+                Some(action) = page.self_rx.recv() => {
+                    let cancelled = matches!(action, FetchingAction::Cancelled);
+                    page.update(action);
+                    match &page.fetching_state {
+                        // progress is reported once the page is stored
+                        FetchingState::Fetching(progress) if progress.current_page == 2 => {
+                            page.handle_event_with_status_check(&'x'.into());
+                        }
+                        FetchingState::Idle if cancelled => break,
+                        _ => (),
+                    }
+                }
+                _ = &mut timeout => panic!("Fetch was not cancelled"),
+            }
+        }
+
+        assert_eq!(page.local_db_cnt, 10);
+        assert!(page.sync_state.checkpoint.is_some());
+        // cancelling is not a failure
+        assert_eq!(page.sync_state.last_error, None);
+        assert!(page.last_report.is_none());
+    }
+
     #[test]
     fn test_user_input() {
         let (_, mut page) = get_test_objs();
@@ -503,12 +736,19 @@ mod test {
     async fn test_fetch() {
         let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<FetchingAction>();
 
-        let client = MealFetcher::Mock(fetcher::MockMealFetcher::default());
+        let client = Arc::new(crate::libs::fetcher::MockMealFetcher::default());
         let date = OFFSET_UTC_PLUS8
-            .with_ymd_and_hms(2025, 03, 1, 0, 0, 0)
+            .with_ymd_and_hms(2025, 3, 1, 0, 0, 0)
             .unwrap();
 
-        Fetch::fetch(tx.into(), client, date);
+        let manager = TransactionManager::new(None).unwrap();
+        Fetch::fetch(
+            tx,
+            manager.clone(),
+            client,
+            FetchCheckpoint::start(date),
+            CancellationToken::new(),
+        );
 
         let timeout = tokio::time::sleep(std::time::Duration::from_secs(10));
         tokio::pin!(timeout);
@@ -535,10 +775,14 @@ mod test {
                                 }
                             }
                         }
-                        FetchingAction::InsertTransaction(transactions) => {
-                            assert!(!transactions.is_empty(), "Should receive some transactions");
+                        FetchingAction::Finished(report) => {
+                            assert!(report.new > 0, "Should store some transactions");
+                            assert_eq!(report.new, manager.fetch_count().unwrap());
                             received_insert = true;
                         }
+                        FetchingAction::Failed(error) => panic!("Fetching failed: {}", error),
+                        FetchingAction::Cancelled => panic!("Fetching was cancelled"),
+                        FetchingAction::SessionExpired(error) => panic!("Session expired: {}", error),
                     }
 
                     // Exit loop when we've received all expected actions
@@ -572,15 +816,15 @@ mod test {
 
         page.manager.update_account("account").unwrap();
         page.manager.update_cookie("cookie").unwrap();
-        let mut page = page.client(MealFetcher::Mock(fetcher::MockMealFetcher::default()));
+        let mut page = page.client(Arc::new(crate::libs::fetcher::MockMealFetcher::default()));
 
         page.handle_event_with_status_check(&'h'.into());
 
         let mut seq: Vec<Event> = vec![KeyCode::Enter.into()];
-        seq.extend("2024-09-01".chars().map(|c| Event::from(c)));
+        seq.extend("2024-09-01".chars().map(Event::from));
         seq.push(KeyCode::Enter.into());
         seq.iter().for_each(|e| {
-            page.handle_event_with_status_check(&e);
+            page.handle_event_with_status_check(e);
         });
 
         // start fetching
@@ -596,12 +840,9 @@ mod test {
             tokio::select! {
                 Some(action) = page.self_rx.recv() => {
 
-                    match &action {
-                        FetchingAction::InsertTransaction(transactions) => {
-                            assert!(!transactions.is_empty(), "Should receive some transactions");
-                            received_insert = true;
-                        }
-                        _ => {}
+                    if let FetchingAction::Finished(report) = &action {
+                        assert!(report.new > 0, "Should store some transactions");
+                        received_insert = true;
                     }
 
                     page.update(action);
@@ -631,6 +872,11 @@ mod test {
             received_insert,
             "Should have received insert transaction action"
         );
+        let report = page
+            .last_report
+            .expect("Should report the inserted transactions");
+        assert_eq!(report.new, page.local_db_cnt);
+        assert_eq!(report.duplicate + report.conflict, 0);
         assert!(
             last_progress > 0,
             "Should have received at least one progress update"
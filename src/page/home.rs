@@ -25,6 +25,7 @@ impl Home {
     fn get_help_msg(&self) -> HelpMsg {
         let help_msg: HelpMsg = vec![
             HelpEntry::new('T', "Go to transactions page"),
+            HelpEntry::new('p', "Switch account profile"),
             HelpEntry::new('q', "Quit"),
             HelpEntry::new('?', "Show help"),
         ]
@@ -91,6 +92,12 @@ impl EventLoopParticipant for Home {
                     ));
                     status.consumed();
                 }
+                KeyCode::Char('p') => {
+                    self.tx.send(LayerManageAction::Push(
+                        Layers::Profiles.into_push_config(false),
+                    ));
+                    status.consumed();
+                }
                 KeyCode::Char('T') => {
                     self.tx.send(LayerManageAction::Push(
                         Layers::Transaction(None).into_push_config(false),
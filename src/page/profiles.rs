@@ -0,0 +1,240 @@
+use crossterm::event::KeyCode;
+use ratatui::{
+    Frame,
+    layout::{Constraint, Layout, Rect},
+    style::{Modifier, Style, palette::tailwind},
+    text::Line,
+    widgets::{Block, BorderType, Borders, HighlightSpacing, List, ListItem, ListState, Padding},
+};
+
+use crate::{
+    actions::{ActionSender, LayerManageAction, Layers},
+    app::layer_manager::EventHandlingStatus,
+    component::input::{InputComp, InputMode},
+    libs::transactions::TransactionManager,
+    tui::Event,
+    utils::help_msg::{HelpEntry, HelpMsg},
+};
+
+use super::{EventLoopParticipant, Layer, WidgetExt};
+
+/// Account profile switcher
+///
+/// Lists the profiles in the database, switches the active one for every page
+/// and creates new profiles.
+pub struct Profiles {
+    tx: ActionSender,
+    manager: TransactionManager,
+
+    profiles: Vec<String>,
+    list_state: ListState,
+
+    new_profile_input: InputComp,
+}
+
+impl Profiles {
+    pub fn new(tx: ActionSender, manager: TransactionManager) -> Self {
+        Self {
+            tx,
+            manager,
+            profiles: Vec::new(),
+            list_state: ListState::default(),
+            new_profile_input: InputComp::new().title("New profile"),
+        }
+    }
+
+    /// Reload the profile list and select the active profile
+    fn reload(&mut self) {
+        self.profiles = self.manager.list_profiles().unwrap_or_default();
+        let active = self.manager.profile();
+        self.list_state
+            .select(self.profiles.iter().position(|p| *p == active));
+    }
+
+    fn switch_to(&mut self, name: &str) {
+        self.manager.switch_profile(name).unwrap();
+        self.reload();
+    }
+
+    fn get_help_msg(&self) -> HelpMsg {
+        if self.new_profile_input.is_inputting() {
+            return self.new_profile_input.get_help_msg();
+        }
+        vec![
+            HelpEntry::new('j', "Go Down"),
+            HelpEntry::new('k', "Go Up"),
+            HelpEntry::new(KeyCode::Enter, "Switch to profile"),
+            HelpEntry::new('n', "New profile"),
+            HelpEntry::new('?', "Help"),
+            HelpEntry::new(KeyCode::Esc, "Back"),
+        ]
+        .into()
+    }
+}
+
+impl Layer for Profiles {
+    fn init(&mut self) {
+        self.reload();
+    }
+}
+
+impl WidgetExt for Profiles {
+    fn render(&mut self, frame: &mut Frame, area: Rect) {
+        let [list_area, input_area, help_area] = Layout::vertical([
+            Constraint::Fill(1),
+            Constraint::Length(3),
+            Constraint::Length(3),
+        ])
+        .areas(area);
+
+        let active = self.manager.profile();
+        let items = self.profiles.iter().map(|name| {
+            if *name == active {
+                ListItem::new(format!("{} (active)", name))
+                    .style(Style::default().fg(tailwind::BLUE.c400))
+            } else {
+                ListItem::new(name.as_str())
+            }
+        });
+        let list = List::new(items)
+            .block(
+                Block::new()
+                    .title(Line::raw("Account profiles").centered())
+                    .border_type(BorderType::Rounded)
+                    .borders(Borders::ALL)
+                    .padding(Padding::horizontal(1)),
+            )
+            .highlight_style(
+                Style::default()
+                    .add_modifier(Modifier::REVERSED)
+                    .fg(tailwind::INDIGO.c300),
+            )
+            .highlight_spacing(HighlightSpacing::Always);
+        frame.render_stateful_widget(list, list_area, &mut self.list_state);
+
+        self.new_profile_input.render(frame, input_area);
+        self.get_help_msg().render(frame, help_area);
+    }
+}
+
+impl EventLoopParticipant for Profiles {
+    fn handle_events(&mut self, event: &Event) -> EventHandlingStatus {
+        let (input_status, input_result) = self.new_profile_input.handle_events(event);
+        if let Some(name) = input_result {
+            self.new_profile_input = InputComp::new().title("New profile");
+            if !name.trim().is_empty() {
+                self.switch_to(&name);
+            }
+        }
+        if matches!(input_status, EventHandlingStatus::Consumed) {
+            // leaving input mode returns focus to the list
+            if !self.new_profile_input.is_inputting() {
+                self.new_profile_input.set_mode(InputMode::Idle);
+            }
+            return input_status;
+        }
+
+        let mut status = EventHandlingStatus::default();
+        if let Event::Key(key) = event {
+            match key.code {
+                KeyCode::Char('j') => {
+                    self.list_state.select_next();
+                    status.consumed();
+                }
+                KeyCode::Char('k') => {
+                    self.list_state.select_previous();
+                    status.consumed();
+                }
+                KeyCode::Enter => {
+                    if let Some(name) = self
+                        .list_state
+                        .selected()
+                        .and_then(|i| self.profiles.get(i))
+                        .cloned()
+                    {
+                        self.switch_to(&name);
+                        self.tx.send(LayerManageAction::Pop);
+                    }
+                    status.consumed();
+                }
+                KeyCode::Char('n') => {
+                    self.new_profile_input.set_mode(InputMode::Inputting);
+                    status.consumed();
+                }
+                KeyCode::Char('?') => {
+                    self.tx.send(LayerManageAction::Push(
+                        Layers::Help(self.get_help_msg()).into_push_config(true),
+                    ));
+                    status.consumed();
+                }
+                KeyCode::Esc => {
+                    self.tx.send(LayerManageAction::Pop);
+                    status.consumed();
+                }
+                _ => {}
+            }
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use insta::assert_snapshot;
+    use ratatui::{Terminal, backend::TestBackend};
+    use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+    use super::*;
+    use crate::actions::Action;
+
+    fn get_test_objs() -> (UnboundedReceiver<Action>, Profiles) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let manager = TransactionManager::new(None).unwrap();
+        manager.create_profile("roommate").unwrap();
+        let mut page = Profiles::new(tx.into(), manager);
+        page.init();
+        (rx, page)
+    }
+
+    #[test]
+    fn test_switch_profile() {
+        let (mut rx, mut page) = get_test_objs();
+        assert_eq!(page.profiles, vec!["default", "roommate"]);
+        assert_eq!(page.list_state.selected(), Some(0));
+
+        page.handle_event_with_status_check(&'j'.into());
+        page.handle_event_with_status_check(&KeyCode::Enter.into());
+        assert_eq!(page.manager.profile(), "roommate");
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(Action::Layer(LayerManageAction::Pop))
+        ));
+    }
+
+    #[test]
+    fn test_new_profile() {
+        let (_, mut page) = get_test_objs();
+
+        page.handle_event_with_status_check(&'n'.into());
+        assert!(page.new_profile_input.is_inputting());
+        // list keys are typed into the input while inputting
+        page.handle_event_with_status_check(&'j'.into());
+        page.handle_event_with_status_check(&'k'.into());
+        page.handle_event_with_status_check(&KeyCode::Enter.into());
+
+        assert!(!page.new_profile_input.is_inputting());
+        assert_eq!(page.manager.profile(), "jk");
+        assert_eq!(page.profiles, vec!["default", "jk", "roommate"]);
+        assert_eq!(page.list_state.selected(), Some(1));
+    }
+
+    #[test]
+    fn test_render() {
+        let (_, mut page) = get_test_objs();
+        let mut terminal = Terminal::new(TestBackend::new(80, 15)).unwrap();
+        terminal
+            .draw(|frame| page.render(frame, frame.area()))
+            .unwrap();
+        assert_snapshot!(terminal.backend());
+    }
+}
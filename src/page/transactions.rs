@@ -3,13 +3,24 @@ use std::cmp::max;
 use crate::{
     actions::{ActionSender, LayerManageAction, Layers},
     app::layer_manager::EventHandlingStatus,
-    libs::transactions::{FilterOptions, Transaction, TransactionManager},
+    component::input::{InputComp, InputMode},
+    libs::{
+        money::Money,
+        transactions::{
+            FilterOptions, MerchantMatch, OFFSET_UTC_PLUS8, Transaction, TransactionKind,
+            TransactionManager, normalize_tags,
+        },
+    },
     tui::Event,
-    utils::help_msg::{HelpEntry, HelpMsg},
+    utils::{
+        help_msg::{HelpEntry, HelpMsg},
+        merchant_class::MerchantType,
+    },
 };
 
 use super::{EventLoopParticipant, Layer, WidgetExt};
-use color_eyre::eyre::Context;
+use chrono::{DateTime, FixedOffset, Utc};
+use color_eyre::eyre::{Context, Result, bail};
 use crossterm::event::KeyCode;
 use ratatui::{
     Frame,
@@ -21,6 +32,7 @@ use ratatui::{
         ScrollbarState, Table, TableState,
     },
 };
+use tracing::warn;
 use unicode_width::UnicodeWidthStr;
 
 struct TableColors {
@@ -51,6 +63,9 @@ const TABLE_COLORS: TableColors = TableColors {
 
 const ITEM_HEIGHT: usize = 3;
 
+/// Number of transactions loaded from the database at a time
+const PAGE_SIZE: u64 = 100;
+
 #[derive(Clone, Debug)]
 pub struct Transactions {
     filter_option: Option<FilterOptions>,
@@ -58,12 +73,37 @@ pub struct Transactions {
     manager: TransactionManager,
 
     transactions: Vec<crate::libs::transactions::Transaction>,
+    /// Number of transactions matching the filter, loaded or not
+    total: usize,
 
     table_state: TableState,
     scroll_state: ScrollbarState,
     longest_item_lens: (usize, usize, usize),
+
+    /// Merchant search, annotation or manual transaction being typed, shown
+    /// instead of the help
+    input: InputComp,
+    editing: Editing,
+    /// Whether hidden transactions are listed too
+    show_hidden: bool,
 }
 
+/// What the text typed into [`Transactions::input`] is for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+enum Editing {
+    /// Fuzzy merchant search
+    #[default]
+    Search,
+    /// Note of the transaction at this index
+    Note(usize),
+    /// Comma separated tags of the transaction at this index
+    Tags(usize),
+    /// New manual transaction, see [`parse_manual_entry`]
+    Manual,
+}
+
+const MANUAL_TITLE: &str = "Add manual ([YYYY-MM-DD HH:MM] amount merchant, +amount for income)";
+
 impl Transactions {
     pub fn new(
         filter_option: Option<FilterOptions>,
@@ -76,16 +116,24 @@ impl Transactions {
             manager,
 
             transactions: Default::default(),
+            total: 0,
 
             table_state: TableState::default(),
             scroll_state: ScrollbarState::default(),
             longest_item_lens: (0, 0, 0),
+
+            input: InputComp::new().title("Search merchant"),
+            editing: Editing::Search,
+            show_hidden: false,
         };
         t.load_from_db();
         t
     }
 
     fn get_help_msg(&self) -> HelpMsg {
+        if self.input.is_inputting() {
+            return self.input.get_help_msg();
+        }
         let mut help_msg = HelpMsg::default();
 
         help_msg.push(HelpEntry::new('?', "Show help"));
@@ -96,6 +144,21 @@ impl Transactions {
         }
 
         help_msg.push(HelpEntry::new(' ', "Filter this merchant"));
+        help_msg.push(HelpEntry::new('c', "Filter this category"));
+        help_msg.push(HelpEntry::new('/', "Search merchants"));
+        help_msg.push(HelpEntry::new('n', "Edit note"));
+        help_msg.push(HelpEntry::new('t', "Edit tags"));
+        help_msg.push(HelpEntry::new('a', "Add manual"));
+        help_msg.push(HelpEntry::new('x', "Hide/unhide"));
+        help_msg.push(HelpEntry::new('D', "Delete manual"));
+        help_msg.push(HelpEntry::new(
+            'H',
+            if self.show_hidden {
+                "Leave out hidden"
+            } else {
+                "Show hidden"
+            },
+        ));
         help_msg.push(HelpEntry::new('l', "Load from local cache"));
 
         help_msg
@@ -140,21 +203,116 @@ impl WidgetExt for Transactions {
         self.render_table(frame, main_area);
         self.render_scrollbar(frame, main_area);
 
-        self.get_help_msg().render(frame, help_area);
+        if self.input.is_inputting() {
+            self.input.render(frame, help_area);
+        } else {
+            self.get_help_msg().render(frame, help_area);
+        }
+    }
+}
+
+impl Transactions {
+    fn selected_merchant(&self) -> Option<String> {
+        self.table_state
+            .selected()
+            .and_then(|i| self.transactions.get(i))
+            .map(|t| t.merchant.clone())
+    }
+
+    /// Start typing into the input, prefilled with `text`
+    fn start_input(&mut self, editing: Editing, title: &str, text: String) {
+        self.input = InputComp::new().title(title).init_text(text);
+        self.input.set_mode(InputMode::Inputting);
+        self.editing = editing;
+    }
+
+    /// Act on the submitted text of the input
+    fn submit_input(&mut self, text: &str) {
+        match self.editing {
+            Editing::Search => {
+                if !text.trim().is_empty() {
+                    self.push_filtered(|f| f.search_merchant(text.trim(), MerchantMatch::Fuzzy));
+                }
+            }
+            Editing::Note(i) => {
+                let id = self.transactions[i].id;
+                self.manager
+                    .set_note(id, text)
+                    .with_context(|| format!("Failed to save note of transaction {}", id))
+                    .unwrap();
+                let note = text.trim();
+                self.transactions[i].note = (!note.is_empty()).then(|| note.to_string());
+            }
+            Editing::Tags(i) => {
+                let id = self.transactions[i].id;
+                let tags: Vec<&str> = text.split([',', '，']).collect();
+                self.manager
+                    .set_tags(id, &tags)
+                    .with_context(|| format!("Failed to save tags of transaction {}", id))
+                    .unwrap();
+                let mut tags = normalize_tags(&tags);
+                tags.sort();
+                self.transactions[i].tags = tags;
+            }
+            Editing::Manual => {
+                let now = Utc::now().with_timezone(&OFFSET_UTC_PLUS8);
+                match parse_manual_entry(text, now) {
+                    Ok(transaction) => {
+                        self.manager
+                            .add_manual(&transaction)
+                            .context("Failed to add manual transaction")
+                            .unwrap();
+                        self.load_from_db();
+                    }
+                    Err(e) => {
+                        // keep the text for fixing the mistake
+                        warn!("Invalid manual transaction {:?}: {}", text, e);
+                        let title = format!("{}: {}", MANUAL_TITLE, e);
+                        self.start_input(Editing::Manual, &title, text.to_string());
+                    }
+                }
+            }
+        }
+        self.longest_item_lens = constraint_len_calculator(&self.transactions, HEADER_STR);
+    }
+
+    /// Index and transaction of the selected row
+    fn selected(&self) -> Option<(usize, &Transaction)> {
+        let i = self.table_state.selected()?;
+        self.transactions.get(i).map(|t| (i, t))
+    }
+
+    /// Push a page showing the transactions of this page narrowed down further
+    fn push_filtered(&self, narrow: impl FnOnce(FilterOptions) -> FilterOptions) {
+        let filter = narrow(self.filter_option.clone().unwrap_or_default());
+        let layer = Layers::Transaction(Some(Box::new(filter)));
+        self.tx
+            .send(LayerManageAction::Push(layer.into_push_config(false)));
     }
 }
 
 impl EventLoopParticipant for Transactions {
     fn handle_events(&mut self, event: &Event) -> EventHandlingStatus {
+        let (input_status, text) = self.input.handle_events(event);
+        if let Some(text) = text {
+            self.submit_input(&text);
+        }
+        if matches!(input_status, EventHandlingStatus::Consumed) {
+            // leaving input mode returns focus to the table
+            if !self.input.is_inputting() {
+                self.input = InputComp::new().title("Search merchant");
+                self.editing = Editing::Search;
+            }
+            return input_status;
+        }
+
         let mut status = EventHandlingStatus::default();
         if let Event::Key(key) = event {
             match (key.modifiers, key.code) {
                 // navigate to fetch page
-                (_, KeyCode::Char('f')) => {
-                    if self.filter_option.is_none() {
-                        self.tx.send(LayerManageAction::Swap(Layers::Fetch));
-                        status.consumed();
-                    }
+                (_, KeyCode::Char('f')) if self.filter_option.is_none() => {
+                    self.tx.send(LayerManageAction::Swap(Layers::Fetch));
+                    status.consumed();
                 }
                 (_, KeyCode::Char('l')) => {
                     self.load_from_db();
@@ -175,20 +333,71 @@ impl EventLoopParticipant for Transactions {
                     status.consumed();
                 }
                 (_, KeyCode::Char(' ')) => {
-                    if let Some(index) = self.table_state.selected() {
-                        if let Some(transaction) = self.transactions.get(index) {
-                            let layer = Layers::Transaction(Some(
-                                self.filter_option
-                                    .clone()
-                                    .unwrap_or_default()
-                                    .merchant(transaction.merchant.clone()),
-                            ));
-                            self.tx
-                                .send(LayerManageAction::Push(layer.into_push_config(false)));
-                        }
+                    if let Some(merchant) = self.selected_merchant() {
+                        self.push_filtered(|f| f.merchant(merchant));
                     }
                     status.consumed();
                 }
+                (_, KeyCode::Char('c')) => {
+                    if let Some(merchant) = self.selected_merchant() {
+                        self.push_filtered(|f| f.category(MerchantType::of_merchant(&merchant)));
+                    }
+                    status.consumed();
+                }
+                (_, KeyCode::Char('/')) => {
+                    self.start_input(Editing::Search, "Search merchant", String::new());
+                    status.consumed();
+                }
+                (_, KeyCode::Char('n')) => {
+                    if let Some((i, t)) = self.selected() {
+                        let note = t.note.clone().unwrap_or_default();
+                        self.start_input(Editing::Note(i), "Note", note);
+                    }
+                    status.consumed();
+                }
+                (_, KeyCode::Char('t')) => {
+                    if let Some((i, t)) = self.selected() {
+                        let tags = t.tags.join(", ");
+                        self.start_input(Editing::Tags(i), "Tags (comma separated)", tags);
+                    }
+                    status.consumed();
+                }
+                (_, KeyCode::Char('a')) => {
+                    self.start_input(Editing::Manual, MANUAL_TITLE, String::new());
+                    status.consumed();
+                }
+                (_, KeyCode::Char('x')) => {
+                    if let Some((i, t)) = self.selected() {
+                        let (id, hidden) = (t.id, !t.hidden);
+                        self.manager
+                            .set_hidden(id, hidden)
+                            .with_context(|| format!("Failed to hide transaction {}", id))
+                            .unwrap();
+                        // kept in the list until reloaded, so it can be unhidden right away
+                        self.transactions[i].hidden = hidden;
+                        self.longest_item_lens =
+                            constraint_len_calculator(&self.transactions, HEADER_STR);
+                    }
+                    status.consumed();
+                }
+                (_, KeyCode::Char('D')) => {
+                    if let Some((_, t)) = self.selected()
+                        && t.manual
+                    {
+                        let id = t.id;
+                        self.manager
+                            .delete_manual(id)
+                            .with_context(|| format!("Failed to delete transaction {}", id))
+                            .unwrap();
+                        self.load_from_db();
+                    }
+                    status.consumed();
+                }
+                (_, KeyCode::Char('H')) => {
+                    self.show_hidden = !self.show_hidden;
+                    self.load_from_db();
+                    status.consumed();
+                }
                 (_, KeyCode::Esc) => {
                     self.tx.send(LayerManageAction::Pop);
                     status.consumed();
@@ -255,7 +464,7 @@ impl Transactions {
             Row::new(vec![
                 Text::from(format!("\n{}\n", t.amount)).alignment(Alignment::Right),
                 Text::from(format!("\n{}\n", t.time.format("%Y-%m-%d %H:%M"))),
-                Text::from(format!("\n{}\n", t.merchant)),
+                Text::from(format!("\n{}\n", merchant_cell(t))),
             ])
             .style(Style::new().fg(TABLE_COLORS.row_fg).bg(color))
             .height(ITEM_HEIGHT as u16)
@@ -296,34 +505,35 @@ impl Transactions {
         );
     }
 
-    fn load_from_db(&mut self) {
-        match &self.filter_option {
-            Some(option) => {
-                self.transactions = self
-                    .manager
-                    .fetch_filtered(option)
-                    .with_context(|| {
-                        format!(
-                            "Failed to load transactions from database with filter: {:?}",
-                            option
-                        )
-                    })
-                    .unwrap();
-            }
-            None => {
-                self.transactions = self
-                    .manager
-                    .fetch_all()
-                    .context("Failed to load transactions from database")
-                    .unwrap();
-            }
+    /// Filter of the listed transactions
+    fn filter(&self) -> FilterOptions {
+        let filter = self.filter_option.clone().unwrap_or_default();
+        match self.show_hidden {
+            true => filter.include_hidden(),
+            false => filter,
         }
-        self.transactions.sort_by(|a, b| b.time.cmp(&a.time));
+    }
+
+    /// Reload from the first page, newest first
+    fn load_from_db(&mut self) {
+        let option = self.filter();
+        self.total = self
+            .manager
+            .fetch_filtered_count(&option)
+            .with_context(|| {
+                format!(
+                    "Failed to count transactions in database with filter: {:?}",
+                    option
+                )
+            })
+            .unwrap() as usize;
+        self.transactions.clear();
+        self.load_more(PAGE_SIZE);
+
         self.scroll_state = self
             .scroll_state
-            .content_length(self.transactions.len() * ITEM_HEIGHT)
+            .content_length(self.total * ITEM_HEIGHT)
             .position(0);
-        self.longest_item_lens = constraint_len_calculator(&self.transactions, HEADER_STR);
         if self.transactions.is_empty() {
             self.table_state.select(None);
         } else {
@@ -331,9 +541,29 @@ impl Transactions {
         }
     }
 
+    /// Append up to `count` transactions after the ones already loaded
+    fn load_more(&mut self, count: u64) {
+        let option = self
+            .filter()
+            .offset(self.transactions.len() as u64)
+            .limit(count);
+        let page = self
+            .manager
+            .fetch_filtered(&option)
+            .with_context(|| {
+                format!(
+                    "Failed to load transactions from database with filter: {:?}",
+                    option
+                )
+            })
+            .unwrap();
+        self.transactions.extend(page);
+        self.longest_item_lens = constraint_len_calculator(&self.transactions, HEADER_STR);
+    }
+
     fn change_focus(&mut self, index: isize) {
         let cur_index = self.table_state.selected().unwrap_or(0);
-        let max = self.transactions.len();
+        let max = self.total;
         if max == 0 {
             return;
         }
@@ -343,6 +573,12 @@ impl Transactions {
         } else {
             (cur_index as isize + index) as usize % max
         };
+        // load the next page before reaching the end of the loaded ones
+        if new_index + 1 >= self.transactions.len() && self.transactions.len() < self.total {
+            let missing =
+                (new_index + 1 + PAGE_SIZE as usize).saturating_sub(self.transactions.len());
+            self.load_more(missing as u64);
+        }
         self.table_state.select(Some(new_index));
         self.scroll_state = self.scroll_state.position(new_index * ITEM_HEIGHT);
     }
@@ -350,6 +586,55 @@ impl Transactions {
 
 const HEADER_STR: &[&str] = &["金额", "时间", "商家"];
 
+/// Text of the merchant column, prefixed with the kind for money coming in
+/// and whether it is manual or hidden, and followed by the tags and note
+fn merchant_cell(t: &Transaction) -> String {
+    let mut cell = match t.kind {
+        TransactionKind::Expense => t.merchant.clone(),
+        kind => format!("[{}] {}", kind.label(), t.merchant)
+            .trim_end()
+            .to_string(),
+    };
+    if t.manual {
+        cell = format!("[手动] {}", cell);
+    }
+    if t.hidden {
+        cell = format!("[隐藏] {}", cell);
+    }
+    for tag in &t.tags {
+        cell.push_str(&format!(" #{}", tag));
+    }
+    if let Some(note) = &t.note {
+        cell.push_str(&format!(" ({})", note));
+    }
+    cell
+}
+
+/// Parse a manual transaction typed as `[YYYY-MM-DD HH:MM] amount merchant`
+///
+/// The time defaults to `now`. A plain amount is money spent, one starting
+/// with `+` money coming in.
+fn parse_manual_entry(text: &str, now: DateTime<FixedOffset>) -> Result<Transaction> {
+    let mut rest = text.trim();
+    let mut time = now;
+    if let Some(parsed) = rest
+        .get(..16)
+        .and_then(|s| Transaction::parse_to_fixed_utc_plus8(s, "%Y-%m-%d %H:%M").ok())
+    {
+        time = parsed;
+        rest = rest[16..].trim_start();
+    }
+    let Some((amount, merchant)) = rest.split_once(char::is_whitespace) else {
+        bail!("Expected an amount and a merchant");
+    };
+    let amount: Money = amount.parse()?;
+    let amount = match amount.is_negative() || rest.starts_with('+') {
+        true => amount,
+        false => -amount,
+    };
+    Ok(Transaction::new(amount, merchant.trim().to_string(), time))
+}
+
 fn constraint_len_calculator(items: &[Transaction], header: &[&str]) -> (usize, usize, usize) {
     let data_len = items.iter().fold((0, 0, 0), |acc, item| {
         let amount_len = max(
@@ -362,7 +647,7 @@ fn constraint_len_calculator(items: &[Transaction], header: &[&str]) -> (usize,
         );
         let merchant_len = max(
             acc.2,
-            UnicodeWidthStr::width_cjk(item.merchant.to_string().as_str()),
+            UnicodeWidthStr::width_cjk(merchant_cell(item).as_str()),
         );
         (amount_len, time_len, merchant_len)
     });
@@ -438,6 +723,35 @@ mod test {
             });
     }
 
+    #[test]
+    fn lazy_loading() {
+        let (_, mut transaction) = get_test_objs(None, 300);
+        let total = transaction.total;
+        assert!(total > PAGE_SIZE as usize);
+        assert_eq!(transaction.transactions.len(), PAGE_SIZE as usize);
+
+        for _ in 0..PAGE_SIZE - 1 {
+            transaction.handle_event_with_status_check(&'j'.into());
+        }
+        assert_eq!(transaction.transactions.len(), 2 * PAGE_SIZE as usize);
+
+        // wrapping around to the end loads everything in between
+        let (_, mut transaction) = get_test_objs(None, 300);
+        transaction.handle_event_with_status_check(&'k'.into());
+        assert_eq!(transaction.table_state.selected(), Some(total - 1));
+        assert_eq!(transaction.transactions.len(), total);
+
+        let all = transaction.manager.fetch_all().unwrap();
+        assert_eq!(
+            transaction
+                .transactions
+                .iter()
+                .map(|t| t.id)
+                .collect::<Vec<_>>(),
+            all.iter().map(|t| t.id).collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn navigation() {
         let (_, mut transaction) = get_test_objs(None, 50);
@@ -504,6 +818,139 @@ mod test {
         assert_snapshot!(terminal.backend());
     }
 
+    #[test]
+    fn search_merchant() {
+        let (mut rx, mut transaction) = get_test_objs(None, 50);
+        transaction.handle_event_with_status_check(&'/'.into());
+        assert!(transaction.input.is_inputting());
+        // navigation keys are typed into the search while inputting
+        "jst".chars().for_each(|c| {
+            transaction.handle_event_with_status_check(&c.into());
+        });
+        assert_eq!(transaction.table_state.selected(), Some(0));
+        transaction.handle_event_with_status_check(&KeyCode::Enter.into());
+        assert!(!transaction.input.is_inputting());
+
+        let Ok(Action::Layer(LayerManageAction::Push(PushPageConfig {
+            layer: Layers::Transaction(Some(filter)),
+            ..
+        }))) = rx.try_recv()
+        else {
+            panic!("Should push a filtered page")
+        };
+        assert_eq!(
+            *filter,
+            FilterOptions::default().search_merchant("jst", MerchantMatch::Fuzzy)
+        );
+    }
+
+    #[test]
+    fn edit_note_and_tags() {
+        let (_, mut transaction) = get_test_objs(None, 50);
+        transaction.handle_event_with_status_check(&'j'.into());
+        let id = transaction.transactions[1].id;
+
+        transaction.handle_event_with_status_check(&'n'.into());
+        assert!(transaction.input.is_inputting());
+        "treat".chars().for_each(|c| {
+            transaction.handle_event_with_status_check(&c.into());
+        });
+        transaction.handle_event_with_status_check(&KeyCode::Enter.into());
+
+        transaction.handle_event_with_status_check(&'t'.into());
+        "exam, fun".chars().for_each(|c| {
+            transaction.handle_event_with_status_check(&c.into());
+        });
+        transaction.handle_event_with_status_check(&KeyCode::Enter.into());
+        assert!(!transaction.input.is_inputting());
+
+        let edited = &transaction.transactions[1];
+        assert_eq!(edited.note.as_deref(), Some("treat"));
+        assert_eq!(edited.tags, vec!["exam", "fun"]);
+        assert!(merchant_cell(edited).ends_with(" #exam #fun (treat)"));
+        let stored = transaction
+            .manager
+            .fetch_all()
+            .unwrap()
+            .into_iter()
+            .find(|t| t.id == id)
+            .unwrap();
+        assert_eq!(stored.note, edited.note);
+        assert_eq!(stored.tags, edited.tags);
+
+        // editing starts from the current value, and Esc leaves it unchanged
+        transaction.handle_event_with_status_check(&'t'.into());
+        assert_eq!(transaction.input.get_text(), "exam, fun");
+        transaction.handle_event_with_status_check(&KeyCode::Esc.into());
+        assert_eq!(transaction.transactions[1].tags, vec!["exam", "fun"]);
+        assert_eq!(transaction.editing, Editing::Search);
+    }
+
+    #[test]
+    fn manual_entry() {
+        let now =
+            Transaction::parse_to_fixed_utc_plus8("2025-03-01 12:00", "%Y-%m-%d %H:%M").unwrap();
+        let t = parse_manual_entry("8 煎饼 摊", now).unwrap();
+        assert_eq!(
+            (t.amount, t.merchant.as_str(), t.kind, t.time),
+            (
+                Money::from_cents(-800),
+                "煎饼 摊",
+                TransactionKind::Expense,
+                now
+            )
+        );
+        let t = parse_manual_entry("2025-02-28 07:30 +50 现金充值", now).unwrap();
+        assert_eq!(t.amount, Money::from_cents(5000));
+        assert_eq!(t.kind, TransactionKind::TopUp);
+        assert_eq!(t.time.format("%m-%d %H:%M").to_string(), "02-28 07:30");
+        assert!(parse_manual_entry("8", now).is_err());
+        assert!(parse_manual_entry("eight 煎饼", now).is_err());
+    }
+
+    #[test]
+    fn add_hide_and_delete() {
+        let (_, mut transaction) = get_test_objs(None, 50);
+        let total = transaction.total;
+
+        transaction.handle_event_with_status_check(&'a'.into());
+        "2099-01-01 08:00 8 煎饼".chars().for_each(|c| {
+            transaction.handle_event_with_status_check(&c.into());
+        });
+        transaction.handle_event_with_status_check(&KeyCode::Enter.into());
+        assert_eq!(transaction.total, total + 1);
+        // the newest transaction is selected after reloading
+        assert!(transaction.transactions[0].manual);
+        assert!(merchant_cell(&transaction.transactions[0]).starts_with("[手动] 煎饼"));
+
+        // an invalid entry keeps the input open
+        transaction.handle_event_with_status_check(&'a'.into());
+        "煎饼".chars().for_each(|c| {
+            transaction.handle_event_with_status_check(&c.into());
+        });
+        transaction.handle_event_with_status_check(&KeyCode::Enter.into());
+        assert!(transaction.input.is_inputting());
+        assert_eq!(transaction.input.get_text(), "煎饼");
+        transaction.handle_event_with_status_check(&KeyCode::Esc.into());
+
+        transaction.handle_event_with_status_check(&'D'.into());
+        assert_eq!(transaction.total, total);
+        // fetched transactions are only hidden
+        transaction.handle_event_with_status_check(&'D'.into());
+        assert_eq!(transaction.total, total);
+
+        transaction.handle_event_with_status_check(&'x'.into());
+        assert!(transaction.transactions[0].hidden);
+        transaction.handle_event_with_status_check(&'l'.into());
+        assert_eq!(transaction.total, total - 1);
+        transaction.handle_event_with_status_check(&'H'.into());
+        assert_eq!(transaction.total, total);
+        assert!(transaction.transactions[0].hidden);
+        transaction.handle_event_with_status_check(&'x'.into());
+        transaction.handle_event_with_status_check(&'H'.into());
+        assert_eq!(transaction.total, total);
+    }
+
     #[test]
     fn push_filtered_page() {
         let (mut rx, mut transaction) = get_test_objs(None, 50);
@@ -517,8 +964,8 @@ mod test {
             })) = action
             {
                 assert!(filter.is_some());
-                Some(assert_eq!(
-                    filter.unwrap(),
+                assert_eq!(
+                    *filter.unwrap(),
                     FilterOptions::default().merchant(
                         transaction
                             .transactions
@@ -527,7 +974,7 @@ mod test {
                             .merchant
                             .clone()
                     )
-                ));
+                );
                 received_push_page = true;
             }
         }
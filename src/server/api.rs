@@ -1,16 +1,24 @@
+use std::{collections::HashMap, sync::Mutex};
+
 use actix_web::{
     HttpResponse, Responder, Result as ActixResult,
-    error::{ErrorInternalServerError, ErrorNotFound},
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
+    http::StatusCode,
     web,
 };
 use chrono::{DateTime, FixedOffset};
 use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
 
 // Assuming Transaction and FilterOptions are correctly defined and made public in libs::transactions
 // and derive Serialize and Deserialize.
 use crate::libs::{
-    fetcher::{RealMealFetcher, fetch},
-    transactions::{FilterOptions, TransactionManager},
+    fetcher::{FetchError, TransactionSource},
+    money::Money,
+    transactions::{
+        FetchCheckpoint, FilterOptions, GroupBy, SortKey, SortOrder, Transaction, TransactionKind,
+        TransactionManager, mask_cookie, mask_secret,
+    },
 };
 
 // --- Helper for converting Result to ActixResult ---
@@ -42,13 +50,58 @@ fn to_actix_empty_response(result: color_eyre::Result<()>) -> ActixResult<impl R
     }
 }
 
+/// Optional `?profile=` query parameter accepted by most routes
+#[derive(Deserialize, Serialize, Default)]
+struct ProfileQuery {
+    profile: Option<String>,
+}
+
+/// The manager scoped to the requested account profile, or the server's profile if none
+fn scoped_manager(
+    manager: &TransactionManager,
+    profile: &Option<String>,
+) -> ActixResult<TransactionManager> {
+    match profile {
+        Some(profile) => manager
+            .for_profile(profile)
+            .map_err(|e| ErrorBadRequest(e.to_string())),
+        None => Ok(manager.clone()),
+    }
+}
+
 // --- Handlers for TransactionManager methods ---
 
-// GET /transactions
+/// Query parameters of `GET /transactions`, all optional
+#[derive(Deserialize, Serialize, Default)]
+struct TransactionsQuery {
+    profile: Option<String>,
+    sort: Option<SortKey>,
+    order: Option<SortOrder>,
+    limit: Option<u64>,
+    offset: Option<u64>,
+    include_hidden: Option<bool>,
+}
+
+// GET /transactions?sort=amount&order=asc&limit=50&offset=100
 async fn handle_fetch_all_transactions(
     manager: web::Data<TransactionManager>,
+    query: web::Query<TransactionsQuery>,
 ) -> ActixResult<impl Responder> {
-    to_actix_response(manager.fetch_all())
+    let query = query.into_inner();
+    let manager = scoped_manager(&manager, &query.profile)?;
+    let mut filter_opts = FilterOptions {
+        limit: query.limit,
+        offset: query.offset,
+        include_hidden: query.include_hidden.unwrap_or_default(),
+        ..Default::default()
+    };
+    if query.sort.is_some() || query.order.is_some() {
+        filter_opts = filter_opts.sort(
+            query.sort.unwrap_or_default(),
+            query.order.unwrap_or_default(),
+        );
+    }
+    to_actix_response(manager.fetch_filtered(&filter_opts))
 }
 
 // POST /transactions/query (using POST to allow FilterOptions in body)
@@ -59,59 +112,343 @@ async fn handle_fetch_filtered_transactions(
     to_actix_response(manager.fetch_filtered(&filter_opts.into_inner()))
 }
 
+#[derive(Deserialize, Serialize)] // Added Serialize for test usage
+struct AggregateRequest {
+    group_by: GroupBy,
+    /// Transactions to aggregate, all of the profile if omitted
+    #[serde(default)]
+    filter: FilterOptions,
+}
+
+// POST /transactions/aggregate
+async fn handle_aggregate_transactions(
+    manager: web::Data<TransactionManager>,
+    req: web::Json<AggregateRequest>,
+) -> ActixResult<impl Responder> {
+    to_actix_response(manager.aggregate(&req.filter, req.group_by))
+}
+
 // GET /transactions/count
 async fn handle_fetch_transaction_count(
     manager: web::Data<TransactionManager>,
+    query: web::Query<ProfileQuery>,
+) -> ActixResult<impl Responder> {
+    to_actix_response(scoped_manager(&manager, &query.profile)?.fetch_count())
+}
+
+#[derive(Deserialize, Serialize)] // Added Serialize for test usage
+struct NoteUpdateRequest {
+    /// A blank note removes it
+    note: String,
+}
+
+// PUT /transactions/{id}/note
+async fn handle_update_note(
+    manager: web::Data<TransactionManager>,
+    id: web::Path<i64>,
+    query: web::Query<ProfileQuery>,
+    req: web::Json<NoteUpdateRequest>,
+) -> ActixResult<impl Responder> {
+    let id = id.into_inner();
+    annotation_response(
+        scoped_manager(&manager, &query.profile)?.set_note(id, &req.note),
+        id,
+    )
+}
+
+#[derive(Deserialize, Serialize)] // Added Serialize for test usage
+struct TagsUpdateRequest {
+    tags: Vec<String>,
+}
+
+// PUT /transactions/{id}/tags
+async fn handle_update_tags(
+    manager: web::Data<TransactionManager>,
+    id: web::Path<i64>,
+    query: web::Query<ProfileQuery>,
+    req: web::Json<TagsUpdateRequest>,
+) -> ActixResult<impl Responder> {
+    let id = id.into_inner();
+    annotation_response(
+        scoped_manager(&manager, &query.profile)?.set_tags(id, &req.tags),
+        id,
+    )
+}
+
+/// Response of a change to one transaction, which is missing if `result` is `false`
+fn annotation_response(
+    result: color_eyre::eyre::Result<bool>,
+    id: i64,
+) -> ActixResult<HttpResponse> {
+    match result {
+        Ok(true) => Ok(HttpResponse::Ok().finish()),
+        Ok(false) => Err(ErrorNotFound(format!("No transaction with ID {}", id))),
+        Err(e) => {
+            tracing::error!("Handler error: {:?}", e);
+            Err(ErrorInternalServerError(format!(
+                "An internal error occurred: {}",
+                e
+            )))
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)] // Added Serialize for test usage
+struct ManualTransactionRequest {
+    time: DateTime<FixedOffset>,
+    /// Negative for money spent
+    amount: Money,
+    merchant: String,
+    /// Implied by the sign of the amount if omitted
+    kind: Option<TransactionKind>,
+}
+
+// POST /transactions/manual
+async fn handle_add_manual_transaction(
+    manager: web::Data<TransactionManager>,
+    query: web::Query<ProfileQuery>,
+    req: web::Json<ManualTransactionRequest>,
+) -> ActixResult<impl Responder> {
+    let req = req.into_inner();
+    let transaction = Transaction {
+        kind: req
+            .kind
+            .unwrap_or_else(|| TransactionKind::from_amount(req.amount)),
+        ..Transaction::new(req.amount, req.merchant, req.time)
+    };
+    scoped_manager(&manager, &query.profile)?
+        .add_manual(&transaction)
+        .map(web::Json)
+        .map_err(|e| ErrorBadRequest(e.to_string()))
+}
+
+// DELETE /transactions/{id}
+//
+// Only manual transactions can be deleted, fetched ones can be hidden instead.
+async fn handle_delete_manual_transaction(
+    manager: web::Data<TransactionManager>,
+    id: web::Path<i64>,
+    query: web::Query<ProfileQuery>,
+) -> ActixResult<impl Responder> {
+    let id = id.into_inner();
+    match scoped_manager(&manager, &query.profile)?.delete_manual(id) {
+        Ok(true) => Ok(HttpResponse::Ok().finish()),
+        Ok(false) => Err(ErrorNotFound(format!(
+            "No manual transaction with ID {}",
+            id
+        ))),
+        Err(e) => {
+            tracing::error!("Handler error: {:?}", e);
+            Err(ErrorInternalServerError(format!(
+                "An internal error occurred: {}",
+                e
+            )))
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)] // Added Serialize for test usage
+struct HiddenUpdateRequest {
+    hidden: bool,
+}
+
+// PUT /transactions/{id}/hidden
+async fn handle_update_hidden(
+    manager: web::Data<TransactionManager>,
+    id: web::Path<i64>,
+    query: web::Query<ProfileQuery>,
+    req: web::Json<HiddenUpdateRequest>,
 ) -> ActixResult<impl Responder> {
-    to_actix_response(manager.fetch_count())
+    let id = id.into_inner();
+    annotation_response(
+        scoped_manager(&manager, &query.profile)?.set_hidden(id, req.hidden),
+        id,
+    )
+}
+
+// GET /transactions/tags
+async fn handle_list_tags(
+    manager: web::Data<TransactionManager>,
+    query: web::Query<ProfileQuery>,
+) -> ActixResult<impl Responder> {
+    to_actix_response(scoped_manager(&manager, &query.profile)?.list_tags())
 }
 
 #[derive(Deserialize, Serialize)]
 struct FetchTransactionsRequest {
-    start_date: DateTime<FixedOffset>,
+    /// Fetch since this time, or incrementally since the last sync if not given
+    #[serde(default)]
+    start_date: Option<DateTime<FixedOffset>>,
+    /// Resume the interrupted fetch instead, ignoring `start_date`
+    #[serde(default)]
+    resume: bool,
+}
+
+// GET /transactions/sync
+async fn handle_get_sync_state(
+    manager: web::Data<TransactionManager>,
+    query: web::Query<ProfileQuery>,
+) -> ActixResult<impl Responder> {
+    to_actix_response(scoped_manager(&manager, &query.profile)?.sync_state())
+}
+
+/// Fetches running for web clients, at most one per account profile
+///
+/// `DELETE /transactions/fetch` cancels the fetch of a profile. The pages it
+/// stored until then are kept and can be resumed.
+#[derive(Debug, Default)]
+pub struct FetchJobs {
+    running: Mutex<HashMap<String, CancellationToken>>,
+}
+
+impl FetchJobs {
+    /// Register a fetch of the profile, `None` if one is already running
+    fn start(&self, profile: &str) -> Option<FetchJob<'_>> {
+        let mut running = self.running.lock().unwrap();
+        if running.contains_key(profile) {
+            return None;
+        }
+        let cancel = CancellationToken::new();
+        running.insert(profile.to_string(), cancel.clone());
+        Some(FetchJob {
+            jobs: self,
+            profile: profile.to_string(),
+            cancel,
+        })
+    }
+
+    /// Cancel the fetch of the profile, returning whether one was running
+    fn cancel(&self, profile: &str) -> bool {
+        match self.running.lock().unwrap().get(profile) {
+            Some(cancel) => {
+                cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A registered fetch, unregistered when dropped, which includes the client
+/// disconnecting
+struct FetchJob<'a> {
+    jobs: &'a FetchJobs,
+    profile: String,
+    cancel: CancellationToken,
+}
+
+impl Drop for FetchJob<'_> {
+    fn drop(&mut self) {
+        self.jobs.running.lock().unwrap().remove(&self.profile);
+    }
 }
 
 // POST /transactions/fetch
+//
+// Answers the insert report, or a `FetchErrorResponse`: 409 with kind
+// `already_running` or `cancelled`, or the status of the `FetchError`.
 async fn handle_fetch_transactions(
     manager: web::Data<TransactionManager>,
+    source: web::Data<dyn TransactionSource>,
+    jobs: web::Data<FetchJobs>,
+    query: web::Query<ProfileQuery>,
     req: web::Json<FetchTransactionsRequest>,
 ) -> ActixResult<impl Responder> {
-    let (account, cookie) = manager.get_account_cookie().map_err(|e| {
-        tracing::error!("Failed to get account/cookie: {:?}", e);
-        ErrorInternalServerError(format!("Failed to get account/cookie: {}", e))
-    })?;
-    let client = RealMealFetcher::default().account(account).cookie(cookie);
-    let results = tokio::task::spawn_blocking(move || {
-        fetch(
-            req.start_date,
-            crate::libs::fetcher::MealFetcher::Real(client),
-            |_t| Ok(()),
-        )
-    })
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to spawn blocking task: {:?}", e);
-        ErrorInternalServerError(format!("Failed to spawn blocking task: {}", e))
-    })?;
-    tracing::debug!("Fetched transactions: {:?}", results);
-    match results {
-        Ok(r) => {
-            manager.insert(&r).map_err(|e| {
-                tracing::error!("Failed to insert transactions: {:?}", e);
-                ErrorInternalServerError(format!("Failed to insert transactions: {}", e))
-            })?;
-            Ok(HttpResponse::Ok().finish())
+    let manager = scoped_manager(&manager, &query.profile)?;
+    let Some(job) = jobs.start(&manager.profile()) else {
+        return Ok(HttpResponse::Conflict().json(FetchErrorResponse {
+            kind: "already_running".to_string(),
+            message: "A fetch of this profile is already running".to_string(),
+        }));
+    };
+    let mut client = source.into_inner();
+    if client.capabilities().needs_credentials {
+        let (account, cookie) = manager.get_account_cookie().map_err(|e| {
+            tracing::error!("Failed to get account/cookie: {:?}", e);
+            ErrorInternalServerError(format!("Failed to get account/cookie: {}", e))
+        })?;
+        client = client.with_credentials(&account, &cookie);
+    }
+    let sync_error = |e: color_eyre::Report| {
+        tracing::error!("Failed to get sync state: {:?}", e);
+        ErrorInternalServerError(format!("Failed to get sync state: {}", e))
+    };
+    let checkpoint = if req.resume {
+        manager
+            .fetch_checkpoint()
+            .map_err(sync_error)?
+            .ok_or_else(|| ErrorBadRequest("There is no interrupted fetch to resume"))?
+    } else {
+        match req.start_date {
+            Some(date) => FetchCheckpoint::start(date),
+            None => FetchCheckpoint::start(manager.incremental_start().map_err(sync_error)?),
+        }
+    };
+    // the fetch is dropped with the request if the client disconnects, what
+    // was stored until then can be resumed
+    let result = manager
+        .fetch_and_store(checkpoint, client, job.cancel.clone(), |_| Ok(()))
+        .await;
+    match result {
+        Ok(report) => {
+            tracing::info!("Stored fetched transactions: {}", report);
+            Ok(HttpResponse::Ok().json(report))
+        }
+        Err(_) if job.cancel.is_cancelled() => {
+            tracing::info!("Fetch cancelled");
+            Ok(HttpResponse::Conflict().json(FetchErrorResponse {
+                kind: "cancelled".to_string(),
+                message: "The fetch was cancelled, the transactions fetched until then were kept"
+                    .to_string(),
+            }))
         }
         Err(e) => {
             tracing::error!("Failed to fetch transactions: {:?}", e);
-            Err(ErrorInternalServerError(format!(
-                "Failed to fetch transactions: {}",
-                e
-            )))
+            if let Err(e) = manager.record_sync_error(&format!("{:#}", e)) {
+                tracing::error!("Failed to record sync error: {:?}", e);
+            }
+            let fetch_error = FetchError::of(&e);
+            let status = match fetch_error {
+                Some(FetchError::SessionExpired) => StatusCode::UNAUTHORIZED,
+                Some(FetchError::RateLimited) => StatusCode::TOO_MANY_REQUESTS,
+                Some(FetchError::Network(_)) => StatusCode::GATEWAY_TIMEOUT,
+                Some(FetchError::Server(_) | FetchError::MalformedResponse(_)) => {
+                    StatusCode::BAD_GATEWAY
+                }
+                Some(FetchError::PageLimit(_)) => StatusCode::UNPROCESSABLE_ENTITY,
+                None => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Ok(HttpResponse::build(status).json(FetchErrorResponse {
+                kind: fetch_error.map_or("internal", FetchError::kind).to_string(),
+                message: format!("Failed to fetch transactions: {}", e),
+            }))
         }
     }
 }
 
+/// Body of a failed fetch, `kind` is the [`FetchError::kind`] of the cause
+/// so the frontend can tell an expired session from an unreachable server,
+/// or `already_running` / `cancelled`.
+#[derive(Deserialize, Serialize)]
+struct FetchErrorResponse {
+    kind: String,
+    message: String,
+}
+
+// DELETE /transactions/fetch
+async fn handle_cancel_fetch(
+    manager: web::Data<TransactionManager>,
+    jobs: web::Data<FetchJobs>,
+    query: web::Query<ProfileQuery>,
+) -> ActixResult<impl Responder> {
+    let manager = scoped_manager(&manager, &query.profile)?;
+    if jobs.cancel(&manager.profile()) {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ErrorNotFound("No fetch of this profile is running"))
+    }
+}
+
 #[derive(Deserialize, Serialize)] // Added Serialize for test usage
 struct AccountUpdateRequest {
     account: String,
@@ -120,14 +457,10 @@ struct AccountUpdateRequest {
 // PUT /config/account
 async fn handle_update_account(
     manager: web::Data<TransactionManager>,
+    query: web::Query<ProfileQuery>,
     req: web::Json<AccountUpdateRequest>,
 ) -> ActixResult<impl Responder> {
-    to_actix_empty_response(manager.update_account(&req.account))
-}
-
-#[derive(Deserialize, Serialize)] // Added Serialize for test usage
-struct CookieUpdateRequest {
-    cookie: String,
+    to_actix_empty_response(scoped_manager(&manager, &query.profile)?.update_account(&req.account))
 }
 
 #[derive(Deserialize, Serialize)] // Added Serialize for test usage
@@ -138,9 +471,12 @@ struct HallticketUpdateRequest {
 // PUT /config/hallticket
 async fn handle_update_hallticket(
     manager: web::Data<TransactionManager>,
+    query: web::Query<ProfileQuery>,
     req: web::Json<HallticketUpdateRequest>,
 ) -> ActixResult<impl Responder> {
-    to_actix_empty_response(manager.update_hallticket(&req.hallticket))
+    to_actix_empty_response(
+        scoped_manager(&manager, &query.profile)?.update_hallticket(&req.hallticket),
+    )
 }
 
 #[derive(Serialize, Deserialize)] // Added Deserialize for test usage
@@ -150,11 +486,34 @@ struct AccountCookieResponse {
 }
 
 // GET /config/account-cookie
+//
+// Both values are masked; use the reveal route to read them.
 async fn handle_get_account_cookie(
     manager: web::Data<TransactionManager>,
+    query: web::Query<ProfileQuery>,
+) -> ActixResult<impl Responder> {
+    account_cookie_response(&manager, &query.profile, false)
+}
+
+// POST /config/account-cookie/reveal
+async fn handle_reveal_account_cookie(
+    manager: web::Data<TransactionManager>,
+    query: web::Query<ProfileQuery>,
 ) -> ActixResult<impl Responder> {
-    match manager.get_account_cookie() {
-        Ok((account, cookie)) => Ok(web::Json(AccountCookieResponse { account, cookie })),
+    account_cookie_response(&manager, &query.profile, true)
+}
+
+fn account_cookie_response(
+    manager: &TransactionManager,
+    profile: &Option<String>,
+    reveal: bool,
+) -> ActixResult<web::Json<AccountCookieResponse>> {
+    match scoped_manager(manager, profile)?.get_account_cookie() {
+        Ok((account, cookie)) if reveal => Ok(web::Json(AccountCookieResponse { account, cookie })),
+        Ok((account, cookie)) => Ok(web::Json(AccountCookieResponse {
+            account: mask_secret(&account),
+            cookie: mask_cookie(&cookie),
+        })),
         Err(e) => {
             tracing::error!("Failed to get account/cookie: {:?}", e);
             // Check if the error indicates "No account and cookie found" or "Account or cookie is empty"
@@ -175,6 +534,36 @@ async fn handle_get_account_cookie(
     }
 }
 
+#[derive(Serialize, Deserialize)] // Added Deserialize for test usage
+struct ProfilesResponse {
+    /// Profile used when a request has no `?profile=` parameter
+    active: String,
+    profiles: Vec<String>,
+}
+
+// GET /profiles
+async fn handle_list_profiles(
+    manager: web::Data<TransactionManager>,
+) -> ActixResult<impl Responder> {
+    to_actix_response(manager.list_profiles().map(|profiles| ProfilesResponse {
+        active: manager.profile(),
+        profiles,
+    }))
+}
+
+#[derive(Deserialize, Serialize)] // Added Serialize for test usage
+struct ProfileCreateRequest {
+    name: String,
+}
+
+// POST /profiles
+async fn handle_create_profile(
+    manager: web::Data<TransactionManager>,
+    req: web::Json<ProfileCreateRequest>,
+) -> ActixResult<impl Responder> {
+    to_actix_empty_response(manager.create_profile(&req.name))
+}
+
 // --- Actix App Configuration ---
 pub fn config_routes(cfg: &mut web::ServiceConfig) {
     let scope = web::scope("/api")
@@ -182,14 +571,32 @@ pub fn config_routes(cfg: &mut web::ServiceConfig) {
             web::scope("/transactions")
                 .route("", web::get().to(handle_fetch_all_transactions))
                 .route("/query", web::post().to(handle_fetch_filtered_transactions))
+                .route("/aggregate", web::post().to(handle_aggregate_transactions))
                 .route("/count", web::get().to(handle_fetch_transaction_count))
-                .route("/fetch", web::post().to(handle_fetch_transactions)),
+                .route("/fetch", web::post().to(handle_fetch_transactions))
+                .route("/fetch", web::delete().to(handle_cancel_fetch))
+                .route("/tags", web::get().to(handle_list_tags))
+                .route("/sync", web::get().to(handle_get_sync_state))
+                .route("/{id}/note", web::put().to(handle_update_note))
+                .route("/{id}/tags", web::put().to(handle_update_tags))
+                .route("/manual", web::post().to(handle_add_manual_transaction))
+                .route("/{id}", web::delete().to(handle_delete_manual_transaction))
+                .route("/{id}/hidden", web::put().to(handle_update_hidden)),
         )
         .service(
             web::scope("/config")
                 .route("/account", web::put().to(handle_update_account))
                 .route("/hallticket", web::put().to(handle_update_hallticket))
-                .route("/account-cookie", web::get().to(handle_get_account_cookie)),
+                .route("/account-cookie", web::get().to(handle_get_account_cookie))
+                .route(
+                    "/account-cookie/reveal",
+                    web::post().to(handle_reveal_account_cookie),
+                ),
+        )
+        .service(
+            web::scope("/profiles")
+                .route("", web::get().to(handle_list_profiles))
+                .route("", web::post().to(handle_create_profile)),
         );
     cfg.service(scope);
 }
@@ -199,15 +606,29 @@ mod tests {
     use super::*;
     use crate::libs::{
         fetcher,
-        transactions::{FilterOptions, Transaction, TransactionManager},
+        transactions::{
+            AggregateRow, FilterOptions, InsertReport, SyncState, Transaction, TransactionManager,
+        },
     };
-    use actix_web::{App, http::StatusCode, test, web::Data};
+    use actix_web::{App, test, web::Data};
+    use std::sync::Arc;
 
     // Helper to initialize TransactionManager for tests (in-memory DB)
     async fn setup_test_app() -> impl actix_web::dev::Service<
         actix_http::Request,
         Response = actix_web::dev::ServiceResponse,
         Error = actix_web::Error,
+    > {
+        setup_test_app_with(fetcher::MockMealFetcher::default()).await
+    }
+
+    /// Like [`setup_test_app`], fetching from the given mock data
+    async fn setup_test_app_with(
+        source: impl TransactionSource + 'static,
+    ) -> impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = actix_web::dev::ServiceResponse,
+        Error = actix_web::Error,
     > {
         let manager =
             TransactionManager::new(None).expect("Failed to create test TransactionManager");
@@ -215,9 +636,12 @@ mod tests {
         manager
             .insert(&mock_data)
             .expect("Failed to insert mock data");
+        let source: Arc<dyn TransactionSource> = Arc::new(source);
         test::init_service(
             App::new()
                 .app_data(Data::new(manager)) // Use app_data for shared state
+                .app_data(Data::from(source))
+                .app_data(Data::new(FetchJobs::default()))
                 .configure(config_routes),
         )
         .await
@@ -235,7 +659,33 @@ mod tests {
         assert_eq!(resp_fetch.status(), StatusCode::OK);
 
         let fetched_transactions: Vec<Transaction> = test::read_body_json(resp_fetch).await;
-        assert_eq!(fetched_transactions.len(), 46);
+        assert_eq!(fetched_transactions.len(), 49);
+    }
+
+    #[actix_web::test]
+    async fn test_fetch_transactions_page() {
+        let app = setup_test_app().await;
+
+        let req = test::TestRequest::get()
+            .uri("/api/transactions?sort=amount&order=asc&limit=10&offset=5")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let page: Vec<Transaction> = test::read_body_json(resp).await;
+        assert_eq!(page.len(), 10);
+        assert!(page.windows(2).all(|w| w[0].amount <= w[1].amount));
+
+        let req = test::TestRequest::get()
+            .uri("/api/transactions?sort=amount&order=asc")
+            .to_request();
+        let all: Vec<Transaction> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(page[0].id, all[5].id);
+
+        let req = test::TestRequest::get()
+            .uri("/api/transactions?sort=nope")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     }
 
     #[actix_web::test]
@@ -254,6 +704,43 @@ mod tests {
         assert_eq!(resp.status(), StatusCode::OK);
         let result: Vec<Transaction> = test::read_body_json(resp).await;
         assert_eq!(result.len(), 8);
+
+        let req = test::TestRequest::post()
+            .uri("/api/transactions/query")
+            .set_json(serde_json::json!({ "merchant": "浴室", "merchant_match": "substring" }))
+            .to_request();
+        let result: Vec<Transaction> = test::call_and_read_body_json(&app, req).await;
+        assert!(result.len() >= 8);
+        assert!(result.iter().all(|t| t.merchant.contains("浴室")));
+    }
+
+    #[actix_web::test]
+    async fn test_aggregate_transactions() {
+        let app = setup_test_app().await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/transactions/aggregate")
+            .set_json(AggregateRequest {
+                group_by: GroupBy::Merchant,
+                filter: FilterOptions::default().expenses_only(),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let rows: Vec<AggregateRow> = test::read_body_json(resp).await;
+        let bathhouse = rows
+            .iter()
+            .find(|r| r.key == "西14西15东12浴室")
+            .expect("Bathhouse should be aggregated");
+        assert_eq!(bathhouse.count, 8);
+
+        // the filter defaults to everything
+        let req = test::TestRequest::post()
+            .uri("/api/transactions/aggregate")
+            .set_json(serde_json::json!({ "group_by": "month" }))
+            .to_request();
+        let rows: Vec<AggregateRow> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(rows.iter().map(|r| r.count).sum::<u64>(), 49);
     }
 
     #[actix_web::test]
@@ -266,7 +753,150 @@ mod tests {
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::OK);
         let count: u64 = test::read_body_json(resp).await;
-        assert_eq!(count, 46);
+        assert_eq!(count, 49);
+    }
+
+    #[actix_web::test]
+    async fn test_sync_state() {
+        let app = setup_test_app().await;
+
+        let req = test::TestRequest::get()
+            .uri("/api/transactions/sync")
+            .to_request();
+        let state: SyncState = test::call_and_read_body_json(&app, req).await;
+        assert!(state.newest_transaction.is_some());
+        // the mock data was not stored by a fetch
+        assert_eq!(state.last_success, None);
+
+        let req = test::TestRequest::get()
+            .uri("/api/transactions/sync?profile=other")
+            .to_request();
+        let state: SyncState = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(state, SyncState::default());
+    }
+
+    #[actix_web::test]
+    async fn test_fetch_and_resume() {
+        let app = setup_test_app().await;
+
+        // nothing to resume
+        let req = test::TestRequest::post()
+            .uri("/api/transactions/fetch")
+            .set_json(serde_json::json!({ "resume": true }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::post()
+            .uri("/api/transactions/fetch?profile=other")
+            .set_json(serde_json::json!({ "start_date": "2025-03-01T00:00:00+08:00" }))
+            .to_request();
+        let report: InsertReport = test::call_and_read_body_json(&app, req).await;
+        assert!(report.new > 0);
+
+        let req = test::TestRequest::get()
+            .uri("/api/transactions/sync?profile=other")
+            .to_request();
+        let state: SyncState = test::call_and_read_body_json(&app, req).await;
+        assert!(state.last_success.is_some());
+        assert_eq!(state.checkpoint, None);
+    }
+
+    #[actix_web::test]
+    async fn test_cancel_fetch() {
+        let source = fetcher::MockMealFetcher::default()
+            .per_page(5)
+            .set_sim_delay(std::time::Duration::from_millis(100));
+        let app = setup_test_app_with(source).await;
+        let cancel = || {
+            test::TestRequest::delete()
+                .uri("/api/transactions/fetch?profile=other")
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, cancel()).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let fetch = test::TestRequest::post()
+            .uri("/api/transactions/fetch?profile=other")
+            .set_json(serde_json::json!({ "start_date": "2022-01-01T00:00:00+08:00" }))
+            .to_request();
+        let (fetched, cancelled) = futures::join!(test::call_service(&app, fetch), async {
+            tokio::time::sleep(std::time::Duration::from_millis(350)).await;
+            // only one fetch per profile at a time
+            let again = test::TestRequest::post()
+                .uri("/api/transactions/fetch?profile=other")
+                .set_json(serde_json::json!({}))
+                .to_request();
+            let resp = test::call_service(&app, again).await;
+            assert_eq!(resp.status(), StatusCode::CONFLICT);
+            let body: FetchErrorResponse = test::read_body_json(resp).await;
+            assert_eq!(body.kind, "already_running");
+            test::call_service(&app, cancel()).await
+        });
+        assert_eq!(cancelled.status(), StatusCode::NO_CONTENT);
+        assert_eq!(fetched.status(), StatusCode::CONFLICT);
+        let body: FetchErrorResponse = test::read_body_json(fetched).await;
+        assert_eq!(body.kind, "cancelled");
+
+        // the pages fetched before are kept, and the fetch can be resumed
+        let req = test::TestRequest::get()
+            .uri("/api/transactions/sync?profile=other")
+            .to_request();
+        let state: SyncState = test::call_and_read_body_json(&app, req).await;
+        assert!(state.checkpoint.is_some());
+        assert!(state.newest_transaction.is_some());
+        assert_eq!(state.last_error, None);
+
+        let resp = test::call_service(&app, cancel()).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_fetch_session_expired() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", fetcher::API_PATH)
+            .with_status(403)
+            .create_async()
+            .await;
+        let source = fetcher::RealMealFetcher::default()
+            .origin(server.url())
+            .limits(fetcher::FetchLimits {
+                initial_backoff: std::time::Duration::ZERO,
+                ..Default::default()
+            });
+        let app = setup_test_app_with(source).await;
+        for req in [
+            test::TestRequest::put()
+                .uri("/api/config/account")
+                .set_json(AccountUpdateRequest {
+                    account: "123456".to_string(),
+                }),
+            test::TestRequest::put()
+                .uri("/api/config/hallticket")
+                .set_json(HallticketUpdateRequest {
+                    hallticket: "expired".to_string(),
+                }),
+        ] {
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        let req = test::TestRequest::post()
+            .uri("/api/transactions/fetch")
+            .set_json(serde_json::json!({}))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let body: FetchErrorResponse = test::read_body_json(resp).await;
+        assert_eq!(body.kind, "session_expired");
+
+        let req = test::TestRequest::get()
+            .uri("/api/transactions/sync")
+            .to_request();
+        let state: SyncState = test::call_and_read_body_json(&app, req).await;
+        assert!(state.last_error.is_some());
     }
 
     #[actix_web::test]
@@ -302,8 +932,138 @@ mod tests {
         let resp_get_ac2 = test::call_service(&app, req_get_ac2).await;
         assert_eq!(resp_get_ac2.status(), StatusCode::OK);
         let ac_response2: AccountCookieResponse = test::read_body_json(resp_get_ac2).await;
-        assert_eq!(ac_response2.account, "test_user"); // Account should persist
-        assert_eq!(ac_response2.cookie, "hallticket=test_hallticket_val");
+        // Account should persist, both are masked
+        assert_eq!(ac_response2.account, "*****user");
+        assert_eq!(ac_response2.cookie, "hallticket=***************_val");
+
+        // Reveal them explicitly
+        let req_reveal = test::TestRequest::post()
+            .uri("/api/config/account-cookie/reveal")
+            .to_request();
+        let revealed: AccountCookieResponse = test::call_and_read_body_json(&app, req_reveal).await;
+        assert_eq!(revealed.account, "test_user");
+        assert_eq!(revealed.cookie, "hallticket=test_hallticket_val");
+    }
+
+    #[actix_web::test]
+    async fn test_annotations() {
+        let app = setup_test_app().await;
+        let req = test::TestRequest::get()
+            .uri("/api/transactions")
+            .to_request();
+        let transactions: Vec<Transaction> = test::call_and_read_body_json(&app, req).await;
+        let id = transactions[0].id;
+
+        let req = test::TestRequest::put()
+            .uri(&format!("/api/transactions/{}/note", id))
+            .set_json(NoteUpdateRequest {
+                note: "with roommate".to_string(),
+            })
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let req = test::TestRequest::put()
+            .uri(&format!("/api/transactions/{}/tags", id))
+            .set_json(TagsUpdateRequest {
+                tags: vec!["exam week".to_string()],
+            })
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = test::TestRequest::post()
+            .uri("/api/transactions/query")
+            .set_json(FilterOptions::default().tag("exam week"))
+            .to_request();
+        let tagged: Vec<Transaction> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(tagged.len(), 1);
+        assert_eq!(tagged[0].note.as_deref(), Some("with roommate"));
+
+        let req = test::TestRequest::get()
+            .uri("/api/transactions/tags")
+            .to_request();
+        let tags: Vec<String> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(tags, vec!["exam week"]);
+
+        let req = test::TestRequest::put()
+            .uri("/api/transactions/-1/note")
+            .set_json(NoteUpdateRequest {
+                note: "nope".to_string(),
+            })
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_manual_and_hidden() {
+        let app = setup_test_app().await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/transactions/manual")
+            .set_json(serde_json::json!({
+                "time": "2025-03-01T08:00:00+08:00",
+                "amount": -8.0,
+                "merchant": "煎饼摊",
+            }))
+            .to_request();
+        let manual: Transaction = test::call_and_read_body_json(&app, req).await;
+        assert!(manual.manual && manual.id < 0);
+        assert_eq!(manual.kind, TransactionKind::Expense);
+
+        let req = test::TestRequest::get()
+            .uri("/api/transactions/count")
+            .to_request();
+        let count: u64 = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(count, 50);
+
+        // hiding a fetched transaction leaves it out unless asked for
+        let req = test::TestRequest::get()
+            .uri("/api/transactions")
+            .to_request();
+        let transactions: Vec<Transaction> = test::call_and_read_body_json(&app, req).await;
+        let fetched = transactions.iter().find(|t| !t.manual).unwrap().id;
+        let req = test::TestRequest::put()
+            .uri(&format!("/api/transactions/{}/hidden", fetched))
+            .set_json(HiddenUpdateRequest { hidden: true })
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let req = test::TestRequest::get()
+            .uri("/api/transactions/count")
+            .to_request();
+        let count: u64 = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(count, 49);
+        let req = test::TestRequest::get()
+            .uri("/api/transactions?include_hidden=true")
+            .to_request();
+        let all: Vec<Transaction> = test::call_and_read_body_json(&app, req).await;
+        assert!(all.iter().any(|t| t.id == fetched && t.hidden));
+
+        // only manual transactions can be deleted
+        let req = test::TestRequest::delete()
+            .uri(&format!("/api/transactions/{}", fetched))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        let req = test::TestRequest::delete()
+            .uri(&format!("/api/transactions/{}", manual.id))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = test::TestRequest::post()
+            .uri("/api/transactions/manual")
+            .set_json(serde_json::json!({
+                "time": "2025-03-01T08:00:00+08:00",
+                "amount": -8.0,
+                "merchant": " ",
+            }))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::BAD_REQUEST
+        );
     }
 
     #[actix_web::test]
@@ -326,4 +1086,70 @@ mod tests {
         // Expecting Not Found because get_account_cookie bails if empty
         assert_eq!(resp_get_ac.status(), StatusCode::NOT_FOUND);
     }
+
+    #[actix_web::test]
+    async fn test_profiles() {
+        let app = setup_test_app().await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/profiles")
+            .set_json(ProfileCreateRequest {
+                name: "roommate".to_string(),
+            })
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get().uri("/api/profiles").to_request();
+        let profiles: ProfilesResponse = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(profiles.active, "default");
+        assert_eq!(profiles.profiles, vec!["default", "roommate"]);
+
+        // credentials and transactions are kept per profile
+        let req = test::TestRequest::put()
+            .uri("/api/config/account?profile=roommate")
+            .set_json(AccountUpdateRequest {
+                account: "654321".to_string(),
+            })
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let req = test::TestRequest::get()
+            .uri("/api/config/account-cookie")
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/api/transactions/count?profile=roommate")
+            .to_request();
+        let count: u64 = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(count, 0);
+        let req = test::TestRequest::get()
+            .uri("/api/transactions?profile=%20roommate%20")
+            .to_request();
+        let result: Vec<Transaction> = test::call_and_read_body_json(&app, req).await;
+        assert!(result.is_empty());
+        // profile names are checked like in every other route
+        for uri in [
+            "/api/transactions?profile=",
+            "/api/transactions/count?profile=",
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            assert_eq!(
+                test::call_service(&app, req).await.status(),
+                StatusCode::BAD_REQUEST
+            );
+        }
+
+        let req = test::TestRequest::post()
+            .uri("/api/transactions/query")
+            .set_json(FilterOptions {
+                profile: Some("roommate".into()),
+                ..Default::default()
+            })
+            .to_request();
+        let result: Vec<Transaction> = test::call_and_read_body_json(&app, req).await;
+        assert!(result.is_empty());
+    }
 }
@@ -1,39 +1,56 @@
 use lazy_static::lazy_static;
-use std::collections::HashMap;
-use strum::Display;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, str::FromStr};
+use strum::{Display, EnumString};
 
-#[derive(Display, Debug, Clone, PartialEq, Eq, Hash)]
-pub(crate) enum MerchantType {
-    #[strum(to_string = "食堂食物")]
+/// Merchant category, used by the analysis pages and as a transaction filter
+///
+/// Parses from both the Chinese name and the snake_case name used in JSON.
+#[derive(Display, EnumString, Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MerchantType {
+    #[strum(to_string = "食堂食物", serialize = "canteen_food")]
     CanteenFood,
-    #[strum(to_string = "食堂饮品")]
+    #[strum(to_string = "食堂饮品", serialize = "canteen_drink")]
     CanteenDrink,
-    #[strum(to_string = "超市")]
+    #[strum(to_string = "超市", serialize = "supermarket")]
     Supermarket,
-    #[strum(to_string = "浴室")]
+    #[strum(to_string = "浴室", serialize = "bathhouse")]
     Bathhouse,
-    #[strum(to_string = "其他")]
+    #[strum(to_string = "其他", serialize = "other")]
     Other,
+    /// Merchants missing from the classification data
+    #[strum(serialize = "unknown")]
     Unknown,
 }
 
 impl MerchantType {
-    /// Converts a type string (like "食堂食物") to a MerchantType variant.
-    fn from_type_str(s: &str) -> Self {
-        match s {
-            "食堂食物" => Self::CanteenFood,
-            "食堂饮品" => Self::CanteenDrink,
-            "超市" => Self::Supermarket,
-            "浴室" => Self::Bathhouse,
-            "其他" => Self::Other,
-            _ => Self::Unknown, // Or handle error appropriately
-        }
-    }
-
     /// Gets the MerchantType based on the merchant name string using the global data.
-    pub fn from_str(merchant_name: &str) -> Self {
+    pub fn of_merchant(merchant_name: &str) -> Self {
         MERCHANT_DATA.get_type(merchant_name)
     }
+
+    /// Names of the merchants classified as this type, sorted
+    ///
+    /// Empty for [`MerchantType::Unknown`], which is everything not listed
+    /// in [`MerchantType::classified_merchants`].
+    pub fn merchant_names(&self) -> Vec<&'static str> {
+        let mut names: Vec<_> = MERCHANT_DATA
+            .data
+            .iter()
+            .filter(|(_, t)| *t == self)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Names of all merchants in the classification data, sorted
+    pub fn classified_merchants() -> Vec<&'static str> {
+        let mut names: Vec<_> = MERCHANT_DATA.data.keys().map(|n| n.as_str()).collect();
+        names.sort_unstable();
+        names
+    }
 }
 
 #[derive(Debug)]
@@ -54,7 +71,7 @@ impl MerchantTypeData {
             serde_yaml::from_str(config_str).expect("Failed to parse merchant classification YAML");
 
         for (type_str, merchants) in config {
-            let merchant_type = MerchantType::from_type_str(&type_str);
+            let merchant_type = MerchantType::from_str(&type_str).unwrap_or(MerchantType::Unknown);
             // Only proceed if the type is known (avoid inserting Unknown type directly from key)
             if merchant_type != MerchantType::Unknown {
                 for merchant in merchants {
@@ -86,39 +103,55 @@ mod tests {
 
     #[test]
     fn test_merchant_type_lookup() {
-        assert_eq!(MerchantType::from_str("炸吧"), MerchantType::CanteenFood);
+        assert_eq!(MerchantType::of_merchant("炸吧"), MerchantType::CanteenFood);
         assert_eq!(
-            MerchantType::from_str("时光水吧"),
+            MerchantType::of_merchant("时光水吧"),
             MerchantType::CanteenDrink
         );
         assert_eq!(
-            MerchantType::from_str("鲜享优果水果店"),
+            MerchantType::of_merchant("鲜享优果水果店"),
             MerchantType::Supermarket
         );
         assert_eq!(
-            MerchantType::from_str("东区浴室-和风"),
+            MerchantType::of_merchant("东区浴室-和风"),
             MerchantType::Bathhouse
         );
-        assert_eq!(MerchantType::from_str("自助补卡机"), MerchantType::Other);
+        assert_eq!(MerchantType::of_merchant("自助补卡机"), MerchantType::Other);
         assert_eq!(
-            MerchantType::from_str("NonExistentMerchant"),
+            MerchantType::of_merchant("NonExistentMerchant"),
             MerchantType::Unknown
         );
     }
 
+    #[test]
+    fn test_parse_and_list() {
+        assert_eq!(
+            MerchantType::from_str("canteen_drink").unwrap(),
+            MerchantType::CanteenDrink
+        );
+        assert_eq!(
+            MerchantType::from_str("浴室").unwrap(),
+            MerchantType::Bathhouse
+        );
+        assert!(
+            MerchantType::CanteenDrink
+                .merchant_names()
+                .contains(&"时光水吧")
+        );
+        assert!(MerchantType::Unknown.merchant_names().is_empty());
+    }
+
     #[test]
     fn test_type_str_conversion() {
         assert_eq!(
-            MerchantType::from_type_str("食堂食物"),
+            MerchantType::from_str("食堂食物").unwrap(),
             MerchantType::CanteenFood
         );
+        // a category name is parsed, not classified as a merchant
         assert_eq!(
-            MerchantType::from_type_str("超市"),
+            MerchantType::from_str("超市").unwrap(),
             MerchantType::Supermarket
         );
-        assert_eq!(
-            MerchantType::from_type_str("InvalidType"),
-            MerchantType::Unknown
-        );
+        assert!(MerchantType::from_str("InvalidType").is_err());
     }
 }